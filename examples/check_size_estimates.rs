@@ -0,0 +1,64 @@
+use ff::PrimeField;
+use nova_snark_example::{
+    estimate::{estimate_compressed_snark_size, estimate_proof_size, SNARK_SIZE_TOLERANCE},
+    prelude::{
+        HashStrength, HasherId, MerkleProcessCircuit, MerkleTree, ParamsBundle, ProofSystem,
+        Prover, SpartanIpa, TreeConfig, F1,
+    },
+    wire::encode_wire,
+};
+use std::process;
+
+fn main() {
+    println!("Checking size estimates against generated proofs");
+    println!("=========================================================");
+
+    let mut failed = false;
+    for height in [4, 16] {
+        let mut tree: MerkleTree<F1, F1> = MerkleTree::new(height);
+        for index in 0..3 {
+            tree.update(index * 5, F1::from(index as u64 + 1));
+        }
+
+        let path = encode_wire(
+            &tree.prove_path(5),
+            HasherId::Poseidon,
+            HashStrength::Standard,
+        )
+        .unwrap();
+        let estimate = estimate_proof_size(height, 2);
+        println!(
+            "height {height}: path of {} bytes, estimated {estimate}",
+            path.len()
+        );
+        if path.len() as u64 != estimate {
+            println!("height {height}: the path estimate is wrong");
+            failed = true;
+        }
+
+        let bundle = ParamsBundle::setup(TreeConfig::new(height));
+        let initial_root = tree.get_root();
+        let circuits: Vec<_> = (0..2)
+            .map(|index| MerkleProcessCircuit::from_update(&mut tree, index, F1::from(7)))
+            .collect();
+        let prover = Prover::new(&bundle);
+        let recursive_snark = prover.prove(&circuits, initial_root).unwrap();
+        let (pk, _) = SpartanIpa::setup(bundle.public_params());
+        let proof = prover
+            .prove_compressed::<SpartanIpa>(&pk, &recursive_snark)
+            .unwrap();
+        let size = bincode::serialized_size(&proof).unwrap();
+        let estimate = estimate_compressed_snark_size(bundle.public_params());
+        println!("height {height}: compressed SNARK of {size} bytes, estimated {estimate}");
+        let element_size = <F1 as PrimeField>::Repr::default().as_ref().len() as u64;
+        if size.abs_diff(estimate) > SNARK_SIZE_TOLERANCE * element_size {
+            println!("height {height}: the compressed SNARK estimate is off by more than {SNARK_SIZE_TOLERANCE} field elements");
+            failed = true;
+        }
+    }
+
+    println!("=========================================================");
+    if failed {
+        process::exit(1);
+    }
+}
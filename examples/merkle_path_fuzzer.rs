@@ -0,0 +1,93 @@
+use bellperson::{util_cs::test_cs::TestConstraintSystem, ConstraintSystem};
+use neptune::poseidon::PoseidonConstants;
+use nova_snark_example::prelude::{
+    compute_merkle_root, verify_merkle_proof, MerkleInclusionCircuit, MerkleTree, F1,
+};
+use std::env;
+
+/// SplitMix64, so that a failing iteration can be replayed from its seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_field(&mut self) -> F1 {
+        // hi * 2^64 + lo
+        let shift = F1::from(u64::MAX) + F1::one();
+        F1::from(self.next_u64()) * shift + F1::from(self.next_u64())
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let iterations: usize = args.next().map_or(1000, |arg| arg.parse().unwrap());
+    let seed: u64 = args.next().map_or(0, |arg| arg.parse().unwrap());
+
+    println!("Fuzzing Merkle paths: {iterations} iterations from seed {seed}");
+    println!("=========================================================");
+
+    let constants = PoseidonConstants::new();
+    let mut rng = SplitMix64(seed);
+    for iteration in 0..iterations {
+        let height = 1 + (rng.next_u64() % 16) as usize;
+        let index = (rng.next_u64() as usize) & ((1 << height) - 1);
+        let value = rng.next_field();
+        let siblings: Vec<F1> = (0..height).map(|_| rng.next_field()).collect();
+
+        // Native path logic
+        let root = compute_merkle_root(&constants, index, value, &siblings);
+        assert!(
+            verify_merkle_proof(&constants, root, height, index, value, &siblings),
+            "iteration {iteration}: native proof rejected"
+        );
+        assert!(
+            !verify_merkle_proof(&constants, root, height, index ^ 1, value, &siblings)
+                || siblings[0] == value,
+            "iteration {iteration}: native proof accepted at the wrong index"
+        );
+
+        // Gadget logic
+        let circuit = MerkleInclusionCircuit {
+            constants: constants.clone(),
+            siblings: siblings.clone(),
+            index,
+            value,
+        };
+        let mut cs = TestConstraintSystem::<F1>::new();
+        let output = circuit
+            .synthesize(&mut cs.namespace(|| "inclusion"))
+            .unwrap();
+        assert!(
+            cs.is_satisfied(),
+            "iteration {iteration}: unsatisfied constraint {:?}",
+            cs.which_is_unsatisfied()
+        );
+        assert_eq!(
+            output[0].get_value(),
+            Some(root),
+            "iteration {iteration}: circuit and native roots differ"
+        );
+        assert_eq!(circuit.output()[0], root);
+
+        // Tree logic
+        if iteration % 16 == 0 {
+            let mut tree: MerkleTree<F1, F1> = MerkleTree::new(height);
+            tree.update(index, value);
+            let siblings = tree.prove(index);
+            assert_eq!(
+                compute_merkle_root(&constants, index, value, &siblings),
+                tree.get_root(),
+                "iteration {iteration}: tree proof does not match its root"
+            );
+        }
+    }
+
+    println!("All {iterations} iterations agree");
+    println!("=========================================================");
+}
@@ -4,9 +4,9 @@ use flate2::{write::ZlibEncoder, Compression};
 use neptune::poseidon::PoseidonConstants;
 use nova_snark::{
     traits::{circuit::TrivialTestCircuit, Group},
-    CompressedSNARK, PublicParams, RecursiveSNARK,
+    PublicParams, RecursiveSNARK,
 };
-use nova_snark_example::merkle_tree::{circuits::MerkleProcessCircuit, tree::MerkleTree};
+use nova_snark_example::prelude::{MerkleProcessCircuit, MerkleTree, ProofSystem, SpartanIpa};
 use std::time::Instant;
 
 fn main() {
@@ -131,12 +131,9 @@ fn main() {
         // produce a compressed SNARK
         println!("Generating a CompressedSNARK using Spartan with IPA-PC...");
         let start = Instant::now();
-        type EE1 = nova_snark::provider::ipa_pc::EvaluationEngine<G1>;
-        type EE2 = nova_snark::provider::ipa_pc::EvaluationEngine<G2>;
-        type S1 = nova_snark::spartan::RelaxedR1CSSNARK<G1, EE1>;
-        type S2 = nova_snark::spartan::RelaxedR1CSSNARK<G2, EE2>;
+        let (pk, vk) = SpartanIpa::setup(&pp);
 
-        let res = CompressedSNARK::<_, _, _, _, S1, S2>::prove(&pp, &recursive_snark).unwrap();
+        let res = SpartanIpa::prove_compressed(&pp, &pk, &recursive_snark).unwrap();
         println!("CompressedSNARK::prove: took {:?}", start.elapsed());
         let compressed_snark = res;
 
@@ -151,12 +148,12 @@ fn main() {
         // verify the compressed SNARK
         println!("Verifying a CompressedSNARK...");
         let start = Instant::now();
-        let (zn_primary, _) = compressed_snark
-            .verify(&pp, num_steps, z0_primary, z0_secondary)
-            .unwrap();
+        let zn_primary =
+            SpartanIpa::verify_compressed(&pp, &vk, &compressed_snark, num_steps, z0_primary[0])
+                .unwrap();
         println!("CompressedSNARK::verify took {:?}", start.elapsed());
         assert_eq!(
-            zn_primary[0], latest_root,
+            zn_primary, latest_root,
             "invalid public inputs of the last proof"
         );
         println!("=========================================================");
@@ -0,0 +1,115 @@
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+use nova_snark_example::prelude::{MerklePath, MerkleTree, TreePath, F1};
+use std::{
+    env,
+    io::{self, BufRead, Write},
+};
+
+const HELP: &str = "\
+commands:
+  update <index> <value>  set the leaf at <index> to <value>
+  remove <index>          clear the leaf at <index>
+  leaf <index>            print the leaf at <index>
+  root                    print the root
+  prove <index>           print the path and siblings of the leaf at <index>
+  verify <index> <value>  check that the leaf at <index> is <value> against the root
+  help                    print this message
+  quit                    exit";
+
+fn parse<T: std::str::FromStr>(arg: Option<&str>) -> Result<T, String> {
+    let arg = arg.ok_or("missing argument")?;
+    arg.parse().map_err(|_| format!("invalid argument `{arg}`"))
+}
+
+fn check_index(tree: &MerkleTree<F1, F1>, index: usize) -> Result<usize, String> {
+    if tree.height() < usize::BITS as usize && index >> tree.height() != 0 {
+        return Err(format!(
+            "index {index} does not fit in {} levels",
+            tree.height()
+        ));
+    }
+    Ok(index)
+}
+
+fn run(
+    tree: &mut MerkleTree<F1, F1>,
+    constants: &PoseidonConstants<F1, U2>,
+    line: &str,
+) -> Result<bool, String> {
+    let mut args = line.split_whitespace();
+    let Some(command) = args.next() else {
+        return Ok(true);
+    };
+    match command {
+        "update" => {
+            let index = check_index(tree, parse(args.next())?)?;
+            let value: u64 = parse(args.next())?;
+            tree.update(index, F1::from(value));
+            println!("root {:?}", tree.get_root());
+        }
+        "remove" => {
+            let index = check_index(tree, parse(args.next())?)?;
+            tree.remove(index);
+            println!("root {:?}", tree.get_root());
+        }
+        "leaf" => {
+            let index = check_index(tree, parse(args.next())?)?;
+            println!("{:?}", tree.get_leaf(index));
+        }
+        "root" => println!("{:?}", tree.get_root()),
+        "prove" => {
+            let index = check_index(tree, parse(args.next())?)?;
+            let path = tree.prove_path(index);
+            let bits: String = TreePath::from_index(index, tree.height())
+                .bits()
+                .iter()
+                .map(|&bit| if bit { '1' } else { '0' })
+                .collect();
+            println!("path from the root (big-endian): {bits}");
+            for (level, (sibling, right)) in path.siblings.iter().zip(path.lr_bits()).enumerate() {
+                let side = if right { "right" } else { "left" };
+                println!("level {level}: node is the {side} child, sibling {sibling:?}");
+            }
+        }
+        "verify" => {
+            let index = check_index(tree, parse(args.next())?)?;
+            let value: u64 = parse(args.next())?;
+            let path = MerklePath {
+                leaf_hash: F1::from(value),
+                ..tree.prove_path(index)
+            };
+            if path.verify(constants, tree.get_root(), tree.height()) {
+                println!("valid");
+            } else {
+                println!("invalid");
+            }
+        }
+        "help" => println!("{HELP}"),
+        "quit" | "exit" => return Ok(false),
+        _ => return Err(format!("unknown command `{command}`, try `help`")),
+    }
+    Ok(true)
+}
+
+fn main() {
+    let height: usize = env::args().nth(1).map_or(4, |arg| arg.parse().unwrap());
+    let mut tree: MerkleTree<F1, F1> = MerkleTree::new(height);
+    let constants = PoseidonConstants::new();
+
+    println!("Merkle tree of {height} levels. Type `help` for commands.");
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match run(&mut tree, &constants, &line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(error) => println!("error: {error}"),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use nova_snark_example::prelude::{
+    MerkleProcessCircuit, MerkleTree, ParamsBundle, Prover, TreeConfig, Verifier, F1,
+};
+use std::time::Instant;
+
+/// Builds the steps of a block writing `num_ops` leaves, padded with no-op steps
+/// to the next power of two so verifiers only ever see a handful of step counts.
+fn build_block(
+    tree: &mut MerkleTree<F1, F1>,
+    first_index: usize,
+    num_ops: usize,
+) -> Vec<MerkleProcessCircuit<F1>> {
+    let mut circuits = vec![];
+    for i in 0..num_ops {
+        let index = first_index + i;
+        circuits.push(MerkleProcessCircuit::from_update(
+            tree,
+            index,
+            F1::from(index as u64 + 1),
+        ));
+    }
+    while circuits.len() < num_ops.next_power_of_two() {
+        circuits.push(MerkleProcessCircuit::no_op(tree, 0));
+    }
+
+    circuits
+}
+
+fn main() {
+    println!("Nova-based Merkle process proof with variable block sizes");
+    println!("=========================================================");
+
+    let num_levels = 11;
+
+    let start = Instant::now();
+    println!("Producing public parameters...");
+    let bundle = ParamsBundle::setup(TreeConfig::new(num_levels));
+    println!("ParamsBundle::setup, took {:?} ", start.elapsed());
+
+    let prover = Prover::new(&bundle);
+    let verifier = Verifier::new(&bundle);
+
+    let mut tree: MerkleTree<F1, F1> = MerkleTree::random(num_levels, 0.25, 0);
+    prover.check_tree(&tree).unwrap();
+    let mut first_index = 0;
+    for num_ops in [7, 1000] {
+        let initial_root = tree.get_root();
+        let circuits = build_block(&mut tree, first_index, num_ops);
+        first_index += num_ops;
+        println!(
+            "Block of {num_ops} operations padded to {} steps",
+            circuits.len()
+        );
+
+        let start = Instant::now();
+        let recursive_snark = prover.prove(&circuits, initial_root).unwrap();
+        println!("Prover::prove, took {:?} ", start.elapsed());
+
+        let start = Instant::now();
+        let final_root = verifier
+            .verify(&recursive_snark, circuits.len(), initial_root)
+            .unwrap();
+        println!("Verifier::verify, took {:?} ", start.elapsed());
+        assert_eq!(final_root, tree.get_root(), "invalid final root");
+    }
+    println!("=========================================================");
+}
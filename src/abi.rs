@@ -0,0 +1,150 @@
+use ethabi::{ParamType, Token, Uint};
+use ff::PrimeField;
+
+use crate::{
+    envelope::ProofEnvelope,
+    prover::{PrimaryCircuit, F1},
+};
+
+/// Solidity function of the L1 contract accepting blocks:
+///
+/// ```solidity
+/// struct Operation { uint256 index; uint256 oldLeaf; uint256 newLeaf; }
+/// function submitBlock(uint256 oldRoot, uint256 newRoot, Operation[] calldata operations, bytes calldata proof);
+/// ```
+pub const SUBMIT_BLOCK: &str = "submitBlock";
+
+/// One leaf update of a block, as passed to the L1 contract.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockOperation {
+    pub index: usize,
+    /// Hash of the leaf before the update.
+    pub old_leaf: F1,
+    /// Hash of the leaf after the update.
+    pub new_leaf: F1,
+}
+
+impl From<&PrimaryCircuit> for BlockOperation {
+    fn from(circuit: &PrimaryCircuit) -> Self {
+        Self {
+            index: circuit.index,
+            old_leaf: circuit.old_value,
+            new_leaf: circuit.new_value,
+        }
+    }
+}
+
+fn params() -> Vec<ParamType> {
+    vec![
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Array(Box::new(ParamType::Tuple(vec![
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+        ]))),
+        ParamType::Bytes,
+    ]
+}
+
+fn field_to_uint(value: F1) -> Uint {
+    Uint::from_little_endian(value.to_repr().as_ref())
+}
+
+fn uint_to_field(value: Uint) -> Option<F1> {
+    let mut repr = <F1 as PrimeField>::Repr::default();
+    value.to_little_endian(repr.as_mut());
+    F1::from_repr(repr).into()
+}
+
+/// Calldata of `submitBlock` for a sealed block proof and the operations it proves.
+pub fn encode_submit_block(envelope: &ProofEnvelope, operations: &[BlockOperation]) -> Vec<u8> {
+    assert!(envelope.is_final(), "soft proofs are not submitted");
+    assert_eq!(
+        operations.len(),
+        envelope.num_steps,
+        "one operation per proven step"
+    );
+
+    let operations = operations
+        .iter()
+        .map(|op| {
+            Token::Tuple(vec![
+                Token::Uint(op.index.into()),
+                Token::Uint(field_to_uint(op.old_leaf)),
+                Token::Uint(field_to_uint(op.new_leaf)),
+            ])
+        })
+        .collect();
+    let tokens = [
+        Token::Uint(field_to_uint(envelope.initial_root)),
+        Token::Uint(field_to_uint(envelope.final_root)),
+        Token::Array(operations),
+        Token::Bytes(envelope.proof.clone()),
+    ];
+
+    let mut calldata = ethabi::short_signature(SUBMIT_BLOCK, &params()).to_vec();
+    calldata.extend(ethabi::encode(&tokens));
+    calldata
+}
+
+/// Block decoded from `submitBlock` calldata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubmittedBlock {
+    pub initial_root: F1,
+    pub final_root: F1,
+    pub operations: Vec<BlockOperation>,
+    pub proof: Vec<u8>,
+}
+
+/// Inverse of `encode_submit_block`.
+pub fn decode_submit_block(calldata: &[u8]) -> Result<SubmittedBlock, ethabi::Error> {
+    let params = params();
+    if calldata.len() < 4 || calldata[..4] != ethabi::short_signature(SUBMIT_BLOCK, &params) {
+        return Err(ethabi::Error::InvalidData);
+    }
+
+    let field = |token: Token| {
+        token
+            .into_uint()
+            .and_then(uint_to_field)
+            .ok_or(ethabi::Error::InvalidData)
+    };
+    let mut tokens = ethabi::decode(&params, &calldata[4..])?.into_iter();
+    let initial_root = field(tokens.next().unwrap())?;
+    let final_root = field(tokens.next().unwrap())?;
+    let operations = tokens
+        .next()
+        .unwrap()
+        .into_array()
+        .ok_or(ethabi::Error::InvalidData)?
+        .into_iter()
+        .map(|op| {
+            let mut fields = op
+                .into_tuple()
+                .ok_or(ethabi::Error::InvalidData)?
+                .into_iter();
+            let index = fields.next().unwrap().into_uint().unwrap();
+            if index > Uint::from(usize::MAX) {
+                return Err(ethabi::Error::InvalidData);
+            }
+            Ok(BlockOperation {
+                index: index.as_usize(),
+                old_leaf: field(fields.next().unwrap())?,
+                new_leaf: field(fields.next().unwrap())?,
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let proof = tokens
+        .next()
+        .unwrap()
+        .into_bytes()
+        .ok_or(ethabi::Error::InvalidData)?;
+
+    Ok(SubmittedBlock {
+        initial_root,
+        final_root,
+        operations,
+        proof,
+    })
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+
+use ff::PrimeField;
+use serde::{de::DeserializeOwned, Serialize};
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug)]
+pub enum EncodingError {
+    InvalidHex,
+    InvalidBase64,
+    /// The bytes have the wrong length for a field element or exceed the modulus.
+    InvalidFieldElement,
+    Encoding(bincode::Error),
+    /// The magic, parameters or height in the header of a wire-format proof, tree snapshot or
+    /// JSON tree are not recognized.
+    InvalidHeader,
+    /// A leaf path of a JSON tree is not one `0` or `1` per level.
+    InvalidPath,
+    /// Strictly decoded JSON has a field at this path, such as `leaves[2].note`, that is not
+    /// part of the format.
+    UnknownField {
+        path: String,
+    },
+    /// JSON input lacks the field at this path.
+    MissingField {
+        path: String,
+    },
+    UnsupportedVersion {
+        found: u8,
+    },
+    /// Strictly decoded input continues after the encoded value.
+    TrailingBytes {
+        count: usize,
+    },
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    /// Decoded tree state does not hash to the root recorded with it.
+    RootMismatch,
+    /// The record at this position of an imported file, counted from 1, is malformed.
+    InvalidRecord {
+        record: u64,
+    },
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodingError::InvalidHex => write!(f, "invalid hex string"),
+            EncodingError::InvalidBase64 => write!(f, "invalid base64 string"),
+            EncodingError::InvalidFieldElement => write!(f, "not a canonical field element"),
+            EncodingError::Encoding(error) => write!(f, "invalid encoding: {error}"),
+            EncodingError::InvalidHeader => write!(f, "invalid proof header"),
+            EncodingError::InvalidPath => write!(f, "invalid leaf path"),
+            EncodingError::UnknownField { path } => write!(f, "unknown field {path}"),
+            EncodingError::MissingField { path } => write!(f, "missing field {path}"),
+            EncodingError::UnsupportedVersion { found } => {
+                write!(f, "unsupported proof format version {found}")
+            }
+            EncodingError::TrailingBytes { count } => {
+                write!(f, "{count} unexpected bytes after the encoded value")
+            }
+            #[cfg(feature = "json")]
+            EncodingError::Json(error) => write!(f, "invalid JSON: {error}"),
+            EncodingError::RootMismatch => write!(f, "leaves do not match the recorded root"),
+            EncodingError::InvalidRecord { record } => write!(f, "invalid record {record}"),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+/// Lowercase hex of `bytes`, without a prefix.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Inverse of `to_hex`, accepting either case and an optional `0x` prefix.
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, EncodingError> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    // `from_str_radix` would also accept a sign, as in "+f".
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(EncodingError::InvalidHex);
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| EncodingError::InvalidHex))
+        .collect()
+}
+
+/// Standard base64 of `bytes`, with padding.
+pub fn to_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Inverse of `to_base64`. Padding is required and non-zero trailing bits are rejected.
+pub fn from_base64(base64: &str) -> Result<Vec<u8>, EncodingError> {
+    let input = base64.as_bytes();
+    if !input.len().is_multiple_of(4) {
+        return Err(EncodingError::InvalidBase64);
+    }
+
+    let mut bytes = Vec::with_capacity(input.len() / 4 * 3);
+    for (n, chunk) in input.chunks(4).enumerate() {
+        let last = n == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(EncodingError::InvalidBase64);
+        }
+
+        let mut group = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let value = BASE64_ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or(EncodingError::InvalidBase64)?;
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding;
+        let decoded = [(group >> 16) as u8, (group >> 8) as u8, group as u8];
+        if decoded[3 - padding..].iter().any(|&byte| byte != 0) {
+            return Err(EncodingError::InvalidBase64);
+        }
+        bytes.extend_from_slice(&decoded[..3 - padding]);
+    }
+
+    Ok(bytes)
+}
+
+/// Big-endian `0x`-prefixed hex of `value`, as most external verifiers print field elements.
+pub fn field_to_hex<F: PrimeField>(value: F) -> String {
+    let mut bytes = value.to_repr().as_ref().to_vec();
+    bytes.reverse();
+    format!("0x{}", to_hex(&bytes))
+}
+
+/// Inverse of `field_to_hex`. Rejects values that are not below the modulus.
+pub fn field_from_hex<F: PrimeField>(hex: &str) -> Result<F, EncodingError> {
+    let mut bytes = from_hex(hex)?;
+    bytes.reverse();
+    let mut repr = F::Repr::default();
+    if bytes.len() != repr.as_ref().len() {
+        return Err(EncodingError::InvalidFieldElement);
+    }
+    repr.as_mut().copy_from_slice(&bytes);
+
+    Option::from(F::from_repr(repr)).ok_or(EncodingError::InvalidFieldElement)
+}
+
+/// Hex of the bincode encoding of `value`, such as a proof, for transports that only carry text.
+pub fn encode_hex<T: Serialize>(value: &T) -> Result<String, EncodingError> {
+    let bytes = bincode::serialize(value).map_err(EncodingError::Encoding)?;
+    Ok(to_hex(&bytes))
+}
+
+pub fn decode_hex<T: DeserializeOwned>(hex: &str) -> Result<T, EncodingError> {
+    bincode::deserialize(&from_hex(hex)?).map_err(EncodingError::Encoding)
+}
+
+/// Like `encode_hex`, in base64.
+pub fn encode_base64<T: Serialize>(value: &T) -> Result<String, EncodingError> {
+    let bytes = bincode::serialize(value).map_err(EncodingError::Encoding)?;
+    Ok(to_base64(&bytes))
+}
+
+pub fn decode_base64<T: DeserializeOwned>(base64: &str) -> Result<T, EncodingError> {
+    bincode::deserialize(&from_base64(base64)?).map_err(EncodingError::Encoding)
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+use nova_snark::errors::NovaError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    proof_system::ProofSystem,
+    prover::{MerklePublicParams, MerkleRecursiveSNARK, F1, F2},
+    public_inputs::ZLayout,
+};
+
+/// How much an envelope's proof can be relied on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Finality {
+    /// The proof is the folded, uncompressed recursive SNARK. It is checked just as strictly,
+    /// but is large and slower to verify, so it is only meant to be consumed before compression finishes.
+    ///
+    /// The recursive SNARK holds the folded witness in the clear, so it reveals every private
+    /// input of the steps, such as the secret of a `MerkleAclCircuit`. Only final envelopes hide them.
+    Soft,
+    /// The proof is compressed by the envelope's proof system.
+    #[default]
+    Final,
+}
+
+/// Serializable proof together with the statement it proves.
+///
+/// bincode does not encode field names, so `from_bytes` cannot tell unknown or missing fields
+/// from other malformed input; strict decoding rejects trailing bytes instead.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProofEnvelope {
+    /// Proof system that compresses, or is going to compress, the proof.
+    pub proof_system: String,
+    pub num_steps: usize,
+    pub initial_root: F1,
+    pub final_root: F1,
+    pub proof: Vec<u8>,
+    /// Last, so that envelopes encoded before soft envelopes existed decode as final ones.
+    #[serde(default)]
+    pub finality: Finality,
+}
+
+/// Layout of envelopes encoded before they had a finality, all of which hold compressed proofs.
+///
+/// bincode does not apply `#[serde(default)]` to a missing trailing field, so `from_bytes`
+/// falls back to this layout instead.
+#[derive(Serialize, Deserialize)]
+struct LegacyEnvelope {
+    proof_system: String,
+    num_steps: usize,
+    initial_root: F1,
+    final_root: F1,
+    proof: Vec<u8>,
+}
+
+impl From<LegacyEnvelope> for ProofEnvelope {
+    fn from(legacy: LegacyEnvelope) -> Self {
+        Self {
+            proof_system: legacy.proof_system,
+            num_steps: legacy.num_steps,
+            initial_root: legacy.initial_root,
+            final_root: legacy.final_root,
+            proof: legacy.proof,
+            finality: Finality::Final,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EnvelopeError {
+    /// The envelope was produced by another proof system.
+    ProofSystemMismatch {
+        expected: String,
+        found: String,
+    },
+    Encoding(bincode::Error),
+    Verification(NovaError),
+    /// The proof is valid but ends at a different root than the envelope claims.
+    FinalRootMismatch,
+    /// The envelope already holds a compressed proof.
+    AlreadyFinal,
+    /// Strictly decoded input continues after the encoded value.
+    TrailingBytes {
+        count: usize,
+    },
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::ProofSystemMismatch { expected, found } => {
+                write!(f, "expected a {expected} proof, found {found}")
+            }
+            EnvelopeError::Encoding(error) => write!(f, "invalid proof encoding: {error}"),
+            EnvelopeError::Verification(error) => write!(f, "invalid proof: {error:?}"),
+            EnvelopeError::FinalRootMismatch => write!(f, "final root mismatch"),
+            EnvelopeError::AlreadyFinal => write!(f, "envelope is already final"),
+            EnvelopeError::TrailingBytes { count } => {
+                write!(f, "{count} unexpected bytes after the encoded value")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+/// How tolerant decoding is of input that does not exactly match the encoding of a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Rejects input that is not exactly one encoded value, or JSON with fields that the format
+    /// does not have, as production deployments should.
+    Strict,
+    /// Ignores anything after the encoded value, and unknown JSON fields.
+    Lenient,
+}
+
+fn decode<T: Serialize + DeserializeOwned>(
+    bytes: &[u8],
+    mode: DecodeMode,
+) -> Result<T, EnvelopeError> {
+    let value: T = bincode::deserialize(bytes).map_err(EnvelopeError::Encoding)?;
+    if mode == DecodeMode::Strict {
+        let size = bincode::serialized_size(&value).map_err(EnvelopeError::Encoding)? as usize;
+        if size < bytes.len() {
+            return Err(EnvelopeError::TrailingBytes {
+                count: bytes.len() - size,
+            });
+        }
+    }
+
+    Ok(value)
+}
+
+/// Like `decode`, falling back to the legacy layout `L` if `bytes` are too short for the current one.
+fn decode_or_legacy<T, L>(bytes: &[u8], mode: DecodeMode) -> Result<T, EnvelopeError>
+where
+    T: Serialize + DeserializeOwned,
+    L: Serialize + DeserializeOwned + Into<T>,
+{
+    match decode::<T>(bytes, mode) {
+        Err(EnvelopeError::Encoding(error)) => match decode::<L>(bytes, mode) {
+            Ok(legacy) => Ok(legacy.into()),
+            Err(_) => Err(EnvelopeError::Encoding(error)),
+        },
+        result => result,
+    }
+}
+
+impl ProofEnvelope {
+    pub fn seal<P: ProofSystem>(
+        proof: &P::Proof,
+        num_steps: usize,
+        initial_root: F1,
+        final_root: F1,
+    ) -> Result<Self, EnvelopeError> {
+        let proof = bincode::serialize(proof).map_err(EnvelopeError::Encoding)?;
+
+        Ok(Self {
+            proof_system: P::ID.to_string(),
+            num_steps,
+            initial_root,
+            final_root,
+            proof,
+            finality: Finality::Final,
+        })
+    }
+
+    /// Soft envelope holding `recursive_snark` until it is compressed by `P`.
+    ///
+    /// The envelope reveals the witness of every step, see `Finality::Soft`.
+    pub fn seal_soft<P: ProofSystem>(
+        recursive_snark: &MerkleRecursiveSNARK,
+        num_steps: usize,
+        initial_root: F1,
+        final_root: F1,
+    ) -> Result<Self, EnvelopeError> {
+        let proof = bincode::serialize(recursive_snark).map_err(EnvelopeError::Encoding)?;
+
+        Ok(Self {
+            proof_system: P::ID.to_string(),
+            num_steps,
+            initial_root,
+            final_root,
+            proof,
+            finality: Finality::Soft,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EnvelopeError> {
+        bincode::serialize(self).map_err(EnvelopeError::Encoding)
+    }
+
+    pub fn from_bytes(bytes: &[u8], mode: DecodeMode) -> Result<Self, EnvelopeError> {
+        decode_or_legacy::<Self, LegacyEnvelope>(bytes, mode)
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.finality == Finality::Final
+    }
+
+    /// Replaces the recursive SNARK of a soft envelope by its compressed `proof`, keeping the statement.
+    pub fn finalize<P: ProofSystem>(&mut self, proof: &P::Proof) -> Result<(), EnvelopeError> {
+        self.check_proof_system::<P>()?;
+        if self.is_final() {
+            return Err(EnvelopeError::AlreadyFinal);
+        }
+
+        self.proof = bincode::serialize(proof).map_err(EnvelopeError::Encoding)?;
+        self.finality = Finality::Final;
+
+        Ok(())
+    }
+
+    fn check_proof_system<P: ProofSystem>(&self) -> Result<(), EnvelopeError> {
+        if self.proof_system != P::ID {
+            return Err(EnvelopeError::ProofSystemMismatch {
+                expected: P::ID.to_string(),
+                found: self.proof_system.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Verifies the enclosed proof, soft or final, and returns the final root.
+    ///
+    /// Check `is_final` first if only compressed proofs are acceptable.
+    pub fn open<P: ProofSystem>(
+        &self,
+        pp: &MerklePublicParams,
+        vk: &P::VerifierKey,
+    ) -> Result<F1, EnvelopeError> {
+        self.check_proof_system::<P>()?;
+
+        let final_root = match self.finality {
+            Finality::Soft => {
+                let recursive_snark: MerkleRecursiveSNARK =
+                    bincode::deserialize(&self.proof).map_err(EnvelopeError::Encoding)?;
+                let z0_primary = ZLayout::MERKLE_PROCESS
+                    .builder()
+                    .set("root", self.initial_root)
+                    .build();
+                let (zn_primary, _) = recursive_snark
+                    .verify(pp, self.num_steps, z0_primary, vec![F2::zero()])
+                    .map_err(EnvelopeError::Verification)?;
+                ZLayout::MERKLE_PROCESS.read(&zn_primary, "root")
+            }
+            Finality::Final => {
+                let proof: P::Proof =
+                    bincode::deserialize(&self.proof).map_err(EnvelopeError::Encoding)?;
+                P::verify_compressed(pp, vk, &proof, self.num_steps, self.initial_root)
+                    .map_err(EnvelopeError::Verification)?
+            }
+        };
+        if final_root != self.final_root {
+            return Err(EnvelopeError::FinalRootMismatch);
+        }
+
+        Ok(final_root)
+    }
+}
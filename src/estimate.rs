@@ -0,0 +1,84 @@
+//! Sizes of proofs computed from their parameters alone, for capacity planning without
+//! generating proofs.
+
+use ff::PrimeField;
+
+use crate::{
+    prover::{MerklePublicParams, F1},
+    public_inputs::ZLayout,
+    wire::HEADER_LEN,
+};
+
+/// Bincode length prefix of a vector.
+const LEN_PREFIX: u64 = 8;
+
+/// Field elements by which `estimate_compressed_snark_size` may miss the size of a generated
+/// proof, as checked by the `check_size_estimates` example.
+pub const SNARK_SIZE_TOLERANCE: u64 = 4;
+
+/// Bytes of a field element, which is also the size of a compressed curve point of the cycle.
+fn element_size() -> u64 {
+    <F1 as PrimeField>::Repr::default().as_ref().len() as u64
+}
+
+/// Bytes of a `MerklePath` in the wire format, for a tree of `height` levels where every
+/// internal node has `arity` children.
+///
+/// The trees of this crate are binary; other arities give the size with `arity - 1` siblings
+/// per level.
+pub fn estimate_proof_size(height: usize, arity: usize) -> u64 {
+    assert!(arity >= 2, "a tree needs at least two children per node");
+    let siblings = height as u64 * (arity as u64 - 1);
+
+    HEADER_LEN as u64 + LEN_PREFIX + siblings * element_size() + 8 + element_size()
+}
+
+/// Approximate bytes of a `SpartanIpa` proof for the circuits of `pp`, as sealed in a
+/// `ProofEnvelope` before the envelope fields.
+///
+/// The size only depends on the circuit shapes, not on the number of steps. Sumcheck and
+/// inner-product rounds are derived from the padded constraint and variable counts, so the
+/// estimate may be off by up to `SNARK_SIZE_TOLERANCE` field elements; use the length of
+/// `ProofEnvelope::to_bytes` for the exact size of a sealed proof.
+pub fn estimate_compressed_snark_size(pp: &MerklePublicParams) -> u64 {
+    let (constraints_primary, constraints_secondary) = pp.num_constraints();
+    let (variables_primary, variables_secondary) = pp.num_variables();
+    let e = element_size();
+
+    // Running relaxed instance of each circuit: commitments to W and E, the two hashes of the
+    // public IO and the scalar u.
+    let relaxed_instance = 2 * e + LEN_PREFIX + 2 * e + e;
+    // Instance of the last secondary step and the commitment to its cross term.
+    let instance = e + LEN_PREFIX + 2 * e;
+    let nifs = e;
+    let zn = LEN_PREFIX + ZLayout::MERKLE_PROCESS.arity() as u64 * e + LEN_PREFIX + e;
+
+    2 * relaxed_instance
+        + instance
+        + nifs
+        + spartan_size(constraints_primary, variables_primary)
+        + spartan_size(constraints_secondary, variables_secondary)
+        + zn
+}
+
+/// Bytes of a Spartan proof with an IPA evaluation argument for an R1CS shape.
+fn spartan_size(num_constraints: usize, num_variables: usize) -> u64 {
+    let e = element_size();
+    let rounds_x = log2_ceil(num_constraints);
+    let rounds_y = log2_ceil(2 * num_variables);
+    let rounds_ipa = log2_ceil(num_constraints.max(num_variables));
+
+    // Outer sumcheck over cubic polynomials and its three claims, with the evaluation of E.
+    let outer = LEN_PREFIX + rounds_x * (LEN_PREFIX + 3 * e) + 3 * e + e;
+    // Inner sumcheck over quadratic polynomials, with the evaluation of W.
+    let inner = LEN_PREFIX + rounds_y * (LEN_PREFIX + 2 * e) + e;
+    // Batched evaluation of W and E and the inner-product argument opening it.
+    let batch = LEN_PREFIX + rounds_ipa * (LEN_PREFIX + 2 * e) + LEN_PREFIX + 2 * e;
+    let ipa = 2 * (LEN_PREFIX + rounds_ipa * e) + e;
+
+    outer + inner + batch + ipa
+}
+
+fn log2_ceil(n: usize) -> u64 {
+    n.max(1).next_power_of_two().trailing_zeros() as u64
+}
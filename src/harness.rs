@@ -0,0 +1,81 @@
+use std::fmt;
+
+use nova_snark::errors::NovaError;
+
+use crate::{
+    envelope::{DecodeMode, EnvelopeError, ProofEnvelope},
+    merkle_tree::{circuits::MerkleProcessCircuit, tree::MerkleTree},
+    params::{ParamsBundle, TreeConfig},
+    proof_system::{ProofSystem, SpartanIpa},
+    prover::{Prover, ProverError, Verifier, F1},
+};
+
+#[derive(Debug)]
+pub enum OneShotError {
+    Prover(ProverError),
+    Compression(NovaError),
+    Envelope(EnvelopeError),
+    /// The verified final root differs from the root of the updated tree.
+    RootMismatch,
+}
+
+impl fmt::Display for OneShotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OneShotError::Prover(error) => write!(f, "{error}"),
+            OneShotError::Compression(error) => write!(f, "compression failed: {error:?}"),
+            OneShotError::Envelope(error) => write!(f, "{error}"),
+            OneShotError::RootMismatch => write!(f, "final root mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for OneShotError {}
+
+/// Runs setup, folding, compression, sealing and verification in one call.
+pub struct OneShot;
+
+impl OneShot {
+    /// Applies `ops` as `(index, leaf)` updates to an empty tree of `height` levels,
+    /// proves them with Spartan and verifies the envelope after an encoding round trip.
+    /// Returns the final root.
+    pub fn prove_and_verify(height: usize, ops: &[(usize, F1)]) -> Result<F1, OneShotError> {
+        if ops.is_empty() {
+            return Err(OneShotError::Prover(ProverError::NoSteps));
+        }
+
+        let bundle = ParamsBundle::setup(TreeConfig::new(height));
+        let (pk, vk) = SpartanIpa::setup(bundle.public_params());
+
+        let mut tree: MerkleTree<F1, F1> = MerkleTree::new(height);
+        let initial_root = tree.get_root();
+        let circuits: Vec<_> = ops
+            .iter()
+            .map(|&(index, leaf)| MerkleProcessCircuit::from_update(&mut tree, index, leaf))
+            .collect();
+        let final_root = tree.get_root();
+
+        let prover = Prover::new(&bundle);
+        let recursive_snark = prover
+            .prove(&circuits, initial_root)
+            .map_err(OneShotError::Prover)?;
+        let proof = prover
+            .prove_compressed::<SpartanIpa>(&pk, &recursive_snark)
+            .map_err(OneShotError::Compression)?;
+        let envelope =
+            ProofEnvelope::seal::<SpartanIpa>(&proof, circuits.len(), initial_root, final_root)
+                .map_err(OneShotError::Envelope)?;
+
+        let bytes = envelope.to_bytes().map_err(OneShotError::Envelope)?;
+        let envelope = ProofEnvelope::from_bytes(&bytes, DecodeMode::Strict)
+            .map_err(OneShotError::Envelope)?;
+        let verified_root = Verifier::new(&bundle)
+            .open::<SpartanIpa>(&vk, &envelope)
+            .map_err(OneShotError::Envelope)?;
+        if verified_root != final_root {
+            return Err(OneShotError::RootMismatch);
+        }
+
+        Ok(verified_root)
+    }
+}
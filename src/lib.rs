@@ -1 +1,18 @@
+#[cfg(feature = "abi")]
+pub mod abi;
+pub mod encoding;
+pub mod envelope;
+pub mod estimate;
+#[cfg(feature = "test-harness")]
+pub mod harness;
 pub mod merkle_tree;
+pub mod params;
+pub mod prelude;
+pub mod proof_system;
+pub mod prover;
+pub mod public_inputs;
+pub mod rng;
+mod sealed;
+#[cfg(feature = "test-harness")]
+pub mod testing;
+pub mod wire;
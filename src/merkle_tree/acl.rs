@@ -0,0 +1,244 @@
+use std::sync::OnceLock;
+
+use bellperson::{gadgets::num::AllocatedNum, ConstraintSystem, SynthesisError};
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::{circuit::poseidon_hash, poseidon::PoseidonConstants, Strength};
+use nova_snark::traits::circuit::StepCircuit;
+use pasta_curves::{Fp, Fq};
+
+use super::{
+    circuits::MerkleInclusionCircuit,
+    gadgets::{enforce_equal, index_bits},
+    tree::{compute_merkle_root, hash_pair, Leafable, MerkleTree},
+};
+use crate::public_inputs::ZLayout;
+
+/// Leaf owned by whoever knows the preimage of `owner`, or by nobody if `owner` is zero.
+///
+/// Hashes to `H(owner, value)`. Unowned leaves can be written by anyone, which is how leaves are claimed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OwnedLeaf<F: PrimeField> {
+    /// `owner_commitment(secret)` of the owner, or zero.
+    pub owner: F,
+    pub value: F,
+}
+
+impl<F: PrimeField> OwnedLeaf<F> {
+    pub fn hash_with(&self, constants: &PoseidonConstants<F, U2>) -> F {
+        hash_pair(constants, self.owner, self.value)
+    }
+
+    /// Whether `secret` allows overwriting this leaf.
+    pub fn is_writable_with(&self, constants: &PoseidonConstants<F, U2>, secret: F) -> bool {
+        self.owner == F::zero() || owner_commitment(constants, secret) == self.owner
+    }
+}
+
+/// Commitment to `secret` stored as the owner of a leaf.
+pub fn owner_commitment<F: PrimeField>(constants: &PoseidonConstants<F, U2>, secret: F) -> F {
+    hash_pair(constants, secret, secret)
+}
+
+// As for the primitive leaves, a generic impl would overlap with `Leafable<F> for F`.
+macro_rules! impl_owned_leaf {
+    ($field:ty, $constants:ident) => {
+        fn $constants() -> &'static PoseidonConstants<$field, U2> {
+            static CONSTANTS: OnceLock<PoseidonConstants<$field, U2>> = OnceLock::new();
+            CONSTANTS.get_or_init(PoseidonConstants::new)
+        }
+
+        impl Leafable<$field> for OwnedLeaf<$field> {
+            fn empty_leaf() -> Self {
+                Self {
+                    owner: <$field>::zero(),
+                    value: <$field>::zero(),
+                }
+            }
+
+            fn hash(&self) -> $field {
+                self.hash_with($constants())
+            }
+
+            fn to_stored(&self) -> Option<Vec<u8>> {
+                let mut value = self.owner.to_repr().as_ref().to_vec();
+                value.extend_from_slice(self.value.to_repr().as_ref());
+                Some(value)
+            }
+
+            fn from_stored(bytes: &[u8]) -> Option<Self> {
+                if bytes.len() % 2 != 0 {
+                    return None;
+                }
+                let (owner, value) = bytes.split_at(bytes.len() / 2);
+                Some(Self {
+                    owner: <$field as Leafable<$field>>::from_stored(owner)?,
+                    value: <$field as Leafable<$field>>::from_stored(value)?,
+                })
+            }
+        }
+    };
+}
+
+impl_owned_leaf!(Fp, fp_constants);
+impl_owned_leaf!(Fq, fq_constants);
+
+/// Step writing an owned leaf, allowed only if `secret` opens the owner commitment of the old leaf.
+///
+/// Leaf hashes are computed with `constants`, so the tree must use standard-strength constants
+/// like `OwnedLeaf::hash`.
+///
+/// `secret` is a private witness of the step, which only a compressed proof keeps private. The
+/// recursive SNARK of a soft `ProofEnvelope` carries the folded witness in the clear, so never
+/// publish one for a chain of these steps.
+#[derive(Clone, Debug)]
+pub struct MerkleAclCircuit<F: PrimeField> {
+    pub constants: PoseidonConstants<F, U2>,
+    pub siblings: Vec<F>,
+    pub index: usize,
+    pub old_leaf: OwnedLeaf<F>,
+    pub new_leaf: OwnedLeaf<F>,
+    pub secret: F,
+}
+
+impl<F: PrimeField> MerkleAclCircuit<F>
+where
+    OwnedLeaf<F>: Leafable<F>,
+{
+    /// Writes `leaf` at `index` with the capability `secret` and returns the step proving that write.
+    pub fn from_update(
+        tree: &mut MerkleTree<F, OwnedLeaf<F>>,
+        index: usize,
+        leaf: OwnedLeaf<F>,
+        secret: F,
+    ) -> Self {
+        assert!(
+            tree.poseidon_constants.strength == Strength::Standard,
+            "owned leaves are hashed with standard-strength constants"
+        );
+        let old_leaf = tree.get_leaf(index);
+        assert!(
+            old_leaf.is_writable_with(&tree.poseidon_constants, secret),
+            "secret does not open the owner commitment"
+        );
+        let siblings = tree.prove(index);
+        tree.update(index, leaf);
+
+        Self {
+            constants: tree.poseidon_constants.as_ref().clone(),
+            siblings,
+            index,
+            old_leaf,
+            new_leaf: leaf,
+            secret,
+        }
+    }
+}
+
+impl<F: PrimeField> MerkleAclCircuit<F> {
+    pub fn old_root(&self) -> F {
+        compute_merkle_root(
+            &self.constants,
+            self.index,
+            self.old_leaf.hash_with(&self.constants),
+            &self.siblings,
+        )
+    }
+
+    fn inclusion(&self) -> MerkleInclusionCircuit<F> {
+        MerkleInclusionCircuit {
+            constants: self.constants.clone(),
+            siblings: self.siblings.clone(),
+            index: self.index,
+            value: F::zero(),
+        }
+    }
+
+    fn synthesize_leaf<CS: ConstraintSystem<F>>(
+        &self,
+        mut cs: CS,
+        leaf: &OwnedLeaf<F>,
+    ) -> Result<(AllocatedNum<F>, AllocatedNum<F>), SynthesisError> {
+        let owner = AllocatedNum::alloc(cs.namespace(|| "allocate owner"), || Ok(leaf.owner))?;
+        let value = AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(leaf.value))?;
+        let hash = poseidon_hash(
+            cs.namespace(|| "hash leaf"),
+            vec![owner.clone(), value],
+            &self.constants,
+        )?;
+
+        Ok((owner, hash))
+    }
+}
+
+impl<F: PrimeField> StepCircuit<F> for MerkleAclCircuit<F> {
+    fn arity(&self) -> usize {
+        ZLayout::MERKLE_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // old root
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let index = AllocatedNum::alloc(cs.namespace(|| "allocate index"), || {
+            Ok(F::from(self.index as u64))
+        })?;
+        let bits = index_bits(
+            cs.namespace(|| "decompose index"),
+            &index,
+            self.siblings.len(),
+        )?;
+        // The owner is checked on the old path, so the new path must replace that same leaf.
+        let siblings = self.inclusion().alloc_siblings(cs)?;
+
+        let (old_owner, old_hash) =
+            self.synthesize_leaf(cs.namespace(|| "old leaf"), &self.old_leaf)?;
+        let old_root = self.inclusion().synthesize_with_bits(
+            &mut cs.namespace(|| "calculate old root"),
+            old_hash,
+            &bits,
+            &siblings,
+        )?;
+        enforce_equal(cs, || "verify old root", &old_root[0], &z[0]);
+
+        let secret = AllocatedNum::alloc(cs.namespace(|| "allocate secret"), || Ok(self.secret))?;
+        let commitment = poseidon_hash(
+            cs.namespace(|| "commit secret"),
+            vec![secret.clone(), secret],
+            &self.constants,
+        )?;
+        // owner * (commitment - owner) = 0: the leaf is unowned or the secret opens its commitment.
+        cs.enforce(
+            || "verify owner",
+            |lc| lc + old_owner.get_variable(),
+            |lc| lc + commitment.get_variable() - old_owner.get_variable(),
+            |lc| lc,
+        );
+
+        let (_, new_hash) = self.synthesize_leaf(cs.namespace(|| "new leaf"), &self.new_leaf)?;
+        let new_root = self.inclusion().synthesize_with_bits(
+            &mut cs.namespace(|| "calculate new root"),
+            new_hash,
+            &bits,
+            &siblings,
+        )?;
+
+        Ok(vec![new_root[0].clone()]) // new root
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        assert_eq!(self.old_root(), z[0], "old root mismatch");
+        assert!(
+            self.old_leaf.is_writable_with(&self.constants, self.secret),
+            "secret does not open the owner commitment"
+        );
+
+        vec![compute_merkle_root(
+            &self.constants,
+            self.index,
+            self.new_leaf.hash_with(&self.constants),
+            &self.siblings,
+        )]
+    }
+}
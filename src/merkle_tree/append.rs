@@ -0,0 +1,129 @@
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+use serde::{Deserialize, Serialize};
+
+use super::tree::{compute_merkle_root, hash_pair, Leafable, MerkleTree};
+
+/// Tree whose leaves are only ever appended, like a transparency log.
+///
+/// Every append is a version of the underlying tree, so the root of any earlier size stays available.
+#[derive(Clone, Debug)]
+pub struct AppendOnlyMerkleTree<F: PrimeField, V: Leafable<F>> {
+    tree: MerkleTree<F, V>,
+    len: usize,
+}
+
+impl<F: PrimeField, V: Leafable<F>> AppendOnlyMerkleTree<F, V> {
+    pub fn new(height: usize) -> Self {
+        Self {
+            tree: MerkleTree::new(height),
+            len: 0,
+        }
+    }
+
+    pub fn tree(&self) -> &MerkleTree<F, V> {
+        &self.tree
+    }
+
+    /// Number of appended leaves.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends `leaf` and returns its index.
+    pub fn append(&mut self, leaf: V) -> usize {
+        let index = self.len;
+        let height = self.tree.height();
+        assert!(
+            height >= usize::BITS as usize || index >> height == 0,
+            "tree is full"
+        );
+        self.tree.update(index, leaf);
+        self.len += 1;
+
+        index
+    }
+
+    pub fn get_root(&self) -> F {
+        self.tree.get_root()
+    }
+
+    /// Root when the tree held its first `len` leaves, or `None` if it never has.
+    pub fn get_root_at(&self, len: usize) -> Option<F> {
+        self.tree.get_root_at(len)
+    }
+
+    /// Proves that the tree of the first `old_len` leaves is a prefix of the current tree.
+    pub fn prove_consistency(&self, old_len: usize) -> ConsistencyProof<F> {
+        assert!(old_len <= self.len, "old size is larger than the tree");
+        let (siblings, leaf_hash) = if old_len == self.len {
+            (vec![], self.tree.empty_leaf().hash())
+        } else {
+            (self.tree.prove(old_len), self.tree.get_leaf(old_len).hash())
+        };
+
+        ConsistencyProof {
+            old_len,
+            new_len: self.len,
+            siblings,
+            leaf_hash,
+        }
+    }
+}
+
+/// Proof that the tree of the first `old_len` leaves is a prefix of a tree of `new_len` leaves.
+///
+/// It is the path of leaf `old_len` in the newer tree. Its left siblings cover exactly the
+/// first `old_len` leaves, so they also yield the older root once the leaf and every right
+/// sibling are replaced by empty subtrees.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsistencyProof<F: PrimeField> {
+    pub old_len: usize,
+    pub new_len: usize,
+    /// Siblings of leaf `old_len` in the newer tree, from the leaf upwards. Empty if both sizes are equal.
+    pub siblings: Vec<F>,
+    /// Hash of leaf `old_len` in the newer tree.
+    pub leaf_hash: F,
+}
+
+impl<F: PrimeField> ConsistencyProof<F> {
+    /// Checks the proof against both roots of a tree of `height` levels, where `empty_hash` is
+    /// the hash of the empty leaf of the tree. Both sizes must fit in the tree, and `height` must
+    /// come from the verifier rather than from the proof.
+    pub fn verify(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        empty_hash: F,
+        height: usize,
+        old_root: F,
+        new_root: F,
+    ) -> bool {
+        let capacity_fits = height >= usize::BITS as usize || self.new_len <= 1 << height;
+        if self.old_len > self.new_len || !capacity_fits {
+            return false;
+        }
+        if self.old_len == self.new_len {
+            return self.siblings.is_empty() && old_root == new_root;
+        }
+        if self.siblings.len() != height {
+            return false;
+        }
+
+        let mut zero = empty_hash;
+        let mut old_siblings = vec![];
+        for (level, &sibling) in self.siblings.iter().enumerate() {
+            let right_child = level < usize::BITS as usize && (self.old_len >> level) & 1 == 1;
+            old_siblings.push(if right_child { sibling } else { zero });
+            zero = hash_pair(constants, zero, zero);
+        }
+
+        compute_merkle_root(constants, self.old_len, empty_hash, &old_siblings) == old_root
+            && compute_merkle_root(constants, self.old_len, self.leaf_hash, &self.siblings)
+                == new_root
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+
+use super::{
+    node_store::AsyncNodeStore,
+    path::TreePath,
+    tree::{hash_pair, Leafable},
+    zero_hashes::zero_hash_ladder,
+};
+
+/// `MerkleTree` whose internal nodes are kept in an `AsyncNodeStore`, so updating and proving
+/// await the store instead of blocking the executor thread.
+///
+/// Leaves are kept in memory as in `MerkleTree`. An update only records its leaves once the
+/// store accepted every node; if the store fails midway, the nodes written since its last commit
+/// may not match the leaves and the tree should be reopened from the committed state.
+#[derive(Clone, Debug)]
+pub struct AsyncMerkleTree<F: PrimeField, V: Leafable<F>, S: AsyncNodeStore<F>> {
+    poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+    height: usize,
+    store: S,
+    leaves: im::HashMap<TreePath, V>,
+    zero_hashes: Vec<F>,
+    empty_leaf: V,
+}
+
+impl<F: PrimeField, V: Leafable<F>, S: AsyncNodeStore<F>> AsyncMerkleTree<F, V, S> {
+    /// Like `MerkleTree::new_with_store`. The store must be empty, which is not checked.
+    pub fn new_with_store(
+        height: usize,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        empty: V,
+        store: S,
+    ) -> Self {
+        Self::open_with_store(height, poseidon_constants, empty, store, [])
+    }
+
+    /// Reopens a tree whose nodes were kept in `store`, given its occupied `leaves`, which an async
+    /// store does not hold. The nodes are trusted to match them.
+    pub fn open_with_store(
+        height: usize,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        empty: V,
+        store: S,
+        leaves: impl IntoIterator<Item = (TreePath, V)>,
+    ) -> Self {
+        let empty_hash = empty.hash();
+        let mut zero_hashes = zero_hash_ladder(&poseidon_constants, empty_hash, height);
+        zero_hashes.reverse();
+        let leaves: im::HashMap<TreePath, V> = leaves
+            .into_iter()
+            .filter(|(_, leaf)| leaf.hash() != empty_hash)
+            .collect();
+        assert!(
+            leaves.keys().all(|path| path.depth() == height),
+            "path does not lead to a leaf"
+        );
+
+        Self {
+            poseidon_constants,
+            height,
+            store,
+            leaves,
+            zero_hashes,
+            empty_leaf: empty,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    pub async fn get_root(&self) -> Result<F, S::Error> {
+        self.get_node_hash(&TreePath::root()).await
+    }
+
+    pub fn get_leaf(&self, index: usize) -> V {
+        self.get_leaf_by_path(&self.leaf_path(index))
+    }
+
+    pub fn get_leaf_by_path(&self, path: &TreePath) -> V {
+        assert_eq!(path.depth(), self.height, "path does not lead to a leaf");
+        match self.leaves.get(path) {
+            Some(leaf) => leaf.clone(),
+            None => self.empty_leaf.clone(),
+        }
+    }
+
+    /// Sets the leaf at `index` and returns the leaf it replaced.
+    pub async fn update(&mut self, index: usize, leaf: V) -> Result<V, S::Error> {
+        let path = self.leaf_path(index);
+        let old = self.get_leaf_by_path(&path);
+        self.update_batch_by_path(&[(path, leaf)]).await?;
+        Ok(old)
+    }
+
+    /// Like `MerkleTree::update_batch_by_path`, writing the nodes in one batch and committing the
+    /// store once.
+    pub async fn update_batch_by_path(
+        &mut self,
+        updates: &[(TreePath, V)],
+    ) -> Result<(), S::Error> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut staged = HashMap::new();
+        let mut paths = BTreeSet::new();
+        for (path, leaf) in updates {
+            assert_eq!(path.depth(), self.height, "path does not lead to a leaf");
+            self.set_node_hash(&mut staged, path.clone(), leaf.hash());
+            paths.insert(path.clone());
+        }
+        for _ in 0..self.height {
+            let parents: BTreeSet<TreePath> = paths
+                .into_iter()
+                .map(|mut path| {
+                    path.pop();
+                    path
+                })
+                .collect();
+            for path in &parents {
+                let l = self
+                    .get_staged_node_hash(&staged, &path.child(false))
+                    .await?;
+                let r = self
+                    .get_staged_node_hash(&staged, &path.child(true))
+                    .await?;
+                let h = hash_pair(&self.poseidon_constants, l, r);
+                self.set_node_hash(&mut staged, path.clone(), h);
+            }
+            paths = parents;
+        }
+        self.store.write_batch(staged.into_iter().collect()).await?;
+        self.store.commit().await?;
+
+        let empty_hash = self.zero_hashes[self.height];
+        for (path, leaf) in updates {
+            if leaf.hash() == empty_hash {
+                self.leaves.remove(path);
+            } else {
+                self.leaves.insert(path.clone(), leaf.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn prove(&self, index: usize) -> Result<Vec<F>, S::Error> {
+        self.prove_by_path(&self.leaf_path(index)).await
+    }
+
+    /// Siblings of the leaf at `path` from the leaf upwards, like `MerkleTree::prove_by_path`.
+    pub async fn prove_by_path(&self, path: &TreePath) -> Result<Vec<F>, S::Error> {
+        assert_eq!(path.depth(), self.height, "path does not lead to a leaf");
+        let mut path = path.clone();
+        let mut siblings = vec![];
+        while !path.is_root() {
+            siblings.push(self.get_node_hash(&path.sibling()).await?);
+            path.pop();
+        }
+
+        Ok(siblings)
+    }
+
+    async fn get_node_hash(&self, path: &TreePath) -> Result<F, S::Error> {
+        let h = self.store.get(path).await?;
+        Ok(h.unwrap_or(self.zero_hashes[path.depth()]))
+    }
+
+    async fn get_staged_node_hash(
+        &self,
+        staged: &HashMap<TreePath, Option<F>>,
+        path: &TreePath,
+    ) -> Result<F, S::Error> {
+        match staged.get(path) {
+            Some(h) => Ok(h.unwrap_or(self.zero_hashes[path.depth()])),
+            None => self.get_node_hash(path).await,
+        }
+    }
+
+    /// Nodes equal to the hash of an empty subtree are removed rather than stored.
+    fn set_node_hash(&self, staged: &mut HashMap<TreePath, Option<F>>, path: TreePath, h: F) {
+        let h = (h != self.zero_hashes[path.depth()]).then_some(h);
+        staged.insert(path, h);
+    }
+
+    fn leaf_path(&self, index: usize) -> TreePath {
+        assert!(
+            self.height >= usize::BITS as usize || index >> self.height == 0,
+            "index {index} out of range"
+        );
+        TreePath::from_index(index, self.height)
+    }
+}
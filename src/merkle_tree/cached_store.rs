@@ -0,0 +1,207 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+use ff::PrimeField;
+
+use super::{node_store::NodeStore, path::TreePath, tree::Leafable};
+
+/// Levels kept by `CachedStore::new` regardless of its capacity: at most `2^17 - 1` nodes.
+pub const DEFAULT_PINNED_DEPTH: usize = 16;
+
+/// Node store that keeps recently read nodes of another store, typically on disk, in memory.
+///
+/// Nodes less deep than the pinned depth are never evicted once read, so the top of the tree,
+/// which every path crosses, is read from `inner` at most once. Deeper nodes are evicted least
+/// recently used first when more than `capacity` of them are cached. Both present and absent
+/// nodes are cached, since most reads of a sparse tree are of empty subtrees.
+///
+/// Writes, leaves included, go through to `inner`. A clone copies the cache, so like the stores it wraps it must not
+/// be updated separately from the original if they share storage.
+#[derive(Debug)]
+pub struct CachedStore<F: PrimeField, S: NodeStore<F>> {
+    inner: S,
+    capacity: usize,
+    pinned_depth: usize,
+    cache: Mutex<Cache<F>>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Cache<F> {
+    pinned: HashMap<TreePath, Option<F>>,
+    /// Each deeper node with the tick of its last use.
+    entries: HashMap<TreePath, (Option<F>, u64)>,
+    /// Nodes of `entries` by the tick of their last use.
+    order: BTreeMap<u64, TreePath>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<F: PrimeField> Cache<F> {
+    fn get(&mut self, path: &TreePath, pinned_depth: usize) -> Option<Option<F>> {
+        let h = if path.depth() < pinned_depth {
+            self.pinned.get(path).copied()
+        } else {
+            let tick = self.tick;
+            self.entries.get_mut(path).map(|(h, last_use)| {
+                self.order.remove(last_use);
+                self.order.insert(tick, path.clone());
+                *last_use = tick;
+                *h
+            })
+        };
+        self.tick += 1;
+        h
+    }
+
+    fn put(&mut self, path: TreePath, h: Option<F>, pinned_depth: usize, capacity: usize) {
+        if path.depth() < pinned_depth {
+            self.pinned.insert(path, h);
+            return;
+        }
+        if let Some((_, last_use)) = self.entries.remove(&path) {
+            self.order.remove(&last_use);
+        }
+        if capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= capacity {
+            let (_, evicted) = self.order.pop_first().unwrap();
+            self.entries.remove(&evicted);
+        }
+        self.entries.insert(path.clone(), (h, self.tick));
+        self.order.insert(self.tick, path);
+        self.tick += 1;
+    }
+
+    fn clear(&mut self) {
+        self.pinned.clear();
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+impl<F: PrimeField, S: NodeStore<F>> CachedStore<F, S> {
+    /// Caches up to `capacity` nodes of `inner` besides those pinned by `DEFAULT_PINNED_DEPTH`.
+    pub fn new(inner: S, capacity: usize) -> Self {
+        Self::with_pinned_depth(inner, capacity, DEFAULT_PINNED_DEPTH)
+    }
+
+    /// Like `new`, pinning the nodes less deep than `pinned_depth`.
+    pub fn with_pinned_depth(inner: S, capacity: usize, pinned_depth: usize) -> Self {
+        Self {
+            inner,
+            capacity,
+            pinned_depth,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The wrapped store. Its nodes must not be written through it while the cache is in use.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn pinned_depth(&self) -> usize {
+        self.pinned_depth
+    }
+
+    /// Number of reads served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.cache.lock().unwrap().hits
+    }
+
+    /// Number of reads passed to the wrapped store.
+    pub fn misses(&self) -> u64 {
+        self.cache.lock().unwrap().misses
+    }
+
+    fn put(&mut self, path: TreePath, h: Option<F>) {
+        self.cache
+            .get_mut()
+            .unwrap()
+            .put(path, h, self.pinned_depth, self.capacity);
+    }
+}
+
+impl<F: PrimeField, S: NodeStore<F> + Clone> Clone for CachedStore<F, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            capacity: self.capacity,
+            pinned_depth: self.pinned_depth,
+            cache: Mutex::new(self.cache.lock().unwrap().clone()),
+        }
+    }
+}
+
+impl<F: PrimeField, S: NodeStore<F>> NodeStore<F> for CachedStore<F, S> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(h) = cache.get(path, self.pinned_depth) {
+            cache.hits += 1;
+            return h;
+        }
+        cache.misses += 1;
+        let h = self.inner.get(path);
+        cache.put(path.clone(), h, self.pinned_depth, self.capacity);
+
+        h
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        self.put(path.clone(), Some(hash));
+        self.inner.insert(path, hash)
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        self.put(path.clone(), None);
+        self.inner.remove(path)
+    }
+
+    fn write_batch(&mut self, writes: impl IntoIterator<Item = (TreePath, Option<F>)>) {
+        let writes: Vec<(TreePath, Option<F>)> = writes.into_iter().collect();
+        for (path, h) in &writes {
+            self.put(path.clone(), *h);
+        }
+        self.inner.write_batch(writes)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn clear(&mut self) {
+        self.cache.get_mut().unwrap().clear();
+        self.inner.clear()
+    }
+
+    fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+        self.inner.write_leaves(writes)
+    }
+
+    fn leaves<V: Leafable<F>>(&self, height: usize) -> Vec<(TreePath, V)> {
+        self.inner.leaves(height)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        self.inner.iter()
+    }
+
+    fn commit(&mut self) {
+        self.inner.commit()
+    }
+}
@@ -1,5 +1,11 @@
+use std::{collections::HashMap, ops::Range};
+
 use bellperson::{
-    gadgets::{boolean::AllocatedBit, num::AllocatedNum},
+    gadgets::{
+        boolean::{AllocatedBit, Boolean},
+        num::AllocatedNum,
+    },
+    util_cs::test_cs::TestConstraintSystem,
     ConstraintSystem, SynthesisError,
 };
 use ff::PrimeField;
@@ -7,7 +13,13 @@ use generic_array::typenum::U2;
 use neptune::{circuit::poseidon_hash, poseidon::PoseidonConstants, Poseidon};
 use nova_snark::traits::circuit::StepCircuit;
 
-use super::tree::usize_to_vec;
+use super::{
+    gadgets::{enforce_equal, enforce_equal_constant, index_bits},
+    node_store::NodeStore,
+    path::TreePath,
+    tree::{aligned_blocks, compute_merkle_root, hash_pair, index_fits, Leafable, MerkleTree},
+};
+use crate::public_inputs::ZLayout;
 
 #[derive(Clone, Debug)]
 pub struct InternalHashCircuit<F: PrimeField> {
@@ -83,28 +95,96 @@ where
         cs: &mut CS,
     ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
         let value = AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(self.value))?;
-        let path = usize_to_vec(self.index, self.siblings.len());
-        let mut result = vec![value];
-        for (i, (&lr_bit, &sibling)) in path.iter().rev().zip(self.siblings.iter()).enumerate() {
-            let poseidon_circuit = InternalHashCircuit {
-                constants: self.constants.clone(),
-                sibling,
-                lr_bit,
-            };
+        self.synthesize_with_value(cs, value)
+    }
+
+    /// Like `synthesize`, for a leaf hash already allocated by the caller. `self.value` is ignored.
+    pub fn synthesize_with_value<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        value: AllocatedNum<F>,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let index = AllocatedNum::alloc(cs.namespace(|| "allocate index"), || {
+            Ok(F::from(self.index as u64))
+        })?;
+        self.synthesize_with_index(cs, value, &index)
+    }
+
+    /// Like `synthesize_with_value`, for an index allocated by the caller, such as an entry of
+    /// the state or a public input. `self.index` is ignored.
+    pub fn synthesize_with_index<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        value: AllocatedNum<F>,
+        index: &AllocatedNum<F>,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let bits = index_bits(
+            cs.namespace(|| "decompose index"),
+            index,
+            self.siblings.len(),
+        )?;
+        let siblings = self.alloc_siblings(cs)?;
+        self.synthesize_with_bits(cs, value, &bits, &siblings)
+    }
 
-            result = poseidon_circuit.synthesize(
-                &mut cs.namespace(|| format!("calculate parent hash {i}")),
+    /// Allocates the siblings, from the leaf level up, for `synthesize_with_bits`.
+    pub fn alloc_siblings<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        self.siblings
+            .iter()
+            .enumerate()
+            .map(|(i, &sibling)| {
+                AllocatedNum::alloc(cs.namespace(|| format!("allocate sibling {i}")), || {
+                    Ok(sibling)
+                })
+            })
+            .collect()
+    }
+
+    /// Like `synthesize_with_index`, for the bits of an index from `gadgets::index_bits` and
+    /// siblings from `alloc_siblings`. An update passes the same bits and siblings to its old and
+    /// new paths, so that both roots are above the same leaf and differ only by its value.
+    /// `self.siblings` is ignored.
+    pub fn synthesize_with_bits<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        value: AllocatedNum<F>,
+        bits: &[Boolean],
+        siblings: &[AllocatedNum<F>],
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        assert_eq!(bits.len(), siblings.len(), "one bit per level");
+        let mut result = value;
+        for (i, (lr_bit, sibling)) in bits.iter().zip(siblings).enumerate() {
+            let mut cs = cs.namespace(|| format!("calculate parent hash {i}"));
+            let (l, r) = AllocatedNum::conditionally_reverse(
+                cs.namespace(|| "reverse children"),
                 &result,
+                sibling,
+                lr_bit,
+            )?;
+            result = poseidon_hash(
+                cs.namespace(|| "calculate poseidon"),
+                vec![l, r],
+                &self.constants,
             )?;
         }
 
-        Ok(vec![result[0].clone()]) // root hash
+        Ok(vec![result]) // root hash
     }
 
+    /// Root computed natively. Panics if `index` is not below `2^siblings.len()`, an index the
+    /// decomposition of `synthesize` leaves unsatisfied.
     pub fn output(&self) -> Vec<F> {
+        assert!(
+            index_fits(self.index, self.siblings.len()),
+            "index {} out of range",
+            self.index
+        );
         let mut result = vec![self.value];
-        let path = usize_to_vec(self.index, self.siblings.len());
-        for (&lr_bit, &sibling) in path.iter().rev().zip(self.siblings.iter()) {
+        let path = TreePath::from_index(self.index, self.siblings.len());
+        for (lr_bit, &sibling) in path.lr_bits().zip(self.siblings.iter()) {
             let poseidon_circuit = InternalHashCircuit {
                 constants: self.constants.clone(),
                 sibling,
@@ -120,60 +200,194 @@ where
     }
 }
 
+/// As a step, proves that the leaf hash `value` is at `index` below the root held in the state,
+/// which it passes on unchanged, so a chain of these steps proves membership of every leaf in the
+/// same tree. The state follows `ZLayout::MERKLE_PROCESS`.
+///
+/// The inherent `synthesize` and `output` compute the root without a state; call the step versions
+/// through `StepCircuit`.
+impl<F> StepCircuit<F> for MerkleInclusionCircuit<F>
+where
+    F: PrimeField,
+{
+    fn arity(&self) -> usize {
+        ZLayout::MERKLE_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // root
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let result =
+            MerkleInclusionCircuit::synthesize(self, &mut cs.namespace(|| "calculate root"))?;
+
+        enforce_equal(cs, || "verify root", &result[0], &z[0]);
+
+        Ok(vec![z[0].clone()]) // root
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        assert_eq!(
+            MerkleInclusionCircuit::output(self)[0],
+            z[0],
+            "root mismatch"
+        );
+
+        vec![z[0]]
+    }
+}
+
+/// Step proving that the leaf at `index` is the empty leaf below the root held in the state,
+/// which it passes on unchanged, so an insert can show that its slot was free. The state follows
+/// `ZLayout::MERKLE_PROCESS`.
+///
+/// `empty_value` is the hash of the empty leaf and is fixed as a constant of the circuit.
 #[derive(Clone, Debug)]
-pub struct MerkleProcessCircuit<F: PrimeField> {
+pub struct MerkleNonInclusionCircuit<F: PrimeField> {
     pub constants: PoseidonConstants<F, U2>,
     pub siblings: Vec<F>,
     pub index: usize,
-    pub old_value: F,
-    pub new_value: F,
+    pub empty_value: F,
 }
 
-impl<F> StepCircuit<F> for MerkleProcessCircuit<F>
+impl<F> MerkleNonInclusionCircuit<F>
+where
+    F: PrimeField,
+{
+    /// Step proving that the leaf at `index` of `tree` is empty, or `None` if it is occupied.
+    pub fn from_tree<V: Leafable<F>, S: NodeStore<F>>(
+        tree: &MerkleTree<F, V, S>,
+        index: usize,
+    ) -> Option<Self> {
+        Some(Self {
+            constants: tree.poseidon_constants.as_ref().clone(),
+            siblings: tree.prove_empty(index)?,
+            index,
+            empty_value: tree.zero_hash(tree.height()),
+        })
+    }
+
+    /// Root implied by an empty leaf at `index`, which must match the incoming state.
+    pub fn root(&self) -> F {
+        compute_merkle_root(
+            &self.constants,
+            self.index,
+            self.empty_value,
+            &self.siblings,
+        )
+    }
+}
+
+impl<F> StepCircuit<F> for MerkleNonInclusionCircuit<F>
 where
     F: PrimeField,
 {
     fn arity(&self) -> usize {
-        1
+        ZLayout::MERKLE_PROCESS.arity()
     }
 
     fn synthesize<CS: ConstraintSystem<F>>(
         &self,
         cs: &mut CS,
-        z: &[AllocatedNum<F>], // old root
+        z: &[AllocatedNum<F>], // root
     ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
-        let old_poseidon_circuit = MerkleInclusionCircuit {
+        let value =
+            AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(self.empty_value))?;
+        enforce_equal_constant(cs, || "verify empty leaf", &value, self.empty_value);
+
+        let inclusion = MerkleInclusionCircuit {
             constants: self.constants.clone(),
             siblings: self.siblings.clone(),
             index: self.index,
-            value: self.old_value,
+            value: self.empty_value,
         };
+        let result =
+            inclusion.synthesize_with_value(&mut cs.namespace(|| "calculate root"), value)?;
 
-        let old_result =
-            old_poseidon_circuit.synthesize(&mut cs.namespace(|| "calculate old root"))?;
+        enforce_equal(cs, || "verify root", &result[0], &z[0]);
 
-        // Ensure `old_result[0] == old_root`
-        cs.enforce(
-            || "verify old root",
-            |lc| lc,
-            |lc| lc,
-            |lc| lc + old_result[0].get_variable() - z[0].get_variable(),
-        );
+        Ok(vec![z[0].clone()]) // root
+    }
 
-        let new_poseidon_circuit = MerkleInclusionCircuit {
+    fn output(&self, z: &[F]) -> Vec<F> {
+        assert_eq!(self.root(), z[0], "root mismatch");
+
+        vec![z[0]]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MerkleProcessCircuit<F: PrimeField> {
+    pub constants: PoseidonConstants<F, U2>,
+    pub siblings: Vec<F>,
+    pub index: usize,
+    pub old_value: F,
+    pub new_value: F,
+}
+
+impl<F> MerkleProcessCircuit<F>
+where
+    F: PrimeField,
+{
+    /// Updates the leaf at `index` and returns the step proving that update.
+    ///
+    /// Applying the output of `MerkleTree::diff` this way yields the steps between two tree states.
+    pub fn from_update<V: Leafable<F>, S: NodeStore<F>>(
+        tree: &mut MerkleTree<F, V, S>,
+        index: usize,
+        leaf: V,
+    ) -> Self {
+        let siblings = tree.prove(index);
+        let new_value = leaf.hash();
+        let old_value = tree.update(index, leaf).hash();
+
+        Self {
+            constants: tree.poseidon_constants.as_ref().clone(),
+            siblings,
+            index,
+            old_value,
+            new_value,
+        }
+    }
+
+    /// Step that rewrites the leaf at `index` with itself, leaving the root unchanged.
+    ///
+    /// Used to pad a block of updates to a fixed number of steps.
+    pub fn no_op<V: Leafable<F>, S: NodeStore<F>>(
+        tree: &MerkleTree<F, V, S>,
+        index: usize,
+    ) -> Self {
+        let value = tree.get_leaf(index).hash();
+
+        Self {
+            constants: tree.poseidon_constants.as_ref().clone(),
+            siblings: tree.prove(index),
+            index,
+            old_value: value,
+            new_value: value,
+        }
+    }
+
+    /// Root implied by the old value, which must match the incoming state. Panics if `index` is
+    /// out of range, as `MerkleInclusionCircuit::output` does.
+    pub fn old_root(&self) -> F {
+        let old_poseidon_circuit = MerkleInclusionCircuit {
             constants: self.constants.clone(),
             siblings: self.siblings.clone(),
             index: self.index,
-            value: self.new_value,
+            value: self.old_value,
         };
 
-        let new_result =
-            new_poseidon_circuit.synthesize(&mut cs.namespace(|| "calculate new root"))?;
-
-        Ok(vec![new_result[0].clone()]) // new root
+        old_poseidon_circuit.output()[0]
     }
 
-    fn output(&self, z: &[F]) -> Vec<F> {
+    /// Synthesizes the step and returns the allocated index and the old and new roots it computes.
+    fn synthesize_roots<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // old root
+    ) -> Result<ProcessRoots<F>, SynthesisError> {
         let old_poseidon_circuit = MerkleInclusionCircuit {
             constants: self.constants.clone(),
             siblings: self.siblings.clone(),
@@ -181,8 +395,76 @@ where
             value: self.old_value,
         };
 
-        let old_result = old_poseidon_circuit.output();
-        assert_eq!(old_result[0], z[0]);
+        // Both paths take one decomposition of the index and one set of siblings, so the new root
+        // is the old one with only the leaf replaced.
+        let index = AllocatedNum::alloc(cs.namespace(|| "allocate index"), || {
+            Ok(F::from(self.index as u64))
+        })?;
+        let bits = index_bits(
+            cs.namespace(|| "decompose index"),
+            &index,
+            self.siblings.len(),
+        )?;
+        let siblings = old_poseidon_circuit.alloc_siblings(cs)?;
+
+        let old_result = {
+            let mut cs = cs.namespace(|| "calculate old root");
+            let value =
+                AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(self.old_value))?;
+            old_poseidon_circuit.synthesize_with_bits(&mut cs, value, &bits, &siblings)?
+        };
+
+        enforce_equal(cs, || "verify old root", &old_result[0], &z[0]);
+
+        let new_poseidon_circuit = MerkleInclusionCircuit {
+            constants: self.constants.clone(),
+            siblings: self.siblings.clone(),
+            index: self.index,
+            value: self.new_value,
+        };
+
+        let new_result = {
+            let mut cs = cs.namespace(|| "calculate new root");
+            let value =
+                AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(self.new_value))?;
+            new_poseidon_circuit.synthesize_with_bits(&mut cs, value, &bits, &siblings)?
+        };
+
+        Ok(ProcessRoots {
+            index,
+            old_root: old_result[0].clone(),
+            new_root: new_result[0].clone(),
+        })
+    }
+}
+
+/// Variables of a synthesized `MerkleProcessCircuit`.
+struct ProcessRoots<F: PrimeField> {
+    index: AllocatedNum<F>,
+    old_root: AllocatedNum<F>,
+    new_root: AllocatedNum<F>,
+}
+
+impl<F> StepCircuit<F> for MerkleProcessCircuit<F>
+where
+    F: PrimeField,
+{
+    fn arity(&self) -> usize {
+        ZLayout::MERKLE_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // old root
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let roots = self.synthesize_roots(cs, z)?;
+
+        Ok(vec![roots.new_root]) // new root
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        assert_eq!(self.old_root(), z[0], "old root mismatch");
 
         let new_poseidon_circuit = MerkleInclusionCircuit {
             constants: self.constants.clone(),
@@ -196,3 +478,556 @@ where
         vec![new_result[0]]
     }
 }
+
+/// `N` leaf updates proven in one step, each `MerkleProcessCircuit` taking the root left by the
+/// previous one, so the folding cost is paid once per `N` updates. The state follows
+/// `ZLayout::MERKLE_PROCESS`.
+///
+/// Each update checks its old path against the incoming root and derives its new root from the
+/// same allocated siblings and index bits, so the root passed on is determined by the updates.
+///
+/// An ordered step also enforces that the indices of its updates are strictly increasing, so
+/// that a set of updates has a single valid step for external re-execution to reproduce. The
+/// padding after them is exempt, but it may only follow the updates and must leave the root
+/// unchanged.
+#[derive(Clone, Debug)]
+pub struct BatchMerkleProcessCircuit<F: PrimeField, const N: usize> {
+    pub updates: [MerkleProcessCircuit<F>; N],
+    /// Number of updates before the padding if the step is ordered. Ordered and unordered steps
+    /// have different shapes, so the steps of one proof have to agree on being ordered.
+    pub ordered: Option<usize>,
+}
+
+impl<F, const N: usize> BatchMerkleProcessCircuit<F, N>
+where
+    F: PrimeField,
+{
+    /// Applies `updates` to `tree` in order and returns the step proving them, padded with no-op
+    /// updates of the first leaf if there are fewer than `N`.
+    pub fn from_updates<V: Leafable<F>, S: NodeStore<F>>(
+        tree: &mut MerkleTree<F, V, S>,
+        updates: &[(usize, V)],
+    ) -> Self {
+        assert!(updates.len() <= N, "more than {N} updates in one step");
+        let mut steps: Vec<MerkleProcessCircuit<F>> = updates
+            .iter()
+            .map(|(index, leaf)| MerkleProcessCircuit::from_update(tree, *index, leaf.clone()))
+            .collect();
+        while steps.len() < N {
+            steps.push(MerkleProcessCircuit::no_op(tree, 0));
+        }
+
+        Self {
+            updates: steps.try_into().unwrap_or_else(|_| unreachable!()),
+            ordered: None,
+        }
+    }
+
+    /// Like `from_updates`, for an ordered step. Panics unless the indices of `updates` are
+    /// strictly increasing.
+    pub fn from_ordered_updates<V: Leafable<F>, S: NodeStore<F>>(
+        tree: &mut MerkleTree<F, V, S>,
+        updates: &[(usize, V)],
+    ) -> Self {
+        assert!(
+            updates.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "indices of ordered updates are not strictly increasing"
+        );
+
+        Self {
+            ordered: Some(updates.len()),
+            ..Self::from_updates(tree, updates)
+        }
+    }
+
+    /// Root before the first update, which must match the incoming state.
+    pub fn old_root(&self) -> F {
+        self.updates
+            .first()
+            .expect("a batch holds at least one update")
+            .old_root()
+    }
+}
+
+impl<F, const N: usize> StepCircuit<F> for BatchMerkleProcessCircuit<F, N>
+where
+    F: PrimeField,
+{
+    fn arity(&self) -> usize {
+        ZLayout::MERKLE_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // old root
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let mut root = z.to_vec();
+        // Index and whether it is not padding, of the previous update of an ordered step.
+        let mut previous: Option<(AllocatedNum<F>, AllocatedBit)> = None;
+        for (i, update) in self.updates.iter().enumerate() {
+            let mut cs = cs.namespace(|| format!("update {i}"));
+            let ProcessRoots {
+                index,
+                old_root,
+                new_root,
+            } = update.synthesize_roots(&mut cs, &root)?;
+            if let Some(count) = self.ordered {
+                let active =
+                    AllocatedBit::alloc(cs.namespace(|| "allocate active"), Some(i < count))?;
+                cs.enforce(
+                    || "padding leaves the root unchanged",
+                    |lc| lc + CS::one() - active.get_variable(),
+                    |lc| lc + new_root.get_variable() - old_root.get_variable(),
+                    |lc| lc,
+                );
+                if let Some((previous_index, previous_active)) = &previous {
+                    cs.enforce(
+                        || "padding is only followed by padding",
+                        |lc| lc + CS::one() - previous_active.get_variable(),
+                        |lc| lc + active.get_variable(),
+                        |lc| lc,
+                    );
+                    // The gap fits in the index bits only if the index is above the previous one,
+                    // as both indices do.
+                    let gap = AllocatedNum::alloc(cs.namespace(|| "allocate gap"), || {
+                        let index = index.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+                        let previous_index = previous_index
+                            .get_value()
+                            .ok_or(SynthesisError::AssignmentMissing)?;
+                        Ok(if i < count {
+                            index - previous_index - F::one()
+                        } else {
+                            F::zero()
+                        })
+                    })?;
+                    cs.enforce(
+                        || "verify gap",
+                        |lc| lc + active.get_variable(),
+                        |lc| lc + index.get_variable() - previous_index.get_variable() - CS::one(),
+                        |lc| lc + gap.get_variable(),
+                    );
+                    index_bits(
+                        cs.namespace(|| "decompose gap"),
+                        &gap,
+                        update.siblings.len(),
+                    )?;
+                }
+                previous = Some((index, active));
+            }
+            root = vec![new_root];
+        }
+
+        Ok(root) // new root
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        if let Some(count) = self.ordered {
+            assert!(
+                self.updates[..count]
+                    .windows(2)
+                    .all(|pair| pair[0].index < pair[1].index),
+                "indices of ordered updates are not strictly increasing"
+            );
+        }
+
+        self.updates
+            .iter()
+            .fold(z.to_vec(), |root, update| update.output(&root))
+    }
+}
+
+/// Update of the leaf at the index held in the state, which the step increments, so a chain of
+/// these steps writes consecutive slots whatever index the prover would choose. The state follows
+/// `ZLayout::SEQUENTIAL_PROCESS`.
+///
+/// The lr bits of the path are the decomposition of `next_index`, and both roots are computed
+/// from them and the same allocated siblings. A step past the last leaf is unsatisfiable.
+#[derive(Clone, Debug)]
+pub struct SequentialMerkleProcessCircuit<F: PrimeField> {
+    pub update: MerkleProcessCircuit<F>,
+}
+
+impl<F> SequentialMerkleProcessCircuit<F>
+where
+    F: PrimeField,
+{
+    /// Updates the leaf at `index`, which must be the `next_index` of the incoming state, and
+    /// returns the step proving that update.
+    pub fn from_update<V: Leafable<F>, S: NodeStore<F>>(
+        tree: &mut MerkleTree<F, V, S>,
+        index: usize,
+        leaf: V,
+    ) -> Self {
+        Self {
+            update: MerkleProcessCircuit::from_update(tree, index, leaf),
+        }
+    }
+
+    /// State before this step, the `z0` of a chain that starts with it.
+    pub fn z0(&self) -> Vec<F> {
+        ZLayout::SEQUENTIAL_PROCESS
+            .builder()
+            .set("root", self.update.old_root())
+            .set("next_index", F::from(self.update.index as u64))
+            .build()
+    }
+}
+
+impl<F> StepCircuit<F> for SequentialMerkleProcessCircuit<F>
+where
+    F: PrimeField,
+{
+    fn arity(&self) -> usize {
+        ZLayout::SEQUENTIAL_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // old root, next index
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let update = &self.update;
+        let inclusion = MerkleInclusionCircuit {
+            constants: update.constants.clone(),
+            siblings: update.siblings.clone(),
+            index: update.index,
+            value: update.old_value,
+        };
+        let bits = index_bits(
+            cs.namespace(|| "decompose index"),
+            &z[1],
+            update.siblings.len(),
+        )?;
+        let siblings = inclusion.alloc_siblings(cs)?;
+
+        let old_value = AllocatedNum::alloc(cs.namespace(|| "allocate old value"), || {
+            Ok(update.old_value)
+        })?;
+        let old_root = inclusion.synthesize_with_bits(
+            &mut cs.namespace(|| "calculate old root"),
+            old_value,
+            &bits,
+            &siblings,
+        )?;
+        enforce_equal(cs, || "verify old root", &old_root[0], &z[0]);
+
+        let new_value = AllocatedNum::alloc(cs.namespace(|| "allocate new value"), || {
+            Ok(update.new_value)
+        })?;
+        let new_root = inclusion.synthesize_with_bits(
+            &mut cs.namespace(|| "calculate new root"),
+            new_value,
+            &bits,
+            &siblings,
+        )?;
+
+        let next_index = AllocatedNum::alloc(cs.namespace(|| "increment index"), || {
+            z[1].get_value()
+                .map(|index| index + F::one())
+                .ok_or(SynthesisError::AssignmentMissing)
+        })?;
+        cs.enforce(
+            || "verify increment",
+            |lc| lc + CS::one(),
+            |lc| lc + next_index.get_variable() - z[1].get_variable() - CS::one(),
+            |lc| lc,
+        );
+
+        Ok(vec![new_root[0].clone(), next_index])
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        let layout = ZLayout::SEQUENTIAL_PROCESS;
+        let index = layout.read(z, "next_index");
+        assert_eq!(
+            F::from(self.update.index as u64),
+            index,
+            "update is not at the next index"
+        );
+        let root = self.update.output(&[layout.read(z, "root")])[0];
+
+        layout
+            .builder()
+            .set("root", root)
+            .set("next_index", index + F::one())
+            .build()
+    }
+}
+
+/// Values assigned to one level of a `MerkleInclusionCircuit`.
+#[derive(Clone, Debug)]
+pub struct LevelWitness<F: PrimeField> {
+    /// Namespace of the level, as in the names of its constraints.
+    pub namespace: String,
+    pub child: F,
+    pub sibling: F,
+    pub lr_bit: bool,
+    /// Poseidon hash of the children, the child of the next level.
+    pub output: F,
+}
+
+/// Assignments of a `MerkleProcessCircuit` step, from `MerkleProcessCircuit::witness`.
+#[derive(Clone, Debug)]
+pub struct StepWitness<F: PrimeField> {
+    /// The root in `z`, which the old path must reach.
+    pub old_root: F,
+    /// Levels of the old path from the leaf upwards.
+    pub old_levels: Vec<LevelWitness<F>>,
+    /// Levels of the new path from the leaf upwards.
+    pub new_levels: Vec<LevelWitness<F>>,
+    /// First unsatisfied constraint, or `None` if the step is valid for `old_root`.
+    pub unsatisfied: Option<String>,
+}
+
+impl<F: PrimeField> MerkleProcessCircuit<F> {
+    /// Synthesizes the step for `old_root` in a test constraint system and returns the value
+    /// assigned to every level of both paths with the first violated constraint.
+    ///
+    /// Comparing `old_levels` with the nodes of the tree shows the level at which the witness
+    /// diverges when the step does not reach `old_root`.
+    pub fn witness(&self, old_root: F) -> Result<StepWitness<F>, SynthesisError> {
+        let mut cs = TestConstraintSystem::<F>::new();
+        let z = AllocatedNum::alloc(cs.namespace(|| "old root"), || Ok(old_root))?;
+        let roots = self.synthesize_roots(&mut cs, &[z])?;
+        let old_levels = self.levels(&mut cs, "calculate old root", &roots.old_root)?;
+        let new_levels = self.levels(&mut cs, "calculate new root", &roots.new_root)?;
+
+        Ok(StepWitness {
+            old_root,
+            old_levels,
+            new_levels,
+            unsatisfied: cs.which_is_unsatisfied().map(str::to_string),
+        })
+    }
+
+    /// Reads the levels of the path synthesized under `namespace` back from `cs`. The output of
+    /// a level is the child of the next one, reordered by its lr bit, and `root` for the last.
+    fn levels(
+        &self,
+        cs: &mut TestConstraintSystem<F>,
+        namespace: &str,
+        root: &AllocatedNum<F>,
+    ) -> Result<Vec<LevelWitness<F>>, SynthesisError> {
+        let height = self.siblings.len();
+        let lr_bits: Vec<bool> = (0..height)
+            .map(|i| {
+                // Bits above the 64th are constants, which `index_bits` does not allocate.
+                i < u64::BITS as usize
+                    && cs.get(&format!("decompose index/allocate index bit {i}/boolean"))
+                        == F::one()
+            })
+            .collect();
+        let child = |cs: &mut TestConstraintSystem<F>, i: usize| {
+            if i == 0 {
+                return cs.get(&format!("{namespace}/allocate value/num"));
+            }
+            let result = if lr_bits[i] { 2 } else { 1 };
+            cs.get(&format!(
+                "{namespace}/calculate parent hash {i}/reverse children/conditional reversal result {result}/num"
+            ))
+        };
+
+        (0..height)
+            .map(|i| {
+                let output = if i + 1 < height {
+                    child(cs, i + 1)
+                } else {
+                    root.get_value().ok_or(SynthesisError::AssignmentMissing)?
+                };
+
+                Ok(LevelWitness {
+                    namespace: format!("{namespace}/calculate parent hash {i}"),
+                    child: child(cs, i),
+                    sibling: cs.get(&format!("allocate sibling {i}/num")),
+                    lr_bit: lr_bits[i],
+                    output,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Subtree replaced by an empty one in a [`RangeClearCircuit`].
+#[derive(Clone, Debug)]
+pub struct ClearedBlock<F: PrimeField> {
+    /// Height of the subtree, zero for a single leaf.
+    pub level: usize,
+    pub index: usize,
+    /// Root of the subtree before it is cleared.
+    pub old_hash: F,
+    /// Siblings from the subtree root upwards, after the previous blocks are cleared.
+    pub siblings: Vec<F>,
+}
+
+/// Step clearing every leaf in `range`, one aligned subtree after the other.
+///
+/// The range is part of the circuit: block positions are constants, so the step cannot clear
+/// anything else, and folding it repeatedly only works for steps clearing the same range.
+#[derive(Clone, Debug)]
+pub struct RangeClearCircuit<F: PrimeField> {
+    pub constants: PoseidonConstants<F, U2>,
+    pub range: Range<usize>,
+    /// One block per entry of `aligned_blocks(height, range)`.
+    pub blocks: Vec<ClearedBlock<F>>,
+    /// Hashes of empty subtrees, indexed by subtree height.
+    pub zero_hashes: Vec<F>,
+}
+
+impl<F: PrimeField> RangeClearCircuit<F> {
+    /// Clears `range` in `tree` as a single version and returns the step proving it.
+    pub fn from_clear<V: Leafable<F>, S: NodeStore<F>>(
+        tree: &mut MerkleTree<F, V, S>,
+        range: Range<usize>,
+    ) -> Self {
+        let height = tree.height();
+        let mut zero_hashes = tree.zero_hashes.clone();
+        zero_hashes.reverse();
+
+        // Clear block by block in memory to record the siblings each block sees, then clear the
+        // whole range in the tree at once.
+        let mut cleared: HashMap<TreePath, F> = HashMap::new();
+        let node = |cleared: &HashMap<TreePath, F>, path: &TreePath| {
+            cleared
+                .get(path)
+                .copied()
+                .unwrap_or_else(|| tree.get_node_hash(path))
+        };
+        let blocks = aligned_blocks(height, range.clone())
+            .into_iter()
+            .map(|(level, index)| {
+                let mut path = TreePath::from_index(index, height - level);
+                let old_hash = node(&cleared, &path);
+                let mut siblings = vec![];
+                let mut h = zero_hashes[level];
+                cleared.insert(path.clone(), h);
+                while !path.is_root() {
+                    let sibling = node(&cleared, &path.sibling());
+                    siblings.push(sibling);
+                    let (l, r) = if path.pop() == Some(true) {
+                        (sibling, h)
+                    } else {
+                        (h, sibling)
+                    };
+                    h = hash_pair(&tree.poseidon_constants, l, r);
+                    cleared.insert(path.clone(), h);
+                }
+
+                ClearedBlock {
+                    level,
+                    index,
+                    old_hash,
+                    siblings,
+                }
+            })
+            .collect();
+        tree.clear_range(range.clone());
+
+        Self {
+            constants: tree.poseidon_constants.as_ref().clone(),
+            range,
+            blocks,
+            zero_hashes,
+        }
+    }
+
+    /// Root of the path from `hash` at the position of `block`, with the position fixed in the
+    /// circuit and `siblings` allocated once for the old and new roots of the block.
+    fn synthesize_block_root<CS: ConstraintSystem<F>>(
+        &self,
+        mut cs: CS,
+        block: &ClearedBlock<F>,
+        hash: AllocatedNum<F>,
+        siblings: &[AllocatedNum<F>],
+    ) -> Result<AllocatedNum<F>, SynthesisError> {
+        let path = TreePath::from_index(block.index, block.siblings.len());
+        let mut h = hash;
+        for (i, (lr_bit, sibling)) in path.lr_bits().zip(siblings).enumerate() {
+            let mut cs = cs.namespace(|| format!("calculate parent hash {i}"));
+            let (l, r) = AllocatedNum::conditionally_reverse(
+                cs.namespace(|| "reverse children"),
+                &h,
+                sibling,
+                &Boolean::constant(lr_bit),
+            )?;
+            h = poseidon_hash(
+                cs.namespace(|| "calculate poseidon"),
+                vec![l, r],
+                &self.constants,
+            )?;
+        }
+
+        Ok(h)
+    }
+}
+
+impl<F: PrimeField> StepCircuit<F> for RangeClearCircuit<F> {
+    fn arity(&self) -> usize {
+        ZLayout::MERKLE_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // old root
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let mut root = z[0].clone();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut cs = cs.namespace(|| format!("clear block {i}"));
+            let siblings = block
+                .siblings
+                .iter()
+                .enumerate()
+                .map(|(j, &sibling)| {
+                    AllocatedNum::alloc(cs.namespace(|| format!("allocate sibling {j}")), || {
+                        Ok(sibling)
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let old = AllocatedNum::alloc(cs.namespace(|| "allocate old subtree"), || {
+                Ok(block.old_hash)
+            })?;
+            let old_root = self.synthesize_block_root(
+                cs.namespace(|| "calculate old root"),
+                block,
+                old,
+                &siblings,
+            )?;
+            enforce_equal(&mut cs, || "verify old root", &old_root, &root);
+
+            let zero_hash = self.zero_hashes[block.level];
+            let zero =
+                AllocatedNum::alloc(cs.namespace(|| "allocate empty subtree"), || Ok(zero_hash))?;
+            enforce_equal_constant(&mut cs, || "verify empty subtree", &zero, zero_hash);
+            root = self.synthesize_block_root(
+                cs.namespace(|| "calculate new root"),
+                block,
+                zero,
+                &siblings,
+            )?;
+        }
+
+        Ok(vec![root]) // new root
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        let mut root = z[0];
+        for block in &self.blocks {
+            let old_root = compute_merkle_root(
+                &self.constants,
+                block.index,
+                block.old_hash,
+                &block.siblings,
+            );
+            assert_eq!(old_root, root, "old root mismatch");
+            root = compute_merkle_root(
+                &self.constants,
+                block.index,
+                self.zero_hashes[block.level],
+                &block.siblings,
+            );
+        }
+
+        vec![root]
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use ff::PrimeField;
+
+use super::tree::{Leafable, MerkleTree};
+
+/// Shared handle to a tree, serving concurrent reads while writes are applied one at a time.
+///
+/// Clones refer to the same tree. Readers see either all or none of an update.
+#[derive(Debug)]
+pub struct ConcurrentMerkleTree<F: PrimeField, V: Leafable<F>> {
+    inner: Arc<RwLock<MerkleTree<F, V>>>,
+}
+
+impl<F: PrimeField, V: Leafable<F>> Clone for ConcurrentMerkleTree<F, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F>> From<MerkleTree<F, V>> for ConcurrentMerkleTree<F, V> {
+    fn from(tree: MerkleTree<F, V>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(tree)),
+        }
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F>> ConcurrentMerkleTree<F, V> {
+    pub fn new(height: usize) -> Self {
+        MerkleTree::new(height).into()
+    }
+
+    /// Locks the tree for reading, so that several calls see the same state.
+    pub fn read(&self) -> RwLockReadGuard<'_, MerkleTree<F, V>> {
+        self.inner.read().unwrap()
+    }
+
+    /// Locks the tree for writing, blocking readers until the guard is dropped.
+    pub fn write(&self) -> RwLockWriteGuard<'_, MerkleTree<F, V>> {
+        self.inner.write().unwrap()
+    }
+
+    pub fn get_root(&self) -> F {
+        self.read().get_root()
+    }
+
+    pub fn get_leaf(&self, index: usize) -> V {
+        self.read().get_leaf(index)
+    }
+
+    /// Root and siblings of the leaf at `index`, taken from the same state.
+    pub fn prove(&self, index: usize) -> (F, Vec<F>) {
+        let tree = self.read();
+        (tree.get_root(), tree.prove(index))
+    }
+
+    /// Sets the leaf at `index` and returns the leaf it replaced, read under the same lock.
+    pub fn update(&self, index: usize, leaf: V) -> V {
+        self.write().update(index, leaf)
+    }
+
+    pub fn update_batch(&self, updates: &[(usize, V)]) {
+        self.write().update_batch(updates)
+    }
+
+    pub fn remove(&self, index: usize) {
+        self.write().remove(index)
+    }
+}
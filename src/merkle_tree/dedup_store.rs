@@ -0,0 +1,540 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use ff::PrimeField;
+
+use super::{
+    node_store::{assert_own_hash, own_hash_leaves, NodeStore, PendingWrites},
+    path::TreePath,
+    tree::Leafable,
+};
+
+/// Hashable key of a node of a pool: its depth and the canonical representation of its hash.
+#[derive(Clone, Copy)]
+struct NodeKey<F: PrimeField>(usize, F::Repr);
+
+impl<F: PrimeField> NodeKey<F> {
+    fn new(depth: usize, h: &F) -> Self {
+        Self(depth, h.to_repr())
+    }
+}
+
+impl<F: PrimeField> fmt::Debug for NodeKey<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NodeKey({}, 0x", self.0)?;
+        for byte in self.1.as_ref().iter().rev() {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl<F: PrimeField> PartialEq for NodeKey<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 && self.1.as_ref() == other.1.as_ref()
+    }
+}
+
+impl<F: PrimeField> Eq for NodeKey<F> {}
+
+impl<F: PrimeField> Hash for NodeKey<F> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+        self.1.as_ref().hash(state)
+    }
+}
+
+/// Node of a `DedupNodeStore` pool, with the hash of each child or `None` if it is empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DedupNode<F> {
+    pub children: [Option<F>; 2],
+    /// Number of nodes and store roots pointing to it.
+    pub refs: usize,
+}
+
+/// Storage of the pool of a `DedupNodeStore`: every node keyed by its depth and its own hash, and
+/// the root of every named store.
+///
+/// A node is only shared with nodes of the same depth, so a leaf whose value happens to equal the
+/// hash of an internal node is never taken for that node.
+///
+/// `MemoryPool` keeps them in memory. The persistent node stores also implement it, keeping the
+/// pool next to, and apart from, the nodes they store by path.
+pub trait DedupBackend<F: PrimeField> {
+    fn get_node(&self, depth: usize, hash: &F) -> Option<DedupNode<F>>;
+
+    /// Root hash and number of nodes of the store named `name` as of its last commit, or `None`
+    /// if it is empty or was never committed.
+    fn get_root(&self, name: &str) -> Option<(F, usize)>;
+
+    /// Number of nodes in the pool.
+    fn num_nodes(&self) -> usize;
+
+    /// Sets each node of `nodes`, given by depth and hash, or removes it for `None`, and sets the root of the store named
+    /// in `root` likewise. A persistent backend applies them atomically, so its reference counts
+    /// always match the nodes and roots it holds.
+    fn write(
+        &mut self,
+        nodes: Vec<(usize, F, Option<DedupNode<F>>)>,
+        root: Option<(&str, Option<(F, usize)>)>,
+    );
+}
+
+/// Default pool of a `DedupNodeStore`, kept in memory.
+#[derive(Debug)]
+pub struct MemoryPool<F: PrimeField> {
+    nodes: HashMap<NodeKey<F>, DedupNode<F>>,
+    roots: HashMap<String, (F, usize)>,
+}
+
+impl<F: PrimeField> Default for MemoryPool<F> {
+    fn default() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            roots: HashMap::new(),
+        }
+    }
+}
+
+impl<F: PrimeField> DedupBackend<F> for MemoryPool<F> {
+    fn get_node(&self, depth: usize, hash: &F) -> Option<DedupNode<F>> {
+        self.nodes.get(&NodeKey::new(depth, hash)).copied()
+    }
+
+    fn get_root(&self, name: &str) -> Option<(F, usize)> {
+        self.roots.get(name).copied()
+    }
+
+    fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn write(
+        &mut self,
+        nodes: Vec<(usize, F, Option<DedupNode<F>>)>,
+        root: Option<(&str, Option<(F, usize)>)>,
+    ) {
+        for (depth, h, node) in nodes {
+            match node {
+                Some(node) => self.nodes.insert(NodeKey::new(depth, &h), node),
+                None => self.nodes.remove(&NodeKey::new(depth, &h)),
+            };
+        }
+        match root {
+            Some((name, Some(root))) => {
+                self.roots.insert(name.to_string(), root);
+            }
+            Some((name, None)) => {
+                self.roots.remove(name);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Depth, hash and new value of a node written to a pool, `None` to remove it.
+type PoolWrite<F> = (usize, F, Option<DedupNode<F>>);
+
+/// Changes to the nodes of a pool, read before the pool itself and written to it at once.
+struct PoolWrites<'a, F: PrimeField, B> {
+    backend: &'a B,
+    nodes: HashMap<NodeKey<F>, PoolWrite<F>>,
+}
+
+impl<'a, F: PrimeField, B: DedupBackend<F>> PoolWrites<'a, F, B> {
+    fn new(backend: &'a B) -> Self {
+        Self {
+            backend,
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn get(&self, depth: usize, h: &F) -> Option<DedupNode<F>> {
+        match self.nodes.get(&NodeKey::new(depth, h)) {
+            Some((_, _, node)) => *node,
+            None => self.backend.get_node(depth, h),
+        }
+    }
+
+    fn get_children(&self, depth: usize, h: &F) -> Option<[Option<F>; 2]> {
+        self.get(depth, h).map(|node| node.children)
+    }
+
+    fn put(&mut self, depth: usize, h: F, node: Option<DedupNode<F>>) {
+        self.nodes.insert(NodeKey::new(depth, &h), (depth, h, node));
+    }
+
+    fn retain(&mut self, depth: usize, h: &F) {
+        if let Some(mut node) = self.get(depth, h) {
+            node.refs += 1;
+            self.put(depth, *h, Some(node));
+        }
+    }
+
+    /// Drops a reference on the node `h` at `depth`, and the node itself along with its
+    /// unreachable descendants once no reference is left.
+    fn release(&mut self, depth: usize, h: &F) {
+        let mut stack = vec![(depth, *h)];
+        while let Some((depth, h)) = stack.pop() {
+            let Some(mut node) = self.get(depth, &h) else {
+                continue;
+            };
+            node.refs -= 1;
+            if node.refs == 0 {
+                self.put(depth, h, None);
+                stack.extend(
+                    node.children
+                        .into_iter()
+                        .flatten()
+                        .map(|child| (depth + 1, child)),
+                );
+            } else {
+                self.put(depth, h, Some(node));
+            }
+        }
+    }
+
+    fn into_writes(self) -> Vec<PoolWrite<F>> {
+        self.nodes.into_values().collect()
+    }
+}
+
+/// Node store that keys every internal node by its depth and its own hash, so identical subtrees
+/// are kept once however many versions and trees contain them. The pool is the only copy of the nodes, kept in
+/// memory by default or in a persistent store given to `open`.
+///
+/// The store holds a root and finds the node at a path by following the children from it. Writes
+/// are buffered until `commit`, which stores the new nodes bottom-up and moves the root to the new
+/// version, in a single write of the pool. Clones share the pool and keep their own root, so
+/// cloning the tree before an update keeps both versions for the cost of the nodes that differ;
+/// `share` and `share_named` start another tree on the same pool. Nodes are reference counted and
+/// dropped when no store root reaches them.
+///
+/// A named store keeps its root in the pool, written with its nodes, so it outlives the store and
+/// `open` or `share_named` with the same name resume from it; at most one store of a pool may use
+/// a name at a time. Other stores, clones included, release their root when dropped. Their
+/// references are written to the pool all the same, so on a persistent pool the nodes of a store
+/// that is never dropped stay there.
+///
+/// The pool holds hashes alone, so the leaves of a tree reopened from it are read back from their
+/// hashes; only trees whose leaves are their own hash, as field elements are, can be committed.
+///
+/// Only nodes reachable from the root through stored nodes can be kept, which every consistent
+/// tree satisfies; stray nodes below empty subtrees are dropped on `commit`. Lookups take one step
+/// per level.
+#[derive(Debug)]
+pub struct DedupNodeStore<F: PrimeField, B: DedupBackend<F> = MemoryPool<F>> {
+    pool: Arc<Mutex<B>>,
+    /// Name the root is kept under in the pool, `None` to release it on drop.
+    name: Option<String>,
+    /// Hash of the root as of the last commit, `None` for an empty tree.
+    root: Option<F>,
+    len: usize,
+    pending: PendingWrites<F>,
+}
+
+impl<F: PrimeField> Default for DedupNodeStore<F> {
+    fn default() -> Self {
+        Self::on_pool(Arc::new(Mutex::new(MemoryPool::default())), None)
+    }
+}
+
+impl<F: PrimeField> DedupNodeStore<F> {
+    /// Empty store on a new in-memory pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<F: PrimeField, B: DedupBackend<F>> DedupNodeStore<F, B> {
+    /// Store named `name` on a new pool kept in `backend`, at the root it last committed there.
+    /// Reopen its tree with `MerkleTree::open_with_store`.
+    pub fn open(backend: B, name: &str) -> Self {
+        Self::on_pool(Arc::new(Mutex::new(backend)), Some(name.to_string()))
+    }
+
+    /// Empty store on the pool of this one, for another tree.
+    pub fn share(&self) -> Self {
+        Self::on_pool(self.pool.clone(), None)
+    }
+
+    /// Store named `name` on the pool of this one, at the root it last committed.
+    pub fn share_named(&self, name: &str) -> Self {
+        Self::on_pool(self.pool.clone(), Some(name.to_string()))
+    }
+
+    fn on_pool(pool: Arc<Mutex<B>>, name: Option<String>) -> Self {
+        let (root, len) = match &name {
+            Some(name) => pool.lock().unwrap().get_root(name).unzip(),
+            None => (None, None),
+        };
+
+        Self {
+            pool,
+            name,
+            root,
+            len: len.unwrap_or(0),
+            pending: PendingWrites::default(),
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Number of distinct internal nodes in the pool, across every store sharing it.
+    pub fn pool_len(&self) -> usize {
+        self.pool.lock().unwrap().num_nodes()
+    }
+
+    /// Hash of the node at `path` as of the last commit.
+    fn get_committed(&self, pool: &PoolWrites<F, B>, path: &TreePath) -> Option<F> {
+        let mut h = self.root?;
+        for (depth, &bit) in path.bits().iter().enumerate() {
+            h = pool.get_children(depth, &h)?[bit as usize]?;
+        }
+        Some(h)
+    }
+
+    fn get_in(&self, pool: &PoolWrites<F, B>, path: &TreePath) -> Option<F> {
+        match self.pending.get(path) {
+            Some(h) => h,
+            None => self.get_committed(pool, path),
+        }
+    }
+}
+
+impl<F: PrimeField, B: DedupBackend<F>> Clone for DedupNodeStore<F, B> {
+    /// The clone has no name and holds its own reference on the root.
+    fn clone(&self) -> Self {
+        if let Some(root) = &self.root {
+            let mut pool = self.pool.lock().unwrap();
+            let mut writes = PoolWrites::new(&*pool);
+            writes.retain(0, root);
+            let nodes = writes.into_writes();
+            pool.write(nodes, None);
+        }
+
+        Self {
+            pool: self.pool.clone(),
+            name: None,
+            root: self.root,
+            len: self.len,
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<F: PrimeField, B: DedupBackend<F>> Drop for DedupNodeStore<F, B> {
+    fn drop(&mut self) {
+        if self.name.is_some() {
+            return;
+        }
+        if let Some(root) = &self.root {
+            // A poisoned pool is left as is rather than panicking while dropping.
+            if let Ok(mut pool) = self.pool.lock() {
+                let mut writes = PoolWrites::new(&*pool);
+                writes.release(0, root);
+                let nodes = writes.into_writes();
+                pool.write(nodes, None);
+            }
+        }
+    }
+}
+
+impl<F: PrimeField, B: DedupBackend<F>> NodeStore<F> for DedupNodeStore<F, B> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        let pool = self.pool.lock().unwrap();
+        self.get_in(&PoolWrites::new(&*pool), path)
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        let old = self.get(&path);
+        let len = self.len() + old.is_none() as usize;
+        self.pending.write(path, Some(hash), len);
+
+        old
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        let old = self.get(path)?;
+        let len = self.len() - 1;
+        self.pending.write(path.clone(), None, len);
+
+        Some(old)
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len.unwrap_or(self.len)
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+        assert_own_hash(writes);
+    }
+
+    fn leaves<V: Leafable<F>>(&self, height: usize) -> Vec<(TreePath, V)> {
+        own_hash_leaves(self.iter(), height)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        let pool = self.pool.lock().unwrap();
+        let nodes = PoolWrites::new(&*pool);
+        let mut stored = vec![];
+        let mut stack: Vec<(TreePath, F)> = self
+            .root
+            .map(|h| (TreePath::root(), h))
+            .into_iter()
+            .collect();
+        while let Some((path, h)) = stack.pop() {
+            // A leaf has no node at its depth, so the walk ends at the leaves.
+            if let Some(children) = nodes.get_children(path.depth(), &h) {
+                for (bit, child) in children.into_iter().enumerate() {
+                    if let Some(child) = child {
+                        stack.push((path.child(bit == 1), child));
+                    }
+                }
+            }
+            stored.push((path, h));
+        }
+        drop(pool);
+
+        Box::new(self.pending.overlay(stored.into_iter()))
+    }
+
+    fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let mut pool = self.pool.lock().unwrap();
+        let mut writes = PoolWrites::new(&*pool);
+        let mut written: Vec<(TreePath, F)> = self
+            .pending
+            .nodes
+            .iter()
+            .filter_map(|(path, h)| h.map(|h| (path.clone(), h)))
+            .collect();
+        written.sort_by_key(|(path, _)| std::cmp::Reverse(path.depth()));
+
+        // Children are stored before their parents, which take a reference on them.
+        let mut created = vec![];
+        for (path, h) in &written {
+            let depth = path.depth();
+            if writes.get(depth, h).is_some() {
+                continue;
+            }
+            let children = [
+                self.get_in(&writes, &path.child(false)),
+                self.get_in(&writes, &path.child(true)),
+            ];
+            if children == [None, None] {
+                // A leaf, whose hash needs no node.
+                continue;
+            }
+            for child in children.iter().flatten() {
+                writes.retain(depth + 1, child);
+            }
+            writes.put(depth, *h, Some(DedupNode { children, refs: 0 }));
+            created.push((depth, *h));
+        }
+
+        let root = self.get_in(&writes, &TreePath::root());
+        if let Some(root) = &root {
+            writes.retain(0, root);
+        }
+        if let Some(old_root) = &self.root {
+            writes.release(0, old_root);
+        }
+        for (depth, h) in created {
+            if writes.get(depth, &h).is_some_and(|node| node.refs == 0) {
+                writes.retain(depth, &h);
+                writes.release(depth, &h);
+            }
+        }
+        let len = self.pending.len.unwrap_or(self.len);
+        let nodes = writes.into_writes();
+        let record = self
+            .name
+            .as_deref()
+            .map(|name| (name, root.map(|root| (root, len))));
+        pool.write(nodes, record);
+        drop(pool);
+
+        self.root = root;
+        self.len = len;
+        self.pending = PendingWrites::default();
+    }
+}
+
+/// Depth of a node as a big-endian `u32` followed by its hash representation, its key in a
+/// persistent pool.
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn encode_dedup_key<F: PrimeField>(depth: usize, h: &F) -> Vec<u8> {
+    let depth = u32::try_from(depth).expect("node is too deep to store");
+    let mut key = depth.to_be_bytes().to_vec();
+    key.extend_from_slice(h.to_repr().as_ref());
+    key
+}
+
+/// Flags of the present children, the representation of each of them and the reference count as
+/// a big-endian `u64`, the value of a node in a persistent pool keyed by `encode_dedup_key`.
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn encode_dedup_node<F: PrimeField>(node: &DedupNode<F>) -> Vec<u8> {
+    let mut value =
+        vec![node.children[0].is_some() as u8 | (node.children[1].is_some() as u8) << 1];
+    for child in node.children.iter().flatten() {
+        value.extend_from_slice(child.to_repr().as_ref());
+    }
+    value.extend_from_slice(&(node.refs as u64).to_be_bytes());
+    value
+}
+
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn decode_dedup_node<F: PrimeField>(value: &[u8]) -> DedupNode<F> {
+    use super::node_store::decode_node;
+
+    let size = F::Repr::default().as_ref().len();
+    let (flags, mut rest) = value.split_first().expect("corrupt pool node");
+    let mut children = [None; 2];
+    for (bit, child) in children.iter_mut().enumerate() {
+        if flags >> bit & 1 == 1 {
+            *child = Some(decode_node(&rest[..size]));
+            rest = &rest[size..];
+        }
+    }
+    let refs = u64::from_be_bytes(rest.try_into().expect("corrupt pool node"));
+
+    DedupNode {
+        children,
+        refs: refs as usize,
+    }
+}
+
+/// Root representation followed by the number of nodes as a big-endian `u64`, the value of the
+/// root of a named store in a persistent pool.
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn encode_dedup_root<F: PrimeField>((root, len): (F, usize)) -> Vec<u8> {
+    let mut value = root.to_repr().as_ref().to_vec();
+    value.extend_from_slice(&(len as u64).to_be_bytes());
+    value
+}
+
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn decode_dedup_root<F: PrimeField>(value: &[u8]) -> (F, usize) {
+    use super::node_store::decode_node;
+
+    let (root, len) = value.split_at(value.len() - 8);
+    let len = u64::from_be_bytes(len.try_into().unwrap());
+    (decode_node(root), len as usize)
+}
@@ -0,0 +1,112 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+
+use super::{
+    tree::{hash_pair, Leafable},
+    zero_hashes::zero_hash_ladder,
+};
+
+/// Computes the root of a tree from its leaves in index order, keeping only the right frontier.
+///
+/// Memory is proportional to the height rather than the number of leaves, so the root of a dataset
+/// too large for a `MerkleTree` can be computed as it is read. Leaves after the last pushed one are empty.
+#[derive(Clone, Debug)]
+pub struct FrontierBuilder<F: PrimeField, V: Leafable<F>> {
+    poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+    height: usize,
+    /// `frontier[level]` is the root of the last complete subtree of `2^level` leaves
+    /// if it still awaits its right sibling.
+    frontier: Vec<Option<F>>,
+    /// Hashes of empty subtrees, indexed by subtree height.
+    zero_hashes: Vec<F>,
+    len: u64,
+    _leaf: PhantomData<V>,
+}
+
+impl<F: PrimeField, V: Leafable<F>> FrontierBuilder<F, V> {
+    pub fn new(height: usize) -> Self {
+        Self::new_with_constants(height, Arc::new(PoseidonConstants::new()))
+    }
+
+    pub fn new_with_constants(
+        height: usize,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+    ) -> Self {
+        assert!(height < u64::BITS as usize, "tree is too large to fill");
+        let zero_hashes = zero_hash_ladder(&poseidon_constants, V::empty_leaf().hash(), height);
+
+        Self {
+            poseidon_constants,
+            height,
+            frontier: vec![None; height + 1],
+            zero_hashes,
+            len: 0,
+            _leaf: PhantomData,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of leaves pushed so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends the next leaf.
+    pub fn push(&mut self, leaf: &V) {
+        assert!(self.len >> self.height == 0, "tree is full");
+
+        let mut h = leaf.hash();
+        let mut level = 0;
+        while let Some(left) = self.frontier[level].take() {
+            h = hash_pair(&self.poseidon_constants, left, h);
+            level += 1;
+        }
+        self.frontier[level] = Some(h);
+        self.len += 1;
+    }
+
+    /// Root of the tree holding the pushed leaves followed by empty ones.
+    pub fn root(&self) -> F {
+        if let Some(root) = self.frontier[self.height] {
+            return root;
+        }
+
+        // Root of the partly filled subtree at `level`, or `None` while it is empty.
+        let mut partial: Option<F> = None;
+        for level in 0..self.height {
+            partial = match (self.frontier[level], partial) {
+                (Some(left), partial) => Some(hash_pair(
+                    &self.poseidon_constants,
+                    left,
+                    partial.unwrap_or(self.zero_hashes[level]),
+                )),
+                (None, Some(left)) => Some(hash_pair(
+                    &self.poseidon_constants,
+                    left,
+                    self.zero_hashes[level],
+                )),
+                (None, None) => None,
+            };
+        }
+
+        partial.unwrap_or(self.zero_hashes[self.height])
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F>> Extend<V> for FrontierBuilder<F, V> {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, leaves: I) {
+        for leaf in leaves {
+            self.push(&leaf);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use bellperson::{
+    gadgets::{
+        boolean::{AllocatedBit, Boolean},
+        num::AllocatedNum,
+    },
+    ConstraintSystem, LinearCombination, SynthesisError,
+};
+use ff::PrimeField;
+
+/// Enforces `a == b` with the constraint `1 * (a - b) = 0`, named by `annotation`.
+pub fn enforce_equal<F, CS, A, AR>(
+    cs: &mut CS,
+    annotation: A,
+    a: &AllocatedNum<F>,
+    b: &AllocatedNum<F>,
+) where
+    F: PrimeField,
+    CS: ConstraintSystem<F>,
+    A: FnOnce() -> AR,
+    AR: Into<String>,
+{
+    cs.enforce(
+        annotation,
+        |lc| lc + CS::one(),
+        |lc| lc + a.get_variable() - b.get_variable(),
+        |lc| lc,
+    );
+}
+
+/// Enforces `a == value` with the constraint `1 * (a - value) = 0`, named by `annotation`.
+pub fn enforce_equal_constant<F, CS, A, AR>(
+    cs: &mut CS,
+    annotation: A,
+    a: &AllocatedNum<F>,
+    value: F,
+) where
+    F: PrimeField,
+    CS: ConstraintSystem<F>,
+    A: FnOnce() -> AR,
+    AR: Into<String>,
+{
+    cs.enforce(
+        annotation,
+        |lc| lc + CS::one(),
+        |lc| lc + a.get_variable() - (value, CS::one()),
+        |lc| lc,
+    );
+}
+
+/// Bits of `index`, least significant first, which are the lr bits of its path from the leaf
+/// level up, constrained to pack to `index`.
+///
+/// As in `TreePath::from_index`, bits above the 64th are constant zeros. At most 64 bits are
+/// packed, fewer than `F::CAPACITY`, so the packing cannot wrap around the modulus and the
+/// decomposition is canonical: an `index` of `2^num_bits` or more leaves it unsatisfied.
+pub fn index_bits<F, CS>(
+    mut cs: CS,
+    index: &AllocatedNum<F>,
+    num_bits: usize,
+) -> Result<Vec<Boolean>, SynthesisError>
+where
+    F: PrimeField,
+    CS: ConstraintSystem<F>,
+{
+    let repr = index.get_value().map(|index| index.to_repr());
+    let allocated = (0..num_bits.min(u64::BITS as usize))
+        .map(|i| {
+            let bit = repr
+                .as_ref()
+                .map(|repr| (repr.as_ref()[i / 8] >> (i % 8)) & 1 == 1);
+            AllocatedBit::alloc(cs.namespace(|| format!("allocate index bit {i}")), bit)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut packed = LinearCombination::zero();
+    let mut coeff = F::one();
+    for bit in &allocated {
+        packed = packed + (coeff, bit.get_variable());
+        coeff = coeff.double();
+    }
+    cs.enforce(
+        || "verify index",
+        |lc| lc + CS::one(),
+        |lc| lc + &packed - index.get_variable(),
+        |lc| lc,
+    );
+
+    let mut bits: Vec<Boolean> = allocated.into_iter().map(Boolean::from).collect();
+    bits.resize(num_bits, Boolean::constant(false));
+    Ok(bits)
+}
@@ -0,0 +1,550 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use bellperson::{gadgets::num::AllocatedNum, ConstraintSystem, SynthesisError};
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::{circuit::poseidon_hash, poseidon::PoseidonConstants};
+use sha3::{
+    digest::{ExtendableOutput, Update, XofReader},
+    Shake256,
+};
+
+use super::{
+    gadgets::index_bits,
+    node_store::NodeStore,
+    path::TreePath,
+    tree::{compute_merkle_root, hash_pair, Leafable, MerkleTree},
+};
+use crate::{params::HasherId, sealed::Sealed};
+
+/// Two-to-one compression of internal nodes, natively and in a circuit.
+///
+/// Sealed, as every hasher has a `HasherId` recorded with its trees and proofs.
+pub trait MerkleHasher<F: PrimeField>: Sealed {
+    /// Id recorded with the trees and proofs it hashes.
+    const ID: HasherId;
+
+    fn hash_pair(&self, left: F, right: F) -> F;
+
+    fn synthesize_pair<CS: ConstraintSystem<F>>(
+        &self,
+        cs: CS,
+        left: &AllocatedNum<F>,
+        right: &AllocatedNum<F>,
+    ) -> Result<AllocatedNum<F>, SynthesisError>;
+}
+
+impl<F: PrimeField> Sealed for PoseidonConstants<F, U2> {}
+
+impl<F: PrimeField> MerkleHasher<F> for PoseidonConstants<F, U2> {
+    const ID: HasherId = HasherId::Poseidon;
+
+    fn hash_pair(&self, left: F, right: F) -> F {
+        hash_pair(self, left, right)
+    }
+
+    fn synthesize_pair<CS: ConstraintSystem<F>>(
+        &self,
+        cs: CS,
+        left: &AllocatedNum<F>,
+        right: &AllocatedNum<F>,
+    ) -> Result<AllocatedNum<F>, SynthesisError> {
+        poseidon_hash(cs, vec![left.clone(), right.clone()], self)
+    }
+}
+
+impl<H: Sealed> Sealed for Arc<H> {}
+
+/// Hashes like the shared hasher, so that a tree's `Arc` of constants can be passed directly.
+impl<F: PrimeField, H: MerkleHasher<F>> MerkleHasher<F> for Arc<H> {
+    const ID: HasherId = H::ID;
+
+    fn hash_pair(&self, left: F, right: F) -> F {
+        self.as_ref().hash_pair(left, right)
+    }
+
+    fn synthesize_pair<CS: ConstraintSystem<F>>(
+        &self,
+        cs: CS,
+        left: &AllocatedNum<F>,
+        right: &AllocatedNum<F>,
+    ) -> Result<AllocatedNum<F>, SynthesisError> {
+        self.as_ref().synthesize_pair(cs, left, right)
+    }
+}
+
+/// State width of the Rescue-Prime permutation: two rate elements and one capacity element.
+pub const RESCUE_WIDTH: usize = 3;
+/// Elements absorbed per permutation.
+const RESCUE_RATE: usize = 2;
+
+/// Rescue-Prime over a state of width 3, hashing a pair with the sponge of the specification:
+/// `[left, right]` padded to `[left, right, 1, 0]` is absorbed in two permutations, and the
+/// hash is the first element of the state.
+///
+/// `reference` generates the parameters of the specification, `new` takes others, such as those
+/// of a target proving stack.
+#[derive(Clone, Debug)]
+pub struct RescuePrime<F: PrimeField> {
+    alpha: u64,
+    /// Little-endian limbs of `alpha^-1 mod (p - 1)`.
+    alpha_inv: Vec<u64>,
+    mds: [[F; RESCUE_WIDTH]; RESCUE_WIDTH],
+    /// `2 * RESCUE_WIDTH` constants per round.
+    round_constants: Vec<F>,
+}
+
+impl<F: PrimeField> RescuePrime<F> {
+    pub fn new(
+        alpha: u64,
+        alpha_inv: Vec<u64>,
+        mds: [[F; RESCUE_WIDTH]; RESCUE_WIDTH],
+        round_constants: Vec<F>,
+    ) -> Self {
+        assert!(alpha >= 3, "alpha must be at least 3");
+        assert!(
+            !round_constants.is_empty() && round_constants.len().is_multiple_of(2 * RESCUE_WIDTH),
+            "round constants must fill whole rounds"
+        );
+        let g = F::multiplicative_generator();
+        assert_eq!(
+            g.pow_vartime([alpha]).pow_vartime(&alpha_inv),
+            g,
+            "alpha_inv does not invert alpha"
+        );
+
+        Self {
+            alpha,
+            alpha_inv,
+            mds,
+            round_constants,
+        }
+    }
+
+    /// Parameters of the Rescue-Prime reference implementation for `F`, with capacity 1 and
+    /// `security_level` bits of security.
+    ///
+    /// As in the reference, `alpha` is the least exponent coprime to `p - 1`, the number of rounds
+    /// is the Gröbner basis bound with a 50% margin and at least 5 rounds, the MDS matrix is taken
+    /// from the echelon form of a Vandermonde matrix of the least primitive element, and the round
+    /// constants are read from SHAKE256 of `Rescue-XLIX(p,3,1,security_level)`. The least
+    /// primitive element is taken to be `F::multiplicative_generator()`, and `F::Repr` to be
+    /// little-endian, as it is for the fields of this crate.
+    pub fn reference(security_level: u32) -> Self {
+        let p_minus_one = limbs((-F::one()).to_repr().as_ref());
+        let p = mul_add_small(&p_minus_one, 1, 1);
+
+        let alpha = (3..)
+            .find(|&alpha| gcd(alpha, rem_small(&p_minus_one, alpha)) == 1)
+            .unwrap();
+        let k = (1..alpha)
+            .find(|&k| (1 + k * rem_small(&p_minus_one, alpha)).is_multiple_of(alpha))
+            .expect("alpha is coprime to p - 1");
+        let alpha_inv = div_small(&mul_add_small(&p_minus_one, k, 1), alpha);
+
+        // Rounds needed against Gröbner basis attacks.
+        let (m, rate) = (RESCUE_WIDTH as u64, RESCUE_RATE as u64);
+        let l1 = (1..25)
+            .find(|&n| {
+                let dcon = (alpha - 1) * m * (n - 1) / 2 + 2;
+                let v = m * (n - 1) + rate;
+                binomial_squared_exceeds(v + dcon, v, security_level)
+            })
+            .unwrap_or(25);
+        let num_rounds = (3 * l1.max(5)).div_ceil(2) as usize;
+
+        // The MDS matrix is the transpose of the right half of the echelon form of
+        // `[g^(i * j)]` with `m` rows and `2 * m` columns.
+        let g = F::multiplicative_generator();
+        let mut v = [[F::zero(); 2 * RESCUE_WIDTH]; RESCUE_WIDTH];
+        for (i, row) in v.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = g.pow_vartime([(i * j) as u64]);
+            }
+        }
+        for c in 0..RESCUE_WIDTH {
+            let pivot = (c..RESCUE_WIDTH)
+                .find(|&r| !bool::from(v[r][c].is_zero()))
+                .expect("Vandermonde matrices of distinct points have full rank");
+            v.swap(c, pivot);
+            let inverse = v[c][c].invert().unwrap();
+            for entry in v[c].iter_mut() {
+                *entry *= inverse;
+            }
+            for r in (0..RESCUE_WIDTH).filter(|&r| r != c) {
+                let factor = v[r][c];
+                let pivot_row = v[c];
+                for (entry, &p) in v[r].iter_mut().zip(&pivot_row) {
+                    *entry -= factor * p;
+                }
+            }
+        }
+        let mut mds = [[F::zero(); RESCUE_WIDTH]; RESCUE_WIDTH];
+        for (i, row) in mds.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = v[j][RESCUE_WIDTH + i];
+            }
+        }
+
+        // Each constant is read from one more byte than the modulus takes, as a little-endian
+        // integer reduced modulo `p`.
+        let bytes_per_int = bit_len(&p).div_ceil(8) + 1;
+        let seed = format!(
+            "Rescue-XLIX({},{},{},{})",
+            to_decimal(&p),
+            RESCUE_WIDTH,
+            RESCUE_WIDTH - RESCUE_RATE,
+            security_level
+        );
+        let mut shake = Shake256::default();
+        shake.update(seed.as_bytes());
+        let mut reader = shake.finalize_xof();
+        let mut chunk = vec![0; bytes_per_int];
+        let round_constants = (0..2 * RESCUE_WIDTH * num_rounds)
+            .map(|_| {
+                reader.read(&mut chunk);
+                chunk.iter().rev().fold(F::zero(), |acc, &byte| {
+                    acc * F::from(256) + F::from(byte as u64)
+                })
+            })
+            .collect();
+
+        Self::new(alpha, alpha_inv, mds, round_constants)
+    }
+
+    pub fn num_rounds(&self) -> usize {
+        self.round_constants.len() / (2 * RESCUE_WIDTH)
+    }
+
+    fn linear_layer(&self, state: [F; RESCUE_WIDTH], constants: &[F]) -> [F; RESCUE_WIDTH] {
+        let mut next = [F::zero(); RESCUE_WIDTH];
+        for ((next, row), &constant) in next.iter_mut().zip(&self.mds).zip(constants) {
+            *next = constant;
+            for (m, s) in row.iter().zip(&state) {
+                *next += *m * s;
+            }
+        }
+        next
+    }
+
+    pub fn permute(&self, mut state: [F; RESCUE_WIDTH]) -> [F; RESCUE_WIDTH] {
+        for constants in self.round_constants.chunks(2 * RESCUE_WIDTH) {
+            for s in state.iter_mut() {
+                *s = s.pow_vartime([self.alpha]);
+            }
+            state = self.linear_layer(state, &constants[..RESCUE_WIDTH]);
+            for s in state.iter_mut() {
+                *s = s.pow_vartime(&self.alpha_inv);
+            }
+            state = self.linear_layer(state, &constants[RESCUE_WIDTH..]);
+        }
+        state
+    }
+
+    fn synthesize_linear_layer<CS: ConstraintSystem<F>>(
+        &self,
+        mut cs: CS,
+        state: &[AllocatedNum<F>],
+        constants: &[F],
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let mut next = vec![];
+        for (i, (row, &constant)) in self.mds.iter().zip(constants).enumerate() {
+            let value = AllocatedNum::alloc(cs.namespace(|| format!("allocate {i}")), || {
+                let mut value = constant;
+                for (m, s) in row.iter().zip(state) {
+                    value += *m * s.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+                }
+                Ok(value)
+            })?;
+            cs.enforce(
+                || format!("combine {i}"),
+                |mut lc| {
+                    for (m, s) in row.iter().zip(state) {
+                        lc = lc + (*m, s.get_variable());
+                    }
+                    lc + (constant, CS::one())
+                },
+                |lc| lc + CS::one(),
+                |lc| lc + value.get_variable(),
+            );
+            next.push(value);
+        }
+        Ok(next)
+    }
+
+    fn synthesize_permutation<CS: ConstraintSystem<F>>(
+        &self,
+        mut cs: CS,
+        mut state: Vec<AllocatedNum<F>>,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        for (r, constants) in self.round_constants.chunks(2 * RESCUE_WIDTH).enumerate() {
+            let mut cs = cs.namespace(|| format!("round {r}"));
+            for (i, s) in state.iter_mut().enumerate() {
+                *s = pow(cs.namespace(|| format!("s-box {i}")), s, self.alpha)?;
+            }
+            state = self.synthesize_linear_layer(
+                cs.namespace(|| "first linear layer"),
+                &state,
+                &constants[..RESCUE_WIDTH],
+            )?;
+            for (i, s) in state.iter_mut().enumerate() {
+                let mut cs = cs.namespace(|| format!("inverse s-box {i}"));
+                let root = AllocatedNum::alloc(cs.namespace(|| "allocate root"), || {
+                    Ok(s.get_value()
+                        .ok_or(SynthesisError::AssignmentMissing)?
+                        .pow_vartime(&self.alpha_inv))
+                })?;
+                let power = pow(cs.namespace(|| "power"), &root, self.alpha)?;
+                cs.enforce(
+                    || "root",
+                    |lc| lc + power.get_variable(),
+                    |lc| lc + CS::one(),
+                    |lc| lc + s.get_variable(),
+                );
+                *s = root;
+            }
+            state = self.synthesize_linear_layer(
+                cs.namespace(|| "second linear layer"),
+                &state,
+                &constants[RESCUE_WIDTH..],
+            )?;
+        }
+        Ok(state)
+    }
+}
+
+/// `x^exponent` by square-and-multiply.
+fn pow<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    x: &AllocatedNum<F>,
+    exponent: u64,
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    assert!(exponent >= 1);
+
+    let mut result = x.clone();
+    for i in (0..(u64::BITS - exponent.leading_zeros() - 1)).rev() {
+        result = result.square(cs.namespace(|| format!("square {i}")))?;
+        if (exponent >> i) & 1 == 1 {
+            result = result.mul(cs.namespace(|| format!("multiply {i}")), x)?;
+        }
+    }
+    Ok(result)
+}
+
+impl<F: PrimeField> Sealed for RescuePrime<F> {}
+
+impl<F: PrimeField> MerkleHasher<F> for RescuePrime<F> {
+    const ID: HasherId = HasherId::RescuePrime;
+
+    fn hash_pair(&self, left: F, right: F) -> F {
+        let mut state = self.permute([left, right, F::zero()]);
+        state[0] += F::one();
+        self.permute(state)[0]
+    }
+
+    fn synthesize_pair<CS: ConstraintSystem<F>>(
+        &self,
+        mut cs: CS,
+        left: &AllocatedNum<F>,
+        right: &AllocatedNum<F>,
+    ) -> Result<AllocatedNum<F>, SynthesisError> {
+        let capacity = AllocatedNum::alloc(cs.namespace(|| "allocate capacity"), || Ok(F::zero()))?;
+        cs.enforce(
+            || "fix capacity",
+            |lc| lc + capacity.get_variable(),
+            |lc| lc + CS::one(),
+            |lc| lc,
+        );
+        let state = vec![left.clone(), right.clone(), capacity];
+        let mut state = self.synthesize_permutation(cs.namespace(|| "first permutation"), state)?;
+
+        let padded = AllocatedNum::alloc(cs.namespace(|| "allocate padding"), || {
+            Ok(state[0]
+                .get_value()
+                .ok_or(SynthesisError::AssignmentMissing)?
+                + F::one())
+        })?;
+        cs.enforce(
+            || "absorb padding",
+            |lc| lc + state[0].get_variable() + CS::one(),
+            |lc| lc + CS::one(),
+            |lc| lc + padded.get_variable(),
+        );
+        state[0] = padded;
+        let state = self.synthesize_permutation(cs.namespace(|| "second permutation"), state)?;
+
+        Ok(state[0].clone())
+    }
+}
+
+/// Little-endian `u64` limbs of the little-endian `bytes`.
+fn limbs(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks(8)
+        .map(|chunk| {
+            let mut limb = [0; 8];
+            limb[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(limb)
+        })
+        .collect()
+}
+
+/// `limbs * k + a`.
+fn mul_add_small(limbs: &[u64], k: u64, a: u64) -> Vec<u64> {
+    let mut carry = a as u128;
+    let mut product: Vec<u64> = limbs
+        .iter()
+        .map(|&limb| {
+            let wide = limb as u128 * k as u128 + carry;
+            carry = wide >> 64;
+            wide as u64
+        })
+        .collect();
+    if carry > 0 {
+        product.push(carry as u64);
+    }
+    product
+}
+
+/// `limbs / d`, rounded down.
+fn div_small(limbs: &[u64], d: u64) -> Vec<u64> {
+    let mut rem = 0u128;
+    let mut quotient = vec![0; limbs.len()];
+    for (q, &limb) in quotient.iter_mut().zip(limbs).rev() {
+        let wide = rem << 64 | limb as u128;
+        *q = (wide / d as u128) as u64;
+        rem = wide % d as u128;
+    }
+    quotient
+}
+
+fn rem_small(limbs: &[u64], d: u64) -> u64 {
+    limbs
+        .iter()
+        .rev()
+        .fold(0u128, |rem, &limb| (rem << 64 | limb as u128) % d as u128) as u64
+}
+
+fn bit_len(limbs: &[u64]) -> usize {
+    limbs
+        .iter()
+        .rposition(|&limb| limb != 0)
+        .map_or(0, |i| 64 * (i + 1) - limbs[i].leading_zeros() as usize)
+}
+
+fn to_decimal(limbs: &[u64]) -> String {
+    let mut digits = vec![];
+    let mut n = limbs.to_vec();
+    while bit_len(&n) > 0 {
+        digits.push(b'0' + rem_small(&n, 10) as u8);
+        n = div_small(&n, 10);
+    }
+    if digits.is_empty() {
+        digits.push(b'0');
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Whether `binomial(n, k)^2 > 2^bits`.
+fn binomial_squared_exceeds(n: u64, k: u64, bits: u32) -> bool {
+    let mut binomial = vec![1];
+    for i in 0..k {
+        binomial = div_small(&mul_add_small(&binomial, n - i, 0), i + 1);
+    }
+    let mut square = vec![0; 2 * binomial.len()];
+    for (i, &a) in binomial.iter().enumerate() {
+        let mut carry = 0u128;
+        for (j, &b) in binomial.iter().enumerate() {
+            let wide = square[i + j] as u128 + a as u128 * b as u128 + carry;
+            square[i + j] = wide as u64;
+            carry = wide >> 64;
+        }
+        square[i + binomial.len()] = carry as u64;
+    }
+
+    // Of the numbers of `bits + 1` bits, only `2^bits` itself does not exceed it.
+    let len = bit_len(&square);
+    let power_of_two = square.iter().map(|limb| limb.count_ones()).sum::<u32>() == 1;
+    len > bits as usize + 1 || (len == bits as usize + 1 && !power_of_two)
+}
+
+/// Root implied by `leaf_hash` at `index` and its `siblings` from the leaf upwards, hashed with `hasher`.
+pub fn compute_root_with<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    index: usize,
+    leaf_hash: F,
+    siblings: &[F],
+) -> F {
+    compute_merkle_root(hasher, index, leaf_hash, siblings)
+}
+
+/// Circuit counterpart of `compute_root_with`.
+pub fn synthesize_root_with<F: PrimeField, H: MerkleHasher<F>, CS: ConstraintSystem<F>>(
+    hasher: &H,
+    mut cs: CS,
+    index: usize,
+    leaf: AllocatedNum<F>,
+    siblings: &[F],
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    let index = AllocatedNum::alloc(cs.namespace(|| "allocate index"), || {
+        Ok(F::from(index as u64))
+    })?;
+    let bits = index_bits(cs.namespace(|| "decompose index"), &index, siblings.len())?;
+    let mut h = leaf;
+    for (i, (lr_bit, &sibling)) in bits.iter().zip(siblings.iter()).enumerate() {
+        let mut cs = cs.namespace(|| format!("calculate parent hash {i}"));
+        let sibling = AllocatedNum::alloc(cs.namespace(|| "allocate sibling"), || Ok(sibling))?;
+        let (l, r) = AllocatedNum::conditionally_reverse(
+            cs.namespace(|| "reverse children"),
+            &h,
+            &sibling,
+            lr_bit,
+        )?;
+        h = hasher.synthesize_pair(cs.namespace(|| "hash"), &l, &r)?;
+    }
+    Ok(h)
+}
+
+impl<F: PrimeField, V: Leafable<F>, S: NodeStore<F>> MerkleTree<F, V, S> {
+    /// Hasher of the internal nodes, as recorded in `TreeConfig` and exported trees.
+    pub fn hasher_id(&self) -> HasherId {
+        <PoseidonConstants<F, U2> as MerkleHasher<F>>::ID
+    }
+
+    /// Root of the same leaves with internal nodes hashed by `hasher` instead of Poseidon.
+    ///
+    /// Nodes are keyed by their paths, so trees deeper than `usize::BITS` levels work too.
+    pub fn root_with<H: MerkleHasher<F>>(&self, hasher: &H) -> F {
+        let mut empty = self.empty_leaf.hash();
+        let mut nodes: BTreeMap<TreePath, F> = self
+            .leaves
+            .iter()
+            .map(|(path, leaf)| (path.clone(), leaf.hash()))
+            .collect();
+        for _ in 0..self.height {
+            let mut parents = BTreeMap::new();
+            for (path, &h) in &nodes {
+                let mut parent = path.clone();
+                let right = parent.pop().expect("nodes are below the root");
+                if parents.contains_key(&parent) {
+                    continue;
+                }
+                let sibling = *nodes.get(&path.sibling()).unwrap_or(&empty);
+                let (l, r) = if right { (sibling, h) } else { (h, sibling) };
+                parents.insert(parent, hasher.hash_pair(l, r));
+            }
+            nodes = parents;
+            empty = hasher.hash_pair(empty, empty);
+        }
+        nodes.values().next().copied().unwrap_or(empty)
+    }
+}
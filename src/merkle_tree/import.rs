@@ -0,0 +1,174 @@
+use std::io::{BufRead, ErrorKind};
+
+use ff::PrimeField;
+
+use super::{
+    node_store::NodeStore,
+    path::TreePath,
+    tree::{Leafable, MerkleTree},
+};
+use crate::encoding::{field_from_hex, EncodingError};
+
+/// Leaves applied by `MerkleTree::import_leaves` in each version.
+pub const IMPORT_CHUNK: usize = 1 << 16;
+
+/// Default of `ImportLeaf::max_bytes_len`.
+pub const MAX_BINARY_VALUE_LEN: usize = 1 << 16;
+
+/// Layout of a leaf file read by `MerkleTree::import_leaves`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeafFormat {
+    /// One `index,value` record per line, with the index in decimal and the value as parsed by
+    /// `ImportLeaf::from_csv`, which receives everything after the first comma. Blank lines are
+    /// skipped, as is a first line whose index column is not a number, taken for a header.
+    Csv,
+    /// Records of the index as a big-endian `u64`, the length of the value as a big-endian `u32`
+    /// and the value as parsed by `ImportLeaf::from_bytes`. A record whose length exceeds
+    /// `ImportLeaf::max_bytes_len` is invalid and its value is not read.
+    Binary,
+}
+
+/// Leaf that can be read from the formats of `LeafFormat`.
+pub trait ImportLeaf: Sized {
+    fn from_csv(value: &str) -> Option<Self>;
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+
+    /// Longest value `from_bytes` accepts, checked before a binary value is read.
+    fn max_bytes_len() -> usize {
+        MAX_BINARY_VALUE_LEN
+    }
+}
+
+impl<F: PrimeField> ImportLeaf for F {
+    /// `0x`-prefixed big-endian hex, as printed by `field_to_hex`, or a decimal `u64`.
+    fn from_csv(value: &str) -> Option<Self> {
+        if value.starts_with("0x") {
+            field_from_hex(value).ok()
+        } else {
+            value.parse::<u64>().ok().map(F::from)
+        }
+    }
+
+    /// The representation returned by `to_repr`.
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut repr = F::Repr::default();
+        if bytes.len() != repr.as_ref().len() {
+            return None;
+        }
+        repr.as_mut().copy_from_slice(bytes);
+        Option::from(F::from_repr(repr))
+    }
+
+    fn max_bytes_len() -> usize {
+        F::Repr::default().as_ref().len()
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F> + ImportLeaf, S: NodeStore<F>> MerkleTree<F, V, S> {
+    /// Sets the leaves listed by `reader` in `format` and returns how many were read.
+    ///
+    /// Records are read as they are needed and applied in versions of `IMPORT_CHUNK` leaves, so the
+    /// file is never held in memory at once; set a history limit beforehand so the changesets of a
+    /// large import are not kept either. Later records of an index take precedence. On an invalid
+    /// record the leaves of the earlier versions stay set and `InvalidRecord` gives its position,
+    /// counted from 1 and in lines for CSV.
+    pub fn import_leaves<R: BufRead>(
+        &mut self,
+        mut reader: R,
+        format: LeafFormat,
+    ) -> Result<u64, EncodingError> {
+        let mut chunk = Vec::with_capacity(IMPORT_CHUNK);
+        let mut count = 0;
+        let mut record = 0;
+        loop {
+            let next = match format {
+                LeafFormat::Csv => read_csv_record(&mut reader, &mut record)?,
+                LeafFormat::Binary => read_binary_record(&mut reader, &mut record)?,
+            };
+            let Some((index, leaf)) = next else {
+                break;
+            };
+            if self.height < usize::BITS as usize && index >> self.height != 0 {
+                return Err(EncodingError::InvalidRecord { record });
+            }
+
+            chunk.push((TreePath::from_index(index, self.height), leaf));
+            count += 1;
+            if chunk.len() == IMPORT_CHUNK {
+                self.update_batch_by_path(&chunk);
+                chunk.clear();
+            }
+        }
+        self.update_batch_by_path(&chunk);
+
+        Ok(count)
+    }
+}
+
+fn io_error(error: std::io::Error) -> EncodingError {
+    EncodingError::Encoding(bincode::Error::from(error))
+}
+
+/// Next record of a CSV file, counting the lines read in `record`.
+fn read_csv_record<V: ImportLeaf>(
+    reader: &mut impl BufRead,
+    record: &mut u64,
+) -> Result<Option<(usize, V)>, EncodingError> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(io_error)? == 0 {
+            return Ok(None);
+        }
+        *record += 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = EncodingError::InvalidRecord { record: *record };
+        let (index, value) = line.split_once(',').ok_or(invalid)?;
+        let Ok(index) = index.trim().parse::<usize>() else {
+            if *record == 1 {
+                continue;
+            }
+            return Err(EncodingError::InvalidRecord { record: *record });
+        };
+        let leaf =
+            V::from_csv(value.trim()).ok_or(EncodingError::InvalidRecord { record: *record })?;
+
+        return Ok(Some((index, leaf)));
+    }
+}
+
+/// Next record of a binary file, counting the records read in `record`.
+fn read_binary_record<V: ImportLeaf>(
+    reader: &mut impl BufRead,
+    record: &mut u64,
+) -> Result<Option<(usize, V)>, EncodingError> {
+    if reader.fill_buf().map_err(io_error)?.is_empty() {
+        return Ok(None);
+    }
+    *record += 1;
+    let invalid = || EncodingError::InvalidRecord { record: *record };
+    let truncated = |error: std::io::Error| match error.kind() {
+        ErrorKind::UnexpectedEof => invalid(),
+        _ => io_error(error),
+    };
+
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).map_err(truncated)?;
+    let index = u64::from_be_bytes(header[..8].try_into().unwrap());
+    let len = u32::from_be_bytes(header[8..].try_into().unwrap()) as usize;
+    if len > V::max_bytes_len() {
+        return Err(invalid());
+    }
+    let mut value = vec![0; len];
+    reader.read_exact(&mut value).map_err(truncated)?;
+
+    let index = usize::try_from(index).map_err(|_| invalid())?;
+    let leaf = V::from_bytes(&value).ok_or_else(invalid)?;
+
+    Ok(Some((index, leaf)))
+}
@@ -0,0 +1,169 @@
+use ff::PrimeField;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    path::TreePath,
+    tree::{Leafable, MerkleTree},
+};
+use crate::{
+    encoding::{field_from_hex, field_to_hex, EncodingError},
+    envelope::DecodeMode,
+    params::HasherId,
+};
+
+const JSON_VERSION: u8 = 1;
+
+/// Fields of `TreeJson`.
+const TREE_FIELDS: [&str; 6] = [
+    "version",
+    "hasher",
+    "height",
+    "root",
+    "empty_leaf",
+    "leaves",
+];
+
+/// Fields of `LeafJson`.
+const LEAF_FIELDS: [&str; 2] = ["path", "value"];
+
+/// Tree state as exported by `MerkleTree::to_json`.
+#[derive(Serialize, Deserialize)]
+struct TreeJson<V> {
+    version: u8,
+    hasher: HasherId,
+    height: usize,
+    /// `encoding::field_to_hex` of the root.
+    root: String,
+    empty_leaf: V,
+    leaves: Vec<LeafJson<V>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LeafJson<V> {
+    /// Bits of the path from the root down, `1` for a right child, which read as a binary
+    /// number give the index.
+    path: String,
+    value: V,
+}
+
+impl<F: PrimeField, V: Leafable<F> + Serialize + DeserializeOwned> MerkleTree<F, V> {
+    /// Pretty-printed JSON of the height, root, empty leaf and occupied leaves in index order,
+    /// with leaf values in their serde representation.
+    pub fn to_json(&self) -> Result<String, EncodingError> {
+        let mut leaves: Vec<(&TreePath, &V)> = self.leaves.iter().collect();
+        leaves.sort_by_key(|&(path, _)| path);
+        let json = TreeJson {
+            version: JSON_VERSION,
+            hasher: self.hasher_id(),
+            height: self.height,
+            root: field_to_hex(self.get_root()),
+            empty_leaf: self.empty_leaf.clone(),
+            leaves: leaves
+                .into_iter()
+                .map(|(path, leaf)| LeafJson {
+                    path: path
+                        .bits()
+                        .iter()
+                        .map(|&bit| if bit { '1' } else { '0' })
+                        .collect(),
+                    value: leaf.clone(),
+                })
+                .collect(),
+        };
+
+        serde_json::to_string_pretty(&json).map_err(EncodingError::Json)
+    }
+
+    /// Rebuilds a tree with the default constants from `to_json`, rehashing every leaf and
+    /// checking the result against the recorded root.
+    ///
+    /// A missing field of the tree or of a leaf is reported with its JSON path, as is, when
+    /// decoding strictly, a field that `to_json` does not write. Fields inside leaf values are
+    /// left to their serde implementation. A tree taller than `max_height`, or exported with
+    /// another hasher than the one it is rebuilt with, is rejected with `InvalidHeader` before
+    /// its leaves are hashed.
+    pub fn from_json(
+        json: &str,
+        max_height: usize,
+        mode: DecodeMode,
+    ) -> Result<Self, EncodingError> {
+        let json: Value = serde_json::from_str(json).map_err(EncodingError::Json)?;
+        check_fields(&json, &TREE_FIELDS, "", mode)?;
+        if let Some(leaves) = json.get("leaves").and_then(Value::as_array) {
+            for (i, leaf) in leaves.iter().enumerate() {
+                check_fields(leaf, &LEAF_FIELDS, &format!("leaves[{i}]."), mode)?;
+            }
+        }
+        let json: TreeJson<V> = serde_json::from_value(json).map_err(EncodingError::Json)?;
+        if json.version != JSON_VERSION {
+            return Err(EncodingError::UnsupportedVersion {
+                found: json.version,
+            });
+        }
+        if json.height > max_height {
+            return Err(EncodingError::InvalidHeader);
+        }
+        let root: F = field_from_hex(&json.root)?;
+
+        let mut leaves = Vec::with_capacity(json.leaves.len());
+        for leaf in json.leaves {
+            if leaf.path.len() != json.height {
+                return Err(EncodingError::InvalidPath);
+            }
+            let path = leaf
+                .path
+                .chars()
+                .map(|bit| match bit {
+                    '0' => Ok(false),
+                    '1' => Ok(true),
+                    _ => Err(EncodingError::InvalidPath),
+                })
+                .collect::<Result<TreePath, _>>()?;
+            leaves.push((path, leaf.value));
+        }
+
+        let mut tree = Self::new_with_empty(json.height, json.empty_leaf);
+        if json.hasher != tree.hasher_id() {
+            return Err(EncodingError::InvalidHeader);
+        }
+        tree.update_batch_by_path(&leaves);
+        if tree.get_root() != root {
+            return Err(EncodingError::RootMismatch);
+        }
+
+        Ok(tree)
+    }
+}
+
+/// Checks that the JSON object `value` has all of `fields` and, when decoding strictly, no
+/// other field, naming the first offending field by its path, `prefix` followed by its name.
+///
+/// Values of the wrong type are left to serde, which reports them when decoding the object.
+fn check_fields(
+    value: &Value,
+    fields: &[&str],
+    prefix: &str,
+    mode: DecodeMode,
+) -> Result<(), EncodingError> {
+    let Some(object) = value.as_object() else {
+        return Ok(());
+    };
+    if let Some(field) = fields.iter().find(|&&field| !object.contains_key(field)) {
+        return Err(EncodingError::MissingField {
+            path: format!("{prefix}{field}"),
+        });
+    }
+    if mode == DecodeMode::Strict {
+        if let Some(field) = object
+            .keys()
+            .find(|field| !fields.contains(&field.as_str()))
+        {
+            return Err(EncodingError::UnknownField {
+                path: format!("{prefix}{field}"),
+            });
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use std::sync::Arc;
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    path::TreePath,
+    tree::{hash_pair, Leafable},
+    zero_hashes::zero_hash_ladder,
+};
+
+/// Tags the key hash of `key_path`, so that paths do not depend on how `K` hashes.
+const KEY_TAG: u64 = 0x6b6579; // "key"
+
+/// Path of `key` from the root: the bits of `H(KEY_TAG, key.hash())`, most significant first.
+///
+/// The key is always hashed with `constants` again, since `Leafable::hash` may be the identity,
+/// as it is for field elements, which would let callers choose their own paths.
+///
+/// Assumes a little-endian field representation, as for the curves of this crate.
+pub fn key_path<F: PrimeField, K: Leafable<F>>(
+    constants: &PoseidonConstants<F, U2>,
+    key: &K,
+) -> TreePath {
+    let repr = hash_pair(constants, F::from(KEY_TAG), key.hash()).to_repr();
+    let bytes = repr.as_ref();
+    (0..F::NUM_BITS as usize)
+        .rev()
+        .map(|i| (bytes[i / 8] >> (i % 8)) & 1 == 1)
+        .collect()
+}
+
+/// Sparse Merkle tree keyed by arbitrary keys, each stored at the path given by its hash.
+///
+/// The tree has `F::NUM_BITS` levels, so distinct keys collide only if their tagged hashes do.
+/// A value whose hash is that of `V::empty_leaf()` is indistinguishable from an absent one.
+#[derive(Clone, Debug)]
+pub struct KvMerkleTree<F: PrimeField, K: Leafable<F>, V: Leafable<F>> {
+    poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+    node_hashes: im::HashMap<TreePath, F>,
+    entries: im::HashMap<TreePath, (K, V)>,
+    zero_hashes: Vec<F>,
+}
+
+impl<F: PrimeField, K: Leafable<F>, V: Leafable<F>> Default for KvMerkleTree<F, K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: PrimeField, K: Leafable<F>, V: Leafable<F>> KvMerkleTree<F, K, V> {
+    pub fn new() -> Self {
+        Self::new_with_constants(Arc::new(PoseidonConstants::new()))
+    }
+
+    pub fn new_with_constants(poseidon_constants: Arc<PoseidonConstants<F, U2>>) -> Self {
+        let zero_hashes = zero_hashes(&poseidon_constants, V::empty_leaf().hash());
+
+        Self {
+            poseidon_constants,
+            node_hashes: im::HashMap::new(),
+            entries: im::HashMap::new(),
+            zero_hashes,
+        }
+    }
+
+    pub fn poseidon_constants(&self) -> &Arc<PoseidonConstants<F, U2>> {
+        &self.poseidon_constants
+    }
+
+    pub fn height(&self) -> usize {
+        F::NUM_BITS as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get_root(&self) -> F {
+        self.get_node_hash(&TreePath::root())
+    }
+
+    fn get_node_hash(&self, path: &TreePath) -> F {
+        match self.node_hashes.get(path) {
+            Some(h) => *h,
+            None => self.zero_hashes[path.depth()],
+        }
+    }
+
+    fn set_node_hash(&mut self, path: TreePath, h: F) {
+        if h == self.zero_hashes[path.depth()] {
+            self.node_hashes.remove(&path);
+        } else {
+            self.node_hashes.insert(path, h);
+        }
+    }
+
+    /// Value of `key`, or the empty leaf if it is absent.
+    pub fn get(&self, key: &K) -> V {
+        match self.entries.get(&key_path(&self.poseidon_constants, key)) {
+            Some((_, value)) => value.clone(),
+            None => V::empty_leaf(),
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries
+            .contains_key(&key_path(&self.poseidon_constants, key))
+    }
+
+    /// Entries in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.entries.values().map(|(key, value)| (key, value))
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        let mut path = key_path(&self.poseidon_constants, &key);
+        let h = value.hash();
+        if h == self.zero_hashes[path.depth()] {
+            self.entries.remove(&path);
+        } else {
+            self.entries.insert(path.clone(), (key, value));
+        }
+
+        self.set_node_hash(path.clone(), h);
+        while path.pop().is_some() {
+            let h = hash_pair(
+                &self.poseidon_constants,
+                self.get_node_hash(&path.child(false)),
+                self.get_node_hash(&path.child(true)),
+            );
+            self.set_node_hash(path.clone(), h);
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.insert(key.clone(), V::empty_leaf())
+    }
+
+    /// Proof of the value of `key`, which shows its absence if the key is not in the tree.
+    pub fn prove(&self, key: &K) -> KvProof<F> {
+        let mut path = key_path(&self.poseidon_constants, key);
+        let mut siblings = vec![];
+        while !path.is_root() {
+            siblings.push(self.get_node_hash(&path.sibling()));
+            path.pop();
+        }
+
+        KvProof { siblings }
+    }
+}
+
+fn zero_hashes<F: PrimeField>(constants: &PoseidonConstants<F, U2>, empty_hash: F) -> Vec<F> {
+    let mut zero_hashes = zero_hash_ladder(constants, empty_hash, F::NUM_BITS as usize);
+    zero_hashes.reverse();
+
+    zero_hashes
+}
+
+/// Siblings of a key's path in a `KvMerkleTree`, from the leaf upwards.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KvProof<F: PrimeField> {
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> KvProof<F> {
+    /// Root implied by the leaf of `key` hashing to `leaf_hash`.
+    pub fn compute_root<K: Leafable<F>>(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        key: &K,
+        leaf_hash: F,
+    ) -> F {
+        let path = key_path(constants, key);
+        assert_eq!(
+            self.siblings.len(),
+            path.depth(),
+            "proof of another tree height"
+        );
+
+        let mut h = leaf_hash;
+        for (lr_bit, &sibling) in path.lr_bits().zip(self.siblings.iter()) {
+            h = if lr_bit {
+                hash_pair(constants, sibling, h)
+            } else {
+                hash_pair(constants, h, sibling)
+            };
+        }
+
+        h
+    }
+
+    /// Checks that `key` maps to a value hashing to `value_hash` under `root`.
+    pub fn verify_membership<K: Leafable<F>>(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        root: F,
+        key: &K,
+        value_hash: F,
+    ) -> bool {
+        self.siblings.len() == F::NUM_BITS as usize
+            && self.compute_root(constants, key, value_hash) == root
+    }
+
+    /// Checks that `key` is absent under `root`, where `empty_hash` is the hash of the empty leaf.
+    pub fn verify_non_membership<K: Leafable<F>>(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        root: F,
+        key: &K,
+        empty_hash: F,
+    ) -> bool {
+        self.verify_membership(constants, root, key, empty_hash)
+    }
+}
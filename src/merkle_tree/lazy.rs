@@ -0,0 +1,173 @@
+use std::{collections::HashMap, fmt, future::Future, sync::Arc};
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+
+use super::{
+    path::{MerklePath, TreePath},
+    tree::hash_pair,
+};
+
+/// Source of the nodes of a tree held elsewhere, such as the RPC of a full node.
+pub trait NodeFetcher<F: PrimeField> {
+    type Error;
+
+    /// Hashes of the nodes at `paths`, in the same order. A path as deep as the tree names a
+    /// leaf, whose hash is returned.
+    fn fetch(&self, paths: &[TreePath])
+        -> impl Future<Output = Result<Vec<F>, Self::Error>> + Send;
+}
+
+#[derive(Debug)]
+pub enum LazyTreeError<E> {
+    Fetch(E),
+    /// The fetcher returned a different number of hashes than requested.
+    WrongCount {
+        requested: usize,
+        returned: usize,
+    },
+    /// The fetched nodes do not lead to the trusted root.
+    RootMismatch,
+}
+
+impl<E: fmt::Display> fmt::Display for LazyTreeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LazyTreeError::Fetch(error) => write!(f, "fetching nodes failed: {error}"),
+            LazyTreeError::WrongCount {
+                requested,
+                returned,
+            } => write!(f, "requested {requested} nodes but got {returned}"),
+            LazyTreeError::RootMismatch => write!(f, "fetched nodes do not match the root"),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for LazyTreeError<E> {}
+
+/// Tree known by its root, whose nodes are fetched on demand to prove paths.
+///
+/// Fetched nodes are checked against the root before they are cached, together with every node
+/// computed from them, so later proofs sharing part of a path fetch only what they miss.
+#[derive(Debug)]
+pub struct LazyMerkleTree<F: PrimeField, N: NodeFetcher<F>> {
+    fetcher: N,
+    poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+    height: usize,
+    root: F,
+    cache: HashMap<TreePath, F>,
+}
+
+impl<F: PrimeField, N: NodeFetcher<F>> LazyMerkleTree<F, N> {
+    /// Tree of `height` levels with the trusted `root`, hashed with `poseidon_constants`.
+    pub fn new(
+        fetcher: N,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        height: usize,
+        root: F,
+    ) -> Self {
+        let mut cache = HashMap::new();
+        cache.insert(TreePath::root(), root);
+
+        Self {
+            fetcher,
+            poseidon_constants,
+            height,
+            root,
+            cache,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn root(&self) -> F {
+        self.root
+    }
+
+    /// Number of nodes known locally.
+    pub fn cached_nodes(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Proves the leaf at `index`, fetching the nodes not cached yet in a single call.
+    pub async fn prove(&mut self, index: usize) -> Result<MerklePath<F>, LazyTreeError<N::Error>> {
+        assert!(
+            self.height >= usize::BITS as usize || index >> self.height == 0,
+            "index {index} out of range"
+        );
+        let siblings = self
+            .prove_by_path(&TreePath::from_index(index, self.height))
+            .await?;
+        let leaf_hash = self.cache[&TreePath::from_index(index, self.height)];
+
+        Ok(MerklePath {
+            siblings,
+            index,
+            leaf_hash,
+        })
+    }
+
+    /// Siblings of the leaf at `path` from the leaf upwards.
+    pub async fn prove_by_path(
+        &mut self,
+        path: &TreePath,
+    ) -> Result<Vec<F>, LazyTreeError<N::Error>> {
+        assert_eq!(path.depth(), self.height, "path does not lead to a leaf");
+
+        // The leaf and the sibling of every node on its path.
+        let mut needed = vec![path.clone()];
+        let mut node = path.clone();
+        while !node.is_root() {
+            needed.push(node.sibling());
+            node.pop();
+        }
+        let missing: Vec<TreePath> = needed
+            .iter()
+            .filter(|path| !self.cache.contains_key(*path))
+            .cloned()
+            .collect();
+
+        let mut fetched = HashMap::new();
+        if !missing.is_empty() {
+            let hashes = self
+                .fetcher
+                .fetch(&missing)
+                .await
+                .map_err(LazyTreeError::Fetch)?;
+            if hashes.len() != missing.len() {
+                return Err(LazyTreeError::WrongCount {
+                    requested: missing.len(),
+                    returned: hashes.len(),
+                });
+            }
+            fetched.extend(missing.into_iter().zip(hashes));
+        }
+        let known = |path: &TreePath| fetched.get(path).or(self.cache.get(path)).copied();
+
+        let mut node = path.clone();
+        let mut h = known(&node).unwrap();
+        let mut computed = vec![(node.clone(), h)];
+        let mut siblings = Vec::with_capacity(self.height);
+        while !node.is_root() {
+            let sibling = known(&node.sibling()).unwrap();
+            siblings.push(sibling);
+            computed.push((node.sibling(), sibling));
+            h = if node.pop().unwrap() {
+                hash_pair(&self.poseidon_constants, sibling, h)
+            } else {
+                hash_pair(&self.poseidon_constants, h, sibling)
+            };
+            computed.push((node.clone(), h));
+        }
+        if h != self.root {
+            return Err(LazyTreeError::RootMismatch);
+        }
+
+        self.cache.extend(computed);
+
+        Ok(siblings)
+    }
+}
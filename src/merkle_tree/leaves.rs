@@ -0,0 +1,194 @@
+use std::sync::OnceLock;
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+use pasta_curves::{Fp, Fq};
+
+use super::{
+    sponge::{LeafSponge, LengthPrefix},
+    tree::Leafable,
+};
+
+/// Bytes packed into each field element, small enough to be below the modulus.
+const BYTES_PER_ELEMENT: usize = 31;
+
+/// Separates leaves of different types that encode to the same bytes.
+const U64_TAG: u64 = 1;
+const U128_TAG: u64 = 2;
+const BYTES32_TAG: u64 = 3;
+const BYTES_TAG: u64 = 4;
+
+/// Encodes the fields of a leaf and hashes them with a length-prefixed Poseidon sponge.
+///
+/// The circuit computes the same hash by allocating `inputs()` and passing them to
+/// `LeafSponge::<F, LengthPrefix>::synthesize`.
+#[derive(Clone, Debug, Default)]
+pub struct LeafBuilder<F: PrimeField> {
+    inputs: Vec<F>,
+}
+
+impl<F: PrimeField> LeafBuilder<F> {
+    pub fn new() -> Self {
+        Self { inputs: vec![] }
+    }
+
+    pub fn field(mut self, value: F) -> Self {
+        self.inputs.push(value);
+        self
+    }
+
+    pub fn u64(self, value: u64) -> Self {
+        self.field(F::from(value))
+    }
+
+    /// Appends the byte length followed by little-endian 31-byte chunks.
+    pub fn bytes(mut self, bytes: &[u8]) -> Self {
+        self.inputs.push(F::from(bytes.len() as u64));
+        for chunk in bytes.chunks(BYTES_PER_ELEMENT) {
+            let mut repr = F::Repr::default();
+            repr.as_mut()[..chunk.len()].copy_from_slice(chunk);
+            self.inputs.push(F::from_repr(repr).unwrap());
+        }
+        self
+    }
+
+    pub fn inputs(&self) -> &[F] {
+        &self.inputs
+    }
+
+    pub fn hash(&self, constants: &PoseidonConstants<F, U2>) -> F {
+        LeafSponge::<F, LengthPrefix>::new(constants.clone()).hash(&self.inputs)
+    }
+}
+
+fn hash_bytes<F: PrimeField>(constants: &PoseidonConstants<F, U2>, tag: u64, bytes: &[u8]) -> F {
+    LeafBuilder::new().u64(tag).bytes(bytes).hash(constants)
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use generic_array::typenum::U2;
+    pub use neptune::poseidon::PoseidonConstants;
+    pub use std::sync::OnceLock;
+}
+
+/// Implements `Leafable<$field>` for a struct by hashing its fields in order with [`LeafBuilder`].
+///
+/// Each field is tagged with the builder method that encodes it, and the empty leaf is `Default::default()`.
+/// The leaf has no stored encoding, so persistent node stores cannot keep it.
+///
+/// ```ignore
+/// poseidon_leaf!(F1, Account { balance: field, nonce: u64, pubkey: bytes });
+/// ```
+#[macro_export]
+macro_rules! poseidon_leaf {
+    ($field:ty, $leaf:ty { $($name:ident : $kind:ident),* $(,)? }) => {
+        impl $crate::merkle_tree::tree::Leafable<$field> for $leaf {
+            fn empty_leaf() -> Self {
+                Default::default()
+            }
+
+            fn hash(&self) -> $field {
+                use $crate::merkle_tree::leaves::__private::{OnceLock, PoseidonConstants, U2};
+                static CONSTANTS: OnceLock<PoseidonConstants<$field, U2>> = OnceLock::new();
+
+                $crate::merkle_tree::leaves::LeafBuilder::<$field>::new()
+                    $(.$kind($crate::poseidon_leaf!(@arg self.$name, $kind)))*
+                    .hash(CONSTANTS.get_or_init(PoseidonConstants::new))
+            }
+        }
+    };
+    (@arg $value:expr, bytes) => {
+        &$value
+    };
+    (@arg $value:expr, $kind:ident) => {
+        $value
+    };
+}
+
+// `impl<F: PrimeField> Leafable<F> for u64` would overlap with `Leafable<F> for F`,
+// so the primitive leaves are implemented for each field of the curve cycle.
+macro_rules! impl_primitive_leaves {
+    ($field:ty, $constants:ident) => {
+        fn $constants() -> &'static PoseidonConstants<$field, U2> {
+            static CONSTANTS: OnceLock<PoseidonConstants<$field, U2>> = OnceLock::new();
+            CONSTANTS.get_or_init(PoseidonConstants::new)
+        }
+
+        impl Leafable<$field> for u64 {
+            fn empty_leaf() -> Self {
+                0
+            }
+
+            fn hash(&self) -> $field {
+                hash_bytes($constants(), U64_TAG, &self.to_le_bytes())
+            }
+
+            fn to_stored(&self) -> Option<Vec<u8>> {
+                Some(self.to_le_bytes().to_vec())
+            }
+
+            fn from_stored(bytes: &[u8]) -> Option<Self> {
+                Some(u64::from_le_bytes(bytes.try_into().ok()?))
+            }
+        }
+
+        impl Leafable<$field> for u128 {
+            fn empty_leaf() -> Self {
+                0
+            }
+
+            fn hash(&self) -> $field {
+                hash_bytes($constants(), U128_TAG, &self.to_le_bytes())
+            }
+
+            fn to_stored(&self) -> Option<Vec<u8>> {
+                Some(self.to_le_bytes().to_vec())
+            }
+
+            fn from_stored(bytes: &[u8]) -> Option<Self> {
+                Some(u128::from_le_bytes(bytes.try_into().ok()?))
+            }
+        }
+
+        impl Leafable<$field> for [u8; 32] {
+            fn empty_leaf() -> Self {
+                [0; 32]
+            }
+
+            fn hash(&self) -> $field {
+                hash_bytes($constants(), BYTES32_TAG, self)
+            }
+
+            fn to_stored(&self) -> Option<Vec<u8>> {
+                Some(self.to_vec())
+            }
+
+            fn from_stored(bytes: &[u8]) -> Option<Self> {
+                bytes.try_into().ok()
+            }
+        }
+
+        impl Leafable<$field> for Vec<u8> {
+            fn empty_leaf() -> Self {
+                vec![]
+            }
+
+            fn hash(&self) -> $field {
+                hash_bytes($constants(), BYTES_TAG, self)
+            }
+
+            fn to_stored(&self) -> Option<Vec<u8>> {
+                Some(self.clone())
+            }
+
+            fn from_stored(bytes: &[u8]) -> Option<Self> {
+                Some(bytes.to_vec())
+            }
+        }
+    };
+}
+
+impl_primitive_leaves!(Fp, fp_constants);
+impl_primitive_leaves!(Fq, fq_constants);
@@ -0,0 +1,353 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use ff::PrimeField;
+use memmap2::MmapMut;
+
+use super::{
+    node_store::{assert_own_hash, own_hash_leaves, NodeStore, PendingWrites},
+    path::TreePath,
+    tree::Leafable,
+};
+
+const MAGIC: [u8; 4] = *b"NSMF";
+/// Magic, height as a big-endian `u32` and number of stored nodes as a big-endian `u64`.
+const HEADER_LEN: usize = 16;
+/// Ends a complete log, followed by the number of records as a big-endian `u64`.
+const WAL_MAGIC: [u8; 4] = *b"NSMC";
+const WAL_TRAILER_LEN: usize = 12;
+
+/// Record of the write-ahead log, as a kind byte, a big-endian `u64` slot and, for `SET`,
+/// the hash.
+const CLEAR: u8 = 0;
+const SET: u8 = 1;
+const REMOVE: u8 = 2;
+
+/// Node store in a memory-mapped file holding every node of a tree of fixed height, for dense
+/// trees where most nodes are stored.
+///
+/// After the header, the file holds one array of hashes per depth, from the root down to the
+/// leaves, followed by a bitmap of the stored nodes in the same order. A node is found by
+/// offset instead of by hashing its path, and opening a file maps it without reading it.
+/// Leaves appended in index order are written at increasing offsets of each level.
+///
+/// As with the database-backed stores, writes are buffered until `commit` and applied as one
+/// atomic write. A mapping has no atomic write of its own, so `commit` provides it as the
+/// databases do: it logs the writes to a `.wal` file next to the store and syncs it before
+/// applying them to the mapping. `open` replays a complete log left by a crash and discards an
+/// incomplete one, so the file always holds a committed version of the tree.
+///
+/// The file takes `2^(height + 1)` hashes whether the tree is dense or not, and does not keep
+/// checkpoints. It keeps no leaves apart from their hashes either, so it only holds trees whose
+/// leaves are their own hash, as field elements are, and panics on a commit of other leaves. A copy would share the mapping, so neither the store nor a tree using it can be
+/// cloned. Reopen a tree with `MerkleTree::open_with_store`.
+#[derive(Debug)]
+pub struct MmapNodeStore<F: PrimeField> {
+    map: Arc<RwLock<MmapMut>>,
+    wal: Arc<Mutex<File>>,
+    height: usize,
+    pending: PendingWrites<F>,
+}
+
+impl<F: PrimeField> MmapNodeStore<F> {
+    /// Creates an empty store for a tree of `height` levels at `path`, replacing any file there.
+    pub fn create<P: AsRef<Path>>(path: P, height: usize) -> io::Result<Self> {
+        let size = file_size::<F>(height).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "tree is too large to map")
+        })?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len(size)?;
+
+        let mut map = map(&file)?;
+        map[..4].copy_from_slice(&MAGIC);
+        map[4..8].copy_from_slice(&(height as u32).to_be_bytes());
+        map.flush()?;
+        let wal = open_wal(path.as_ref())?;
+        wal.set_len(0)?;
+
+        Ok(Self::new(map, wal, height))
+    }
+
+    /// Maps the store at `path`, written by `create`, after recovering from its log.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        let map = map(&file)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a node store file");
+        if map.len() < HEADER_LEN || map[..4] != MAGIC {
+            return Err(invalid());
+        }
+        let height = u32::from_be_bytes(map[4..8].try_into().unwrap()) as usize;
+        if file_size::<F>(height) != Some(map.len() as u64) {
+            return Err(invalid());
+        }
+
+        let store = Self::new(map, open_wal(path.as_ref())?, height);
+        store.recover()?;
+
+        Ok(store)
+    }
+
+    fn new(map: MmapMut, wal: File, height: usize) -> Self {
+        Self {
+            map: Arc::new(RwLock::new(map)),
+            wal: Arc::new(Mutex::new(wal)),
+            height,
+            pending: PendingWrites::default(),
+        }
+    }
+
+    /// Height of the tree the file was created for.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Writes the mapped pages back to the file. Committed writes are already durable in the log.
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.read().unwrap().flush()
+    }
+
+    /// Applies a complete log left by an interrupted commit and empties the log.
+    fn recover(&self) -> io::Result<()> {
+        let mut wal = self.wal.lock().unwrap();
+        let mut log = vec![];
+        wal.read_to_end(&mut log)?;
+        if let Some(records) = self.parse_log(&log) {
+            let mut map = self.map.write().unwrap();
+            for (kind, slot, hash) in records {
+                self.apply(&mut map, kind, slot, hash);
+            }
+            map.flush()?;
+        }
+        wal.set_len(0)?;
+        wal.sync_data()
+    }
+
+    /// Records of `log`, or `None` if it is empty or was not completely written.
+    fn parse_log<'a>(&self, log: &'a [u8]) -> Option<Vec<(u8, usize, &'a [u8])>> {
+        let body_len = log.len().checked_sub(WAL_TRAILER_LEN)?;
+        let (mut body, trailer) = log.split_at(body_len);
+        if trailer[..4] != WAL_MAGIC {
+            return None;
+        }
+        let count = u64::from_be_bytes(trailer[4..].try_into().unwrap());
+
+        let mut records = vec![];
+        while !body.is_empty() {
+            let kind = body[0];
+            let slot = usize::try_from(u64::from_be_bytes(body.get(1..9)?.try_into().unwrap()))
+                .ok()
+                .filter(|&slot| slot < num_slots(self.height))?;
+            let len = if kind == SET { element_size::<F>() } else { 0 };
+            let hash = body.get(9..9 + len)?;
+            records.push((kind, slot, hash));
+            body = &body[9 + len..];
+        }
+
+        (records.len() as u64 == count).then_some(records)
+    }
+
+    fn apply(&self, map: &mut MmapMut, kind: u8, slot: usize, hash: &[u8]) {
+        let bitmap = self.bitmap_offset();
+        let mut len = u64::from_be_bytes(map[8..16].try_into().unwrap());
+        let stored = (map[bitmap + slot / 8] >> (slot % 8)) & 1 == 1;
+        match kind {
+            CLEAR => {
+                map[bitmap..].fill(0);
+                len = 0;
+            }
+            SET => {
+                let offset = HEADER_LEN + slot * element_size::<F>();
+                map[offset..offset + hash.len()].copy_from_slice(hash);
+                map[bitmap + slot / 8] |= 1 << (slot % 8);
+                len += !stored as u64;
+            }
+            _ => {
+                map[bitmap + slot / 8] &= !(1 << (slot % 8));
+                len -= stored as u64;
+            }
+        }
+        map[8..16].copy_from_slice(&len.to_be_bytes());
+    }
+
+    /// Position of the node at `path` in level-major order.
+    fn slot(&self, path: &TreePath) -> usize {
+        assert!(path.depth() <= self.height, "path is deeper than the tree");
+        (1 << path.depth()) - 1 + path.to_index()
+    }
+
+    fn bitmap_offset(&self) -> usize {
+        HEADER_LEN + num_slots(self.height) * element_size::<F>()
+    }
+
+    fn stored(&self, slot: usize) -> Option<F> {
+        let map = self.map.read().unwrap();
+        if (map[self.bitmap_offset() + slot / 8] >> (slot % 8)) & 1 == 0 {
+            return None;
+        }
+
+        let offset = HEADER_LEN + slot * element_size::<F>();
+        let mut repr = F::Repr::default();
+        repr.as_mut()
+            .copy_from_slice(&map[offset..offset + element_size::<F>()]);
+        Some(Option::from(F::from_repr(repr)).expect("corrupt node hash"))
+    }
+}
+
+impl<F: PrimeField> NodeStore<F> for MmapNodeStore<F> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        if let Some(h) = self.pending.get(path) {
+            return h;
+        }
+        self.stored(self.slot(path))
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        assert!(path.depth() <= self.height, "path is deeper than the tree");
+        let old = self.get(&path);
+        let len = self.len() + old.is_none() as usize;
+        self.pending.write(path, Some(hash), len);
+
+        old
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        let old = self.get(path)?;
+        let len = self.len() - 1;
+        self.pending.write(path.clone(), None, len);
+
+        Some(old)
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len.unwrap_or_else(|| {
+            let map = self.map.read().unwrap();
+            u64::from_be_bytes(map[8..16].try_into().unwrap()) as usize
+        })
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+        assert_own_hash(writes);
+    }
+
+    fn leaves<V: Leafable<F>>(&self, height: usize) -> Vec<(TreePath, V)> {
+        own_hash_leaves(self.iter(), height)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        let slots: Vec<usize> = {
+            let map = self.map.read().unwrap();
+            let bitmap = &map[self.bitmap_offset()..];
+            (0..num_slots(self.height))
+                .filter(|&slot| (bitmap[slot / 8] >> (slot % 8)) & 1 == 1)
+                .collect()
+        };
+
+        let stored = slots.into_iter().map(move |slot| {
+            // The slots of depth `d` start at `2^d - 1`.
+            let depth = (usize::BITS - (slot + 1).leading_zeros() - 1) as usize;
+            let path = TreePath::from_index(slot + 1 - (1 << depth), depth);
+            let hash = self.stored(slot).expect("stored node disappeared");
+            (path, hash)
+        });
+        Box::new(self.pending.overlay(stored))
+    }
+
+    fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        let mut records: Vec<(u8, usize, Vec<u8>)> = vec![];
+        if pending.cleared {
+            records.push((CLEAR, 0, vec![]));
+        }
+        for (path, h) in &pending.nodes {
+            records.push(match h {
+                Some(h) => (SET, self.slot(path), h.to_repr().as_ref().to_vec()),
+                None => (REMOVE, self.slot(path), vec![]),
+            });
+        }
+
+        let mut log = vec![];
+        for (kind, slot, hash) in &records {
+            log.push(*kind);
+            log.extend_from_slice(&(*slot as u64).to_be_bytes());
+            log.extend_from_slice(hash);
+        }
+        let mut trailer = WAL_MAGIC.to_vec();
+        trailer.extend_from_slice(&(records.len() as u64).to_be_bytes());
+
+        let mut wal = self.wal.lock().unwrap();
+        let logged = (|| {
+            wal.set_len(0)?;
+            wal.write_all(&log)?;
+            wal.sync_data()?;
+            // The trailer is only written once the records are durable, so a log with a trailer
+            // is complete.
+            wal.write_all(&trailer)?;
+            wal.sync_data()
+        })();
+        logged.expect("node store log write failed");
+
+        let mut map = self.map.write().unwrap();
+        for (kind, slot, hash) in &records {
+            self.apply(&mut map, *kind, *slot, hash);
+        }
+        map.flush().expect("node store write failed");
+        wal.set_len(0).expect("node store log write failed");
+    }
+}
+
+fn map(file: &File) -> io::Result<MmapMut> {
+    // Safety: the file is opened by this store and is only changed through the mapping.
+    unsafe { MmapMut::map_mut(file) }
+}
+
+/// Opens the log of the store at `path`, in the same directory with a `.wal` extension added.
+fn open_wal(path: &Path) -> io::Result<File> {
+    let mut wal = PathBuf::from(path).into_os_string();
+    wal.push(".wal");
+    OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(wal)
+}
+
+fn element_size<F: PrimeField>() -> usize {
+    F::Repr::default().as_ref().len()
+}
+
+/// Nodes of a tree of `height` levels, from the root to the leaves.
+fn num_slots(height: usize) -> usize {
+    (1 << (height + 1)) - 1
+}
+
+fn file_size<F: PrimeField>(height: usize) -> Option<u64> {
+    if height + 1 >= usize::BITS as usize - 4 {
+        return None;
+    }
+    let slots = num_slots(height);
+    let size = HEADER_LEN
+        .checked_add(slots.checked_mul(element_size::<F>())?)?
+        .checked_add(slots.div_ceil(8))?;
+
+    u64::try_from(size).ok()
+}
@@ -1,2 +1,36 @@
+pub mod acl;
+pub mod append;
+pub mod async_tree;
+pub mod cached_store;
 pub mod circuits;
+pub mod concurrent;
+pub mod dedup_store;
+pub mod frontier;
+pub mod gadgets;
+pub mod hasher;
+pub mod import;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod kv;
+pub mod lazy;
+pub mod leaves;
+#[cfg(feature = "mmap-store")]
+pub mod mmap_store;
+pub mod multiproof;
+pub mod node_store;
+pub mod path;
+pub mod quaternary;
+mod random;
+#[cfg(feature = "rocksdb-store")]
+pub mod rocksdb_store;
+pub mod sampling;
+pub mod schedule;
+#[cfg(feature = "sled-store")]
+pub mod sled_store;
+pub mod snapshot;
+pub mod sponge;
+#[cfg(feature = "sqlite-store")]
+pub mod sqlite_store;
 pub mod tree;
+pub mod verify;
+pub(crate) mod zero_hashes;
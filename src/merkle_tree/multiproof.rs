@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    node_store::NodeStore,
+    path::{MerklePath, TreePath},
+    tree::{hash_pair, Leafable, MerkleTree},
+};
+
+/// Proof of several leaves at once, where every sibling is sent at most once and
+/// siblings derivable from the proven leaves are omitted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MerkleMultiProof<F: PrimeField> {
+    pub height: usize,
+    /// Proven indices in strictly increasing order.
+    pub indices: Vec<usize>,
+    /// Missing siblings from the leaves upwards, ordered by position within each level.
+    pub siblings: Vec<F>,
+}
+
+/// Proof of the `count` consecutive leaves from `start`, whose only siblings lie along
+/// the left and right edges of the range.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MerkleRangeProof<F: PrimeField> {
+    pub height: usize,
+    pub start: usize,
+    pub count: usize,
+    /// Siblings in the order of the equivalent `MerkleMultiProof`.
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> MerkleRangeProof<F> {
+    /// Multi-proof of the same leaves, which the range proof encodes without its indices.
+    /// Returns `None` if the range does not fit in the tree.
+    ///
+    /// It allocates `count` indices, so bound an untrusted `count` before calling it, as
+    /// `compute_root` does with the number of leaf hashes.
+    pub fn to_multi_proof(&self) -> Option<MerkleMultiProof<F>> {
+        let end = self.start.checked_add(self.count)?;
+        if self.count == 0 || !fits(end - 1, self.height) {
+            return None;
+        }
+        Some(MerkleMultiProof {
+            height: self.height,
+            indices: (self.start..end).collect(),
+            siblings: self.siblings.clone(),
+        })
+    }
+
+    /// Root implied by `leaf_hashes`, the hashes of the leaves in the range in index order.
+    /// Returns `None` if the proof is malformed.
+    pub fn compute_root(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        leaf_hashes: &[F],
+    ) -> Option<F> {
+        if self.count != leaf_hashes.len() {
+            return None;
+        }
+        self.to_multi_proof()?.compute_root(constants, leaf_hashes)
+    }
+
+    pub fn verify(&self, constants: &PoseidonConstants<F, U2>, root: F, leaf_hashes: &[F]) -> bool {
+        self.compute_root(constants, leaf_hashes) == Some(root)
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F>, S: NodeStore<F>> MerkleTree<F, V, S> {
+    /// Proves the `count` leaves from `start` with a single proof.
+    pub fn prove_range(&self, start: usize, count: usize) -> MerkleRangeProof<F> {
+        assert!(count > 0, "range is empty");
+        let end = start.checked_add(count).expect("range overflows");
+        let proof = self.prove_many(&(start..end).collect::<Vec<_>>());
+
+        MerkleRangeProof {
+            height: self.height,
+            start,
+            count,
+            siblings: proof.siblings,
+        }
+    }
+
+    /// Proves the leaves at `indices` with a single multi-proof. Duplicate indices are proven once.
+    pub fn prove_many(&self, indices: &[usize]) -> MerkleMultiProof<F> {
+        let indices: Vec<usize> = indices
+            .iter()
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert!(
+            indices.iter().all(|&index| fits(index, self.height)),
+            "index out of range"
+        );
+
+        let mut siblings = vec![];
+        let mut known = indices.clone();
+        for level in 0..self.height {
+            let depth = self.height - level;
+            for (i, &position) in known.iter().enumerate() {
+                let sibling = position ^ 1;
+                let derivable = if position & 1 == 0 {
+                    known.get(i + 1) == Some(&sibling)
+                } else {
+                    i > 0 && known[i - 1] == sibling
+                };
+                if !derivable {
+                    siblings.push(self.get_node_hash(&TreePath::from_index(sibling, depth)));
+                }
+            }
+            known = parents(&known);
+        }
+
+        MerkleMultiProof {
+            height: self.height,
+            indices,
+            siblings,
+        }
+    }
+}
+
+impl<F: PrimeField> MerkleMultiProof<F> {
+    /// Combines paths generated independently from the same tree into one multi-proof, keeping
+    /// each shared sibling once and dropping siblings derivable from the other proven leaves.
+    ///
+    /// Returns the proof with the leaf hashes in the order of its indices, or `None` if there are
+    /// no paths, their heights differ or two paths of the same leaf disagree on its hash.
+    pub fn aggregate(paths: &[MerklePath<F>]) -> Option<(Self, Vec<F>)> {
+        let height = paths.first()?.height();
+        let mut by_index: BTreeMap<usize, &MerklePath<F>> = BTreeMap::new();
+        for path in paths {
+            if path.height() != height || !fits(path.index, height) {
+                return None;
+            }
+            if let Some(other) = by_index.insert(path.index, path) {
+                if other.leaf_hash != path.leaf_hash {
+                    return None;
+                }
+            }
+        }
+        let indices: Vec<usize> = by_index.keys().copied().collect();
+        let leaf_hashes: Vec<F> = by_index.values().map(|path| path.leaf_hash).collect();
+
+        // Each known position with a path through it, which holds the sibling at every level.
+        let mut known: Vec<(usize, &MerklePath<F>)> = by_index.into_iter().collect();
+        let mut siblings = vec![];
+        for level in 0..height {
+            for (i, &(position, path)) in known.iter().enumerate() {
+                let sibling = position ^ 1;
+                let derivable = if position & 1 == 0 {
+                    known.get(i + 1).map(|&(next, _)| next) == Some(sibling)
+                } else {
+                    i > 0 && known[i - 1].0 == sibling
+                };
+                if !derivable {
+                    siblings.push(path.siblings[level]);
+                }
+            }
+            known = known
+                .into_iter()
+                .map(|(position, path)| (position >> 1, path))
+                .collect();
+            known.dedup_by_key(|&mut (position, _)| position);
+        }
+
+        Some((
+            Self {
+                height,
+                indices,
+                siblings,
+            },
+            leaf_hashes,
+        ))
+    }
+
+    /// Root implied by `leaf_hashes`, given in the order of `indices`.
+    /// Returns `None` if the proof is malformed.
+    pub fn compute_root(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        leaf_hashes: &[F],
+    ) -> Option<F> {
+        if self.indices.is_empty()
+            || leaf_hashes.len() != self.indices.len()
+            || !self.indices.windows(2).all(|w| w[0] < w[1])
+            || !self.indices.iter().all(|&index| fits(index, self.height))
+        {
+            return None;
+        }
+
+        let mut nodes: Vec<(usize, F)> = self
+            .indices
+            .iter()
+            .copied()
+            .zip(leaf_hashes.iter().copied())
+            .collect();
+        let mut siblings = self.siblings.iter();
+        for _ in 0..self.height {
+            let mut next = Vec::with_capacity(nodes.len());
+            let mut i = 0;
+            while i < nodes.len() {
+                let (position, h) = nodes[i];
+                let parent = if position & 1 == 0 {
+                    if i + 1 < nodes.len() && nodes[i + 1].0 == position + 1 {
+                        i += 1;
+                        hash_pair(constants, h, nodes[i].1)
+                    } else {
+                        hash_pair(constants, h, *siblings.next()?)
+                    }
+                } else {
+                    hash_pair(constants, *siblings.next()?, h)
+                };
+                next.push((position >> 1, parent));
+                i += 1;
+            }
+            nodes = next;
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+
+        Some(nodes[0].1)
+    }
+
+    pub fn verify(&self, constants: &PoseidonConstants<F, U2>, root: F, leaf_hashes: &[F]) -> bool {
+        self.compute_root(constants, leaf_hashes) == Some(root)
+    }
+}
+
+fn parents(positions: &[usize]) -> Vec<usize> {
+    let mut parents: Vec<usize> = positions.iter().map(|position| position >> 1).collect();
+    parents.dedup();
+    parents
+}
+
+fn fits(index: usize, height: usize) -> bool {
+    height >= usize::BITS as usize || index >> height == 0
+}
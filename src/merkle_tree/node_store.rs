@@ -0,0 +1,383 @@
+use std::{collections::HashMap, convert::Infallible, future::Future};
+
+use ff::PrimeField;
+
+use super::{
+    path::TreePath,
+    tree::{Checkpoint, Leafable},
+};
+
+/// Storage of the internal node hashes of a `MerkleTree`, keyed by path.
+///
+/// Only nodes that differ from the hash of an empty subtree are stored. A tree clones its store
+/// when it is cloned, so a store that is cheap to clone keeps `MerkleTree::clone` cheap. Stores
+/// backed by a database or file cannot be copied, and neither can the trees using them.
+pub trait NodeStore<F: PrimeField> {
+    fn get(&self, path: &TreePath) -> Option<F>;
+
+    /// Stores `hash` at `path` and returns the hash it replaces.
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F>;
+
+    fn remove(&mut self, path: &TreePath) -> Option<F>;
+
+    fn len(&self) -> usize;
+
+    fn clear(&mut self);
+
+    /// Sets each node of `writes` to its hash, or removes it for `None`. The tree stages the nodes
+    /// of a version and writes them at once before `commit`, so a store can apply them together.
+    fn write_batch(&mut self, writes: impl IntoIterator<Item = (TreePath, Option<F>)>) {
+        for (path, h) in writes {
+            match h {
+                Some(h) => self.insert(path, h),
+                None => self.remove(&path),
+            };
+        }
+    }
+
+    /// Keeps `checkpoint` with the writes of the next `commit`. Stores that only hold nodes, as
+    /// the in-memory ones do, ignore it.
+    fn record_checkpoint(&mut self, _checkpoint: Checkpoint<F>) {}
+
+    /// Checkpoints kept by earlier commits, in the order they were recorded.
+    fn checkpoints(&self) -> Vec<Checkpoint<F>> {
+        vec![]
+    }
+
+    /// Keeps each written leaf, or forgets it for `None`, with the writes of the next `commit`, so
+    /// that `MerkleTree::open_with_store` reopens the tree from the store alone. Stores that only
+    /// hold nodes, as the in-memory ones do, ignore them.
+    fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        _writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+    }
+
+    /// Leaves of a tree of `height` levels kept by earlier commits, in any order.
+    fn leaves<V: Leafable<F>>(&self, _height: usize) -> Vec<(TreePath, V)> {
+        vec![]
+    }
+
+    /// Every stored node, in any order.
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_>;
+
+    /// Makes every write since the previous call durable at once. The tree calls it after each
+    /// version, so a persistent store that applies writes atomically here reopens with the nodes
+    /// of either the previous or the new version after a crash, never a mix of both.
+    fn commit(&mut self) {}
+
+    fn contains(&self, path: &TreePath) -> bool {
+        self.get(path).is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every node for which `keep` returns `false`.
+    fn retain(&mut self, mut keep: impl FnMut(&TreePath) -> bool) {
+        let removed: Vec<TreePath> = self
+            .iter()
+            .map(|(path, _)| path)
+            .filter(|path| !keep(path))
+            .collect();
+        for path in removed {
+            self.remove(&path);
+        }
+    }
+}
+
+/// Default in-memory store, whose clones share storage until either is updated.
+impl<F: PrimeField> NodeStore<F> for im::HashMap<TreePath, F> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        im::HashMap::get(self, path).copied()
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        im::HashMap::insert(self, path, hash)
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        im::HashMap::remove(self, path)
+    }
+
+    fn len(&self) -> usize {
+        im::HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        im::HashMap::clear(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        Box::new(im::HashMap::iter(self).map(|(path, h)| (path.clone(), *h)))
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&TreePath) -> bool) {
+        im::HashMap::retain(self, |path, _| keep(path))
+    }
+}
+
+impl<F: PrimeField> NodeStore<F> for HashMap<TreePath, F> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        HashMap::get(self, path).copied()
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        HashMap::insert(self, path, hash)
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        HashMap::remove(self, path)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        Box::new(HashMap::iter(self).map(|(path, h)| (path.clone(), *h)))
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&TreePath) -> bool) {
+        HashMap::retain(self, |path, _| keep(path))
+    }
+}
+
+/// Async counterpart of `NodeStore` for storage behind a network or an async database, used by
+/// `AsyncMerkleTree`. Every `NodeStore` that can be cloned is one whose futures are ready at
+/// once, which leaves other types free to implement this trait.
+pub trait AsyncNodeStore<F: PrimeField> {
+    type Error;
+
+    fn get(&self, path: &TreePath) -> impl Future<Output = Result<Option<F>, Self::Error>> + Send;
+
+    /// Like `NodeStore::write_batch`, the only way the tree writes nodes, so a network store
+    /// sends each version in a single request.
+    fn write_batch(
+        &mut self,
+        writes: Vec<(TreePath, Option<F>)>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Like `NodeStore::commit`.
+    fn commit(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+impl<F: PrimeField, S: NodeStore<F> + Clone> AsyncNodeStore<F> for S {
+    type Error = Infallible;
+
+    fn get(&self, path: &TreePath) -> impl Future<Output = Result<Option<F>, Infallible>> + Send {
+        std::future::ready(Ok(NodeStore::get(self, path)))
+    }
+
+    fn write_batch(
+        &mut self,
+        writes: Vec<(TreePath, Option<F>)>,
+    ) -> impl Future<Output = Result<(), Infallible>> + Send {
+        NodeStore::write_batch(self, writes);
+        std::future::ready(Ok(()))
+    }
+
+    fn commit(&mut self) -> impl Future<Output = Result<(), Infallible>> + Send {
+        NodeStore::commit(self);
+        std::future::ready(Ok(()))
+    }
+}
+
+/// Writes of a store since its last commit, read before the committed nodes.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PendingWrites<F: PrimeField> {
+    /// Every stored node is removed before `nodes` are written.
+    pub(crate) cleared: bool,
+    /// New hash of each written node, or `None` if it is removed.
+    pub(crate) nodes: HashMap<TreePath, Option<F>>,
+    /// Number of nodes after the pending writes, once any is made.
+    pub(crate) len: Option<usize>,
+    /// Checkpoints to write with the nodes.
+    pub(crate) checkpoints: Vec<Checkpoint<F>>,
+    /// `Leafable::to_stored` value of each written leaf, or `None` if it is removed. Every stored
+    /// leaf is removed with the nodes when `cleared`.
+    pub(crate) leaves: HashMap<TreePath, Option<Vec<u8>>>,
+}
+
+impl<F: PrimeField> PendingWrites<F> {
+    /// Hash of the node at `path` if the pending writes decide it, `None` to read the store.
+    pub(crate) fn get(&self, path: &TreePath) -> Option<Option<F>> {
+        match self.nodes.get(path) {
+            Some(h) => Some(*h),
+            None if self.cleared => Some(None),
+            None => None,
+        }
+    }
+
+    pub(crate) fn write(&mut self, path: TreePath, hash: Option<F>, len: usize) {
+        self.nodes.insert(path, hash);
+        self.len = Some(len);
+    }
+
+    /// Removes every node and leaf, keeping the pending checkpoints.
+    pub(crate) fn clear(&mut self) {
+        self.cleared = true;
+        self.nodes.clear();
+        self.leaves.clear();
+        self.len = Some(0);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.cleared
+            && self.nodes.is_empty()
+            && self.checkpoints.is_empty()
+            && self.leaves.is_empty()
+    }
+
+    #[cfg(any(
+        feature = "rocksdb-store",
+        feature = "sled-store",
+        feature = "sqlite-store"
+    ))]
+    pub(crate) fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+        for (path, leaf) in writes {
+            let value = leaf.map(|leaf| leaf.to_stored().expect("leaf type cannot be stored"));
+            self.leaves.insert(path, value);
+        }
+    }
+
+    /// Leaves of `stored` with the pending writes applied, decoded.
+    #[cfg(any(
+        feature = "rocksdb-store",
+        feature = "sled-store",
+        feature = "sqlite-store"
+    ))]
+    pub(crate) fn leaf_overlay<V: Leafable<F>>(
+        &self,
+        stored: impl Iterator<Item = (TreePath, Vec<u8>)>,
+    ) -> Vec<(TreePath, V)> {
+        stored
+            .filter(|(path, _)| !self.cleared && !self.leaves.contains_key(path))
+            .chain(
+                self.leaves
+                    .iter()
+                    .filter_map(|(path, value)| Some((path.clone(), value.clone()?))),
+            )
+            .map(|(path, value)| (path, V::from_stored(&value).expect("corrupt leaf")))
+            .collect()
+    }
+
+    /// Nodes of `stored` with the pending writes applied.
+    pub(crate) fn overlay<'a>(
+        &'a self,
+        stored: impl Iterator<Item = (TreePath, F)> + 'a,
+    ) -> impl Iterator<Item = (TreePath, F)> + 'a {
+        stored
+            .filter(move |(path, _)| !self.cleared && !self.nodes.contains_key(path))
+            .chain(
+                self.nodes
+                    .iter()
+                    .filter_map(|(path, h)| h.map(|h| (path.clone(), h))),
+            )
+    }
+}
+
+/// Panics unless every leaf of `writes` is its own hash, as field elements are, the only leaves a
+/// store holding just hashes can give back.
+pub(crate) fn assert_own_hash<F: PrimeField, V: Leafable<F>>(
+    writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+) {
+    for leaf in writes.into_iter().filter_map(|(_, leaf)| leaf) {
+        assert!(
+            leaf.to_stored()
+                .is_some_and(|value| value == leaf.hash().to_repr().as_ref()),
+            "node store only keeps leaves that are their own hash"
+        );
+    }
+}
+
+/// Leaves of a tree of `height` levels read back from its leaf hashes among `nodes`.
+pub(crate) fn own_hash_leaves<F: PrimeField, V: Leafable<F>>(
+    nodes: impl Iterator<Item = (TreePath, F)>,
+    height: usize,
+) -> Vec<(TreePath, V)> {
+    nodes
+        .filter(|(path, _)| path.depth() == height)
+        .map(|(path, h)| {
+            let leaf = V::from_stored(h.to_repr().as_ref())
+                .filter(|leaf| leaf.hash() == h)
+                .expect("leaf is not its own hash");
+            (path, leaf)
+        })
+        .collect()
+}
+
+/// Depth of `path` as a big-endian `u32` followed by `TreePath::to_bytes_be`, the key of a node
+/// in a persistent store.
+#[cfg(any(
+    feature = "rocksdb-store",
+    feature = "sled-store",
+    feature = "sqlite-store"
+))]
+pub(crate) fn encode_path(path: &TreePath) -> Vec<u8> {
+    let depth = u32::try_from(path.depth()).expect("path is too deep to store");
+    let mut key = depth.to_be_bytes().to_vec();
+    key.extend_from_slice(&path.to_bytes_be());
+    key
+}
+
+#[cfg(any(
+    feature = "rocksdb-store",
+    feature = "sled-store",
+    feature = "sqlite-store"
+))]
+pub(crate) fn decode_path(key: &[u8]) -> TreePath {
+    let depth = u32::from_be_bytes(key[..4].try_into().expect("corrupt node key"));
+    TreePath::from_bytes_be(&key[4..], depth as usize)
+}
+
+#[cfg(any(
+    feature = "rocksdb-store",
+    feature = "sled-store",
+    feature = "sqlite-store"
+))]
+pub(crate) fn decode_node<F: PrimeField>(value: &[u8]) -> F {
+    let mut repr = F::Repr::default();
+    repr.as_mut().copy_from_slice(value);
+    Option::from(F::from_repr(repr)).expect("corrupt node hash")
+}
+
+/// Key of the checkpoint recorded after the one stored under `last`, if any: a big-endian `u64`
+/// sequence number. Checkpoints sort in the order they were recorded, even when a rollback has
+/// the same version recorded again within the same second.
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn next_checkpoint_key(last: Option<&[u8]>) -> [u8; 8] {
+    let sequence = last.map_or(0, |key| {
+        u64::from_be_bytes(key.try_into().expect("corrupt checkpoint key")) + 1
+    });
+    sequence.to_be_bytes()
+}
+
+/// Version and timestamp as big-endian `u64`s followed by the root representation, the value
+/// of a checkpoint in a persistent store.
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn encode_checkpoint<F: PrimeField>(checkpoint: &Checkpoint<F>) -> Vec<u8> {
+    let mut value = (checkpoint.version as u64).to_be_bytes().to_vec();
+    value.extend_from_slice(&checkpoint.timestamp.to_be_bytes());
+    value.extend_from_slice(checkpoint.root.to_repr().as_ref());
+    value
+}
+
+/// Inverse of `encode_checkpoint`.
+#[cfg(any(feature = "rocksdb-store", feature = "sled-store"))]
+pub(crate) fn decode_checkpoint<F: PrimeField>(value: &[u8]) -> Checkpoint<F> {
+    assert!(value.len() > 16, "corrupt checkpoint");
+    Checkpoint {
+        version: u64::from_be_bytes(value[..8].try_into().unwrap()) as usize,
+        root: decode_node(&value[16..]),
+        timestamp: u64::from_be_bytes(value[8..16].try_into().unwrap()),
+    }
+}
@@ -0,0 +1,430 @@
+use std::hash::{Hash, Hasher};
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    circuits::{MerkleInclusionCircuit, MerkleProcessCircuit},
+    hasher::MerkleHasher,
+    node_store::NodeStore,
+    tree::{compute_merkle_root, index_fits, Leafable, MerkleTree},
+    zero_hashes::zero_hash_ladder,
+};
+
+/// Position of a node, as the child taken at each level from the root down, `true` for the right child.
+///
+/// Conversions from integers and bytes name their bit order explicitly.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TreePath(Vec<bool>);
+
+impl TreePath {
+    pub fn root() -> Self {
+        Self(vec![])
+    }
+
+    /// Path of the `index`-th node at `depth`: the most significant of the `depth` low bits
+    /// of `index` selects the child of the root. This is the order used by `MerkleTree`.
+    pub fn from_index(index: usize, depth: usize) -> Self {
+        let mut path = Self::from_index_le(index, depth);
+        path.0.reverse();
+        path
+    }
+
+    /// Path whose least significant bit of `index` selects the child of the root.
+    pub fn from_index_le(index: usize, depth: usize) -> Self {
+        Self(
+            (0..depth)
+                .map(|i| i < usize::BITS as usize && (index >> i) & 1 == 1)
+                .collect(),
+        )
+    }
+
+    /// Path reading the first `depth` bits of `bytes`, most significant bit of each byte first.
+    pub fn from_bytes_be(bytes: &[u8], depth: usize) -> Self {
+        assert!(
+            depth <= bytes.len() * 8,
+            "not enough bytes for {depth} levels"
+        );
+        Self(
+            (0..depth)
+                .map(|i| (bytes[i / 8] >> (7 - i % 8)) & 1 == 1)
+                .collect(),
+        )
+    }
+
+    /// Inverse of `from_index`. Panics if the path is beyond the first `2^usize::BITS` nodes at its depth.
+    pub fn to_index(&self) -> usize {
+        self.try_to_index().expect("path does not fit in an index")
+    }
+
+    /// Like `to_index`, or `None` if a bit above the width of `usize` is set.
+    pub fn try_to_index(&self) -> Option<usize> {
+        let excess = self.depth().saturating_sub(usize::BITS as usize);
+        if self.0[..excess].iter().any(|&bit| bit) {
+            return None;
+        }
+        Some(
+            self.0[excess..]
+                .iter()
+                .fold(0, |index, &bit| (index << 1) | bit as usize),
+        )
+    }
+
+    /// Bytes holding the path as in `from_bytes_be`, padded with zero bits after the last level.
+    pub fn to_bytes_be(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.depth().div_ceil(8)];
+        for (i, &bit) in self.0.iter().enumerate() {
+            bytes[i / 8] |= (bit as u8) << (7 - i % 8);
+        }
+        bytes
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Children taken from the root down.
+    pub fn bits(&self) -> &[bool] {
+        &self.0
+    }
+
+    /// Whether each node on the path is a right child, from the deepest node upwards.
+    pub fn lr_bits(&self) -> impl Iterator<Item = bool> + '_ {
+        self.0.iter().rev().copied()
+    }
+
+    pub fn push(&mut self, right: bool) {
+        self.0.push(right)
+    }
+
+    /// Moves to the parent and returns whether the node was a right child, or `None` at the root.
+    pub fn pop(&mut self) -> Option<bool> {
+        self.0.pop()
+    }
+
+    pub fn child(&self, right: bool) -> Self {
+        let mut child = self.clone();
+        child.push(right);
+        child
+    }
+
+    pub fn sibling(&self) -> Self {
+        let mut sibling = self.clone();
+        let last = sibling.0.last_mut().expect("the root has no sibling");
+        *last = !*last;
+        sibling
+    }
+
+    pub fn starts_with(&self, prefix: &TreePath) -> bool {
+        self.0.starts_with(&prefix.0)
+    }
+
+    /// Path of `suffix` taken from the node at `self`.
+    pub fn join(&self, suffix: &TreePath) -> Self {
+        let mut path = self.clone();
+        path.0.extend_from_slice(&suffix.0);
+        path
+    }
+
+    /// Heap bytes held by the path.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+/// Collects the children taken from the root down.
+impl FromIterator<bool> for TreePath {
+    fn from_iter<I: IntoIterator<Item = bool>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Proof of the leaf at `index`.
+///
+/// `verify` recomputes the root natively in the same way as `MerkleInclusionCircuit`,
+/// so a proof can be checked off-chain before it is proven in a circuit.
+///
+/// Equality and hashing compare every field. An index with bits above the height is not another
+/// encoding of the same proof: `verify` rejects it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerklePath<F: PrimeField> {
+    /// Siblings from the leaf upwards.
+    pub siblings: Vec<F>,
+    pub index: usize,
+    pub leaf_hash: F,
+}
+
+impl<F: PrimeField> MerklePath<F> {
+    pub fn height(&self) -> usize {
+        self.siblings.len()
+    }
+
+    /// Whether each node on the path is a right child, from the leaf upwards.
+    pub fn lr_bits(&self) -> Vec<bool> {
+        TreePath::from_index(self.index, self.height())
+            .lr_bits()
+            .collect()
+    }
+
+    /// Root implied by the path, with internal nodes hashed by `hasher`, such as the Poseidon
+    /// constants of the tree, or `None` if `index` is not below `2^height()`.
+    pub fn compute_root<H: MerkleHasher<F>>(&self, hasher: &H) -> Option<F> {
+        self.root_for_leaf(hasher, self.leaf_hash)
+    }
+
+    /// Root implied by a leaf hashing to `leaf_hash` at `index`, which need not be `self.leaf_hash`,
+    /// or `None` if `index` is out of range. Comparing it with a trusted root checks a claimed
+    /// value against this path.
+    pub fn root_for_leaf<H: MerkleHasher<F>>(&self, hasher: &H, leaf_hash: F) -> Option<F> {
+        index_fits(self.index, self.height())
+            .then(|| compute_merkle_root(hasher, self.index, leaf_hash, &self.siblings))
+    }
+
+    /// Checks the path against `root` of a tree of `height` levels, which must come from the
+    /// verifier: a shorter path ending at `root` would prove an internal node as a leaf.
+    pub fn verify<H: MerkleHasher<F>>(&self, hasher: &H, root: F, height: usize) -> bool {
+        self.height() == height && self.compute_root(hasher) == Some(root)
+    }
+
+    pub fn to_circuit(&self, constants: PoseidonConstants<F, U2>) -> MerkleInclusionCircuit<F> {
+        MerkleInclusionCircuit {
+            constants,
+            siblings: self.siblings.clone(),
+            index: self.index,
+            value: self.leaf_hash,
+        }
+    }
+}
+
+impl<F: PrimeField> Hash for MerklePath<F> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_fields(&self.siblings, state);
+        self.index.hash(state);
+        hash_fields(&[self.leaf_hash], state);
+    }
+}
+
+impl<F: PrimeField> MerklePath<F> {
+    /// Drops the siblings that are roots of empty subtrees, which `decompress` restores
+    /// from `constants` and `empty_hash`, the hash of the empty leaf of the tree.
+    pub fn compress(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        empty_hash: F,
+    ) -> CompressedMerklePath<F> {
+        let zero_hashes = zero_hash_ladder(constants, empty_hash, self.height());
+        let mut defaults = vec![0; self.height().div_ceil(8)];
+        let mut siblings = vec![];
+        for (i, &sibling) in self.siblings.iter().enumerate() {
+            if sibling == zero_hashes[i] {
+                defaults[i / 8] |= 1 << (i % 8);
+            } else {
+                siblings.push(sibling);
+            }
+        }
+
+        CompressedMerklePath {
+            height: self.height(),
+            defaults,
+            siblings,
+            index: self.index,
+            leaf_hash: self.leaf_hash,
+        }
+    }
+}
+
+/// `MerklePath` without the siblings that are roots of empty subtrees.
+///
+/// Equality and hashing compare every field, so a path that leaves an empty sibling unflagged
+/// differs from its `canonicalize`d form.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressedMerklePath<F: PrimeField> {
+    pub height: usize,
+    /// Bit `i % 8` of byte `i / 8` is set if the `i`-th sibling from the leaf is an empty subtree.
+    pub defaults: Vec<u8>,
+    /// The other siblings, from the leaf upwards.
+    pub siblings: Vec<F>,
+    pub index: usize,
+    pub leaf_hash: F,
+}
+
+impl<F: PrimeField> CompressedMerklePath<F> {
+    /// The same proof with every sibling that is the root of an empty subtree flagged in
+    /// `defaults`, which is how `MerklePath::compress` encodes it. Returns `None` if the proof
+    /// does not decompress.
+    pub fn canonicalize(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        empty_hash: F,
+        max_height: usize,
+    ) -> Option<Self> {
+        Some(
+            self.decompress(constants, empty_hash, max_height)?
+                .compress(constants, empty_hash),
+        )
+    }
+
+    /// Restores the omitted siblings, or returns `None` if the bitmap does not match the siblings,
+    /// sets bits past the height, or `index` does not fit in the tree.
+    ///
+    /// `height` comes from the proof, so it is checked against `max_height` before the shared
+    /// zero-hash ladder is extended to it.
+    pub fn decompress(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        empty_hash: F,
+        max_height: usize,
+    ) -> Option<MerklePath<F>> {
+        if self.height > max_height
+            || self.defaults.len() != self.height.div_ceil(8)
+            || !index_fits(self.index, self.height)
+        {
+            return None;
+        }
+        if let Some(&last) = self.defaults.last() {
+            if !self.height.is_multiple_of(8) && last >> (self.height % 8) != 0 {
+                return None;
+            }
+        }
+
+        let zero_hashes = zero_hash_ladder(constants, empty_hash, self.height);
+        let mut present = self.siblings.iter();
+        let mut siblings = Vec::with_capacity(self.height);
+        for (i, &zero_hash) in zero_hashes[..self.height].iter().enumerate() {
+            if (self.defaults[i / 8] >> (i % 8)) & 1 == 1 {
+                siblings.push(zero_hash);
+            } else {
+                siblings.push(*present.next()?);
+            }
+        }
+        if present.next().is_some() {
+            return None;
+        }
+
+        Some(MerklePath {
+            siblings,
+            index: self.index,
+            leaf_hash: self.leaf_hash,
+        })
+    }
+}
+
+impl<F: PrimeField> Hash for CompressedMerklePath<F> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.height.hash(state);
+        Hash::hash(&self.defaults, state);
+        hash_fields(&self.siblings, state);
+        self.index.hash(state);
+        hash_fields(&[self.leaf_hash], state);
+    }
+}
+
+/// Update of the leaf at `index` from `old_value` to `new_value`, checked natively in the same way
+/// as the `MerkleProcessCircuit` step with the same fields.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProcessProof<F: PrimeField> {
+    /// Siblings from the leaf upwards, shared by the old and new roots.
+    pub siblings: Vec<F>,
+    pub index: usize,
+    pub old_value: F,
+    pub new_value: F,
+}
+
+impl<F: PrimeField> MerkleProcessProof<F> {
+    /// Root before the update, or `None` if `index` is not below `2^siblings.len()`.
+    pub fn old_root(&self, constants: &PoseidonConstants<F, U2>) -> Option<F> {
+        self.root_for(constants, self.old_value)
+    }
+
+    /// Root after the update, or `None` if `index` is out of range.
+    pub fn new_root(&self, constants: &PoseidonConstants<F, U2>) -> Option<F> {
+        self.root_for(constants, self.new_value)
+    }
+
+    fn root_for(&self, constants: &PoseidonConstants<F, U2>, value: F) -> Option<F> {
+        index_fits(self.index, self.siblings.len())
+            .then(|| compute_merkle_root(constants, self.index, value, &self.siblings))
+    }
+
+    /// Checks that the update takes a tree of `height` levels from `old_root` to `new_root`.
+    pub fn verify(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        old_root: F,
+        new_root: F,
+        height: usize,
+    ) -> bool {
+        self.siblings.len() == height
+            && self.old_root(constants) == Some(old_root)
+            && self.new_root(constants) == Some(new_root)
+    }
+
+    pub fn to_circuit(&self, constants: PoseidonConstants<F, U2>) -> MerkleProcessCircuit<F> {
+        MerkleProcessCircuit {
+            constants,
+            siblings: self.siblings.clone(),
+            index: self.index,
+            old_value: self.old_value,
+            new_value: self.new_value,
+        }
+    }
+}
+
+impl<F: PrimeField> Hash for MerkleProcessProof<F> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_fields(&self.siblings, state);
+        self.index.hash(state);
+        hash_fields(&[self.old_value, self.new_value], state);
+    }
+}
+
+impl<F: PrimeField> MerkleProcessCircuit<F> {
+    pub fn to_proof(&self) -> MerkleProcessProof<F> {
+        MerkleProcessProof {
+            siblings: self.siblings.clone(),
+            index: self.index,
+            old_value: self.old_value,
+            new_value: self.new_value,
+        }
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F>, S: NodeStore<F>> MerkleTree<F, V, S> {
+    /// Like `prove`, together with the index and hash of the proven leaf.
+    pub fn prove_path(&self, index: usize) -> MerklePath<F> {
+        MerklePath {
+            siblings: self.prove(index),
+            index,
+            leaf_hash: self.get_leaf(index).hash(),
+        }
+    }
+
+    /// Sets the empty leaf at `index` to `leaf` and returns the update, whose old value proves
+    /// that the slot was empty. Returns `None` and leaves the tree unchanged if it is occupied.
+    pub fn prove_insert(&mut self, index: usize, leaf: V) -> Option<MerkleProcessProof<F>> {
+        let siblings = self.prove_empty(index)?;
+        let new_value = leaf.hash();
+        let old_value = self.update(index, leaf).hash();
+
+        Some(MerkleProcessProof {
+            siblings,
+            index,
+            old_value,
+            new_value,
+        })
+    }
+}
+
+// Field elements do not implement `Hash`, so their canonical representations are hashed instead.
+fn hash_fields<F: PrimeField, H: Hasher>(values: &[F], state: &mut H) {
+    values.len().hash(state);
+    for value in values {
+        value.to_repr().as_ref().hash(state);
+    }
+}
@@ -0,0 +1,405 @@
+use bellperson::{
+    gadgets::{
+        boolean::{AllocatedBit, Boolean},
+        num::AllocatedNum,
+    },
+    ConstraintSystem, SynthesisError,
+};
+use ff::PrimeField;
+use generic_array::typenum::U4;
+use neptune::{circuit::poseidon_hash, poseidon::PoseidonConstants, Poseidon};
+use nova_snark::traits::circuit::StepCircuit;
+use serde::{Deserialize, Serialize};
+
+use super::gadgets::{enforce_equal, index_bits};
+use crate::public_inputs::ZLayout;
+
+/// Children of the parent of `node`, at `position` among them, in order.
+///
+/// The siblings of a node in a quaternary tree are `[partner, left, right]`: `partner` is the
+/// other node of the pair holding the node, at `position ^ 1`, and `left` and `right` are the
+/// other pair in order. The circuits then order the children with three conditional swaps.
+pub fn children<F: PrimeField>(node: F, siblings: &[F; 3], position: usize) -> [F; 4] {
+    let [partner, left, right] = *siblings;
+    let pair = if position & 1 == 1 {
+        [partner, node]
+    } else {
+        [node, partner]
+    };
+    if position & 2 == 2 {
+        [left, right, pair[0], pair[1]]
+    } else {
+        [pair[0], pair[1], left, right]
+    }
+}
+
+/// Siblings of the child at `position` of `children`, in the order `children` takes them.
+pub fn siblings_of<F: PrimeField>(children: &[F; 4], position: usize) -> [F; 3] {
+    let pair = position & 2;
+    let other = 2 - pair;
+    [children[position ^ 1], children[other], children[other + 1]]
+}
+
+/// Root of a quaternary tree from the hash of the leaf at `index` and its siblings from the leaf
+/// level up.
+pub fn compute_quaternary_root<F: PrimeField>(
+    constants: &PoseidonConstants<F, U4>,
+    index: usize,
+    leaf_hash: F,
+    siblings: &[[F; 3]],
+) -> F {
+    siblings
+        .iter()
+        .enumerate()
+        .fold(leaf_hash, |node, (level, siblings)| {
+            let position = digit(index, level);
+            Poseidon::new_with_preimage(&children(node, siblings, position), constants).hash()
+        })
+}
+
+/// Proof of the leaf at `index` of a quaternary tree, with one set of siblings per level from
+/// the leaf level up, as `children` takes them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuaternaryPath<F: PrimeField> {
+    pub index: usize,
+    pub siblings: Vec<[F; 3]>,
+}
+
+impl<F: PrimeField> QuaternaryPath<F> {
+    pub fn compute_root(&self, constants: &PoseidonConstants<F, U4>, leaf_hash: F) -> F {
+        compute_quaternary_root(constants, self.index, leaf_hash, &self.siblings)
+    }
+}
+
+/// Base-4 digit of `index` selecting the node at `level` above the leaves.
+fn digit(index: usize, level: usize) -> usize {
+    index.checked_shr(2 * level as u32).unwrap_or(0) & 3
+}
+
+/// Children of the parent of `node` in a circuit, ordered by the bits of its position.
+fn synthesize_children<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    node: &AllocatedNum<F>,
+    siblings: &[AllocatedNum<F>; 3],
+    bits: [&Boolean; 2],
+) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+    let [partner, left, right] = siblings;
+    let (pair_left, pair_right) = AllocatedNum::conditionally_reverse(
+        cs.namespace(|| "reverse pair"),
+        node,
+        partner,
+        bits[0],
+    )?;
+    let (first, third) = AllocatedNum::conditionally_reverse(
+        cs.namespace(|| "reverse left"),
+        &pair_left,
+        left,
+        bits[1],
+    )?;
+    let (second, fourth) = AllocatedNum::conditionally_reverse(
+        cs.namespace(|| "reverse right"),
+        &pair_right,
+        right,
+        bits[1],
+    )?;
+
+    Ok(vec![first, second, third, fourth])
+}
+
+fn alloc_siblings<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    siblings: &[F; 3],
+) -> Result<[AllocatedNum<F>; 3], SynthesisError> {
+    let [partner, left, right] = *siblings;
+    Ok([
+        AllocatedNum::alloc(cs.namespace(|| "allocate partner"), || Ok(partner))?,
+        AllocatedNum::alloc(cs.namespace(|| "allocate left"), || Ok(left))?,
+        AllocatedNum::alloc(cs.namespace(|| "allocate right"), || Ok(right))?,
+    ])
+}
+
+/// Arity-4 counterpart of `InternalHashCircuit`: one level of a path, from a node to its parent.
+#[derive(Clone, Debug)]
+pub struct QuaternaryHashCircuit<F: PrimeField> {
+    pub constants: PoseidonConstants<F, U4>,
+    pub siblings: [F; 3],
+    /// Position of the node among the children, below 4.
+    pub position: usize,
+}
+
+impl<F> StepCircuit<F> for QuaternaryHashCircuit<F>
+where
+    F: PrimeField,
+{
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // child node
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        assert!(self.position < 4, "position of one of 4 children");
+        let siblings = alloc_siblings(cs.namespace(|| "allocate siblings"), &self.siblings)?;
+        let low = AllocatedBit::alloc(
+            cs.namespace(|| "allocate low bit"),
+            Some(self.position & 1 == 1),
+        )?;
+        let high = AllocatedBit::alloc(
+            cs.namespace(|| "allocate high bit"),
+            Some(self.position & 2 == 2),
+        )?;
+        let children = synthesize_children(
+            cs.namespace(|| "order children"),
+            &z[0],
+            &siblings,
+            [&low.into(), &high.into()],
+        )?;
+        let output = poseidon_hash(
+            cs.namespace(|| "calculate poseidon"),
+            children,
+            &self.constants,
+        )?;
+
+        Ok(vec![output])
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        debug_assert_eq!(z.len(), self.arity());
+
+        let preimage = children(z[0], &self.siblings, self.position);
+        let mut poseidon = Poseidon::new_with_preimage(&preimage, &self.constants);
+
+        vec![poseidon.hash()]
+    }
+}
+
+/// Arity-4 counterpart of `MerkleInclusionCircuit`: the root above the leaf hash `value` at
+/// `index`, with one set of siblings per level from the leaf level up, as `children` takes them.
+///
+/// A tree of `4^depth` leaves is half as deep as the binary tree of the same capacity, so a path
+/// takes half as many Poseidon hashes, of four elements instead of two. The base-4 digits of
+/// `index` are selected by pairs of bits of its decomposition, the low bit first.
+#[derive(Clone, Debug)]
+pub struct QuaternaryInclusionCircuit<F: PrimeField> {
+    pub constants: PoseidonConstants<F, U4>,
+    pub siblings: Vec<[F; 3]>,
+    pub index: usize,
+    pub value: F,
+}
+
+impl<F> QuaternaryInclusionCircuit<F>
+where
+    F: PrimeField,
+{
+    pub fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let value = AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(self.value))?;
+        self.synthesize_with_value(cs, value)
+    }
+
+    /// Like `synthesize`, for a leaf hash already allocated by the caller. `self.value` is ignored.
+    pub fn synthesize_with_value<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        value: AllocatedNum<F>,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let index = AllocatedNum::alloc(cs.namespace(|| "allocate index"), || {
+            Ok(F::from(self.index as u64))
+        })?;
+        self.synthesize_with_index(cs, value, &index)
+    }
+
+    /// Like `synthesize_with_value`, for an index allocated by the caller. `self.index` is ignored.
+    pub fn synthesize_with_index<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        value: AllocatedNum<F>,
+        index: &AllocatedNum<F>,
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let bits = index_bits(
+            cs.namespace(|| "decompose index"),
+            index,
+            2 * self.siblings.len(),
+        )?;
+        let siblings = self.alloc_siblings(cs)?;
+        self.synthesize_with_bits(cs, value, &bits, &siblings)
+    }
+
+    /// Allocates the siblings of each level, from the leaf level up, for `synthesize_with_bits`.
+    pub fn alloc_siblings<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+    ) -> Result<Vec<[AllocatedNum<F>; 3]>, SynthesisError> {
+        self.siblings
+            .iter()
+            .enumerate()
+            .map(|(i, siblings)| {
+                alloc_siblings(cs.namespace(|| format!("allocate siblings {i}")), siblings)
+            })
+            .collect()
+    }
+
+    /// Like `synthesize_with_index`, for the bits of an index from `gadgets::index_bits`, two
+    /// per level, and siblings from `alloc_siblings`. An update passes the same bits and siblings
+    /// to its old and new paths. `self.siblings` is ignored.
+    pub fn synthesize_with_bits<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        value: AllocatedNum<F>,
+        bits: &[Boolean],
+        siblings: &[[AllocatedNum<F>; 3]],
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        assert_eq!(bits.len(), 2 * siblings.len(), "two bits per level");
+        let mut result = value;
+        for (i, (bits, siblings)) in bits.chunks(2).zip(siblings).enumerate() {
+            let mut cs = cs.namespace(|| format!("calculate parent hash {i}"));
+            let children = synthesize_children(
+                cs.namespace(|| "order children"),
+                &result,
+                siblings,
+                [&bits[0], &bits[1]],
+            )?;
+            result = poseidon_hash(
+                cs.namespace(|| "calculate poseidon"),
+                children,
+                &self.constants,
+            )?;
+        }
+
+        Ok(vec![result]) // root hash
+    }
+
+    pub fn output(&self) -> Vec<F> {
+        vec![compute_quaternary_root(
+            &self.constants,
+            self.index,
+            self.value,
+            &self.siblings,
+        )]
+    }
+}
+
+/// Like the step of `MerkleInclusionCircuit`, the root held in the state passes on unchanged.
+/// The state follows `ZLayout::MERKLE_PROCESS`.
+impl<F> StepCircuit<F> for QuaternaryInclusionCircuit<F>
+where
+    F: PrimeField,
+{
+    fn arity(&self) -> usize {
+        ZLayout::MERKLE_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // root
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        let result =
+            QuaternaryInclusionCircuit::synthesize(self, &mut cs.namespace(|| "calculate root"))?;
+
+        enforce_equal(cs, || "verify root", &result[0], &z[0]);
+
+        Ok(vec![z[0].clone()]) // root
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        assert_eq!(
+            QuaternaryInclusionCircuit::output(self)[0],
+            z[0],
+            "root mismatch"
+        );
+
+        vec![z[0]]
+    }
+}
+
+/// Arity-4 counterpart of `MerkleProcessCircuit`: replaces the leaf hash `old_value` at `index`
+/// with `new_value`. The state follows `ZLayout::MERKLE_PROCESS`.
+#[derive(Clone, Debug)]
+pub struct QuaternaryProcessCircuit<F: PrimeField> {
+    pub constants: PoseidonConstants<F, U4>,
+    pub siblings: Vec<[F; 3]>,
+    pub index: usize,
+    pub old_value: F,
+    pub new_value: F,
+}
+
+impl<F> QuaternaryProcessCircuit<F>
+where
+    F: PrimeField,
+{
+    fn inclusion(&self, value: F) -> QuaternaryInclusionCircuit<F> {
+        QuaternaryInclusionCircuit {
+            constants: self.constants.clone(),
+            siblings: self.siblings.clone(),
+            index: self.index,
+            value,
+        }
+    }
+
+    /// Root implied by the old value, which must match the incoming state.
+    pub fn old_root(&self) -> F {
+        self.inclusion(self.old_value).output()[0]
+    }
+
+    pub fn new_root(&self) -> F {
+        self.inclusion(self.new_value).output()[0]
+    }
+}
+
+impl<F> StepCircuit<F> for QuaternaryProcessCircuit<F>
+where
+    F: PrimeField,
+{
+    fn arity(&self) -> usize {
+        ZLayout::MERKLE_PROCESS.arity()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        z: &[AllocatedNum<F>], // old root
+    ) -> Result<Vec<AllocatedNum<F>>, SynthesisError> {
+        // Both paths take one decomposition of the index and one set of siblings, so the new root
+        // is the old one with only the leaf replaced.
+        let index = AllocatedNum::alloc(cs.namespace(|| "allocate index"), || {
+            Ok(F::from(self.index as u64))
+        })?;
+        let bits = index_bits(
+            cs.namespace(|| "decompose index"),
+            &index,
+            2 * self.siblings.len(),
+        )?;
+        let siblings = self.inclusion(self.old_value).alloc_siblings(cs)?;
+
+        let old_result = {
+            let mut cs = cs.namespace(|| "calculate old root");
+            let value =
+                AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(self.old_value))?;
+            self.inclusion(self.old_value)
+                .synthesize_with_bits(&mut cs, value, &bits, &siblings)?
+        };
+
+        enforce_equal(cs, || "verify old root", &old_result[0], &z[0]);
+
+        let new_result = {
+            let mut cs = cs.namespace(|| "calculate new root");
+            let value =
+                AllocatedNum::alloc(cs.namespace(|| "allocate value"), || Ok(self.new_value))?;
+            self.inclusion(self.new_value)
+                .synthesize_with_bits(&mut cs, value, &bits, &siblings)?
+        };
+
+        Ok(vec![new_result[0].clone()]) // new root
+    }
+
+    fn output(&self, z: &[F]) -> Vec<F> {
+        assert_eq!(self.old_root(), z[0], "old root mismatch");
+
+        vec![self.new_root()]
+    }
+}
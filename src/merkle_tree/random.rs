@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+
+use ff::PrimeField;
+
+use super::tree::MerkleTree;
+use crate::rng::{RngProvider, SeededRng};
+
+impl<F: PrimeField> MerkleTree<F, F> {
+    /// Tree with a `density` fraction of its leaves set to random values, the same for every `seed`.
+    ///
+    /// Meant for benchmarks and examples, so that they measure comparable trees.
+    pub fn random(height: usize, density: f64, seed: u64) -> Self {
+        Self::random_with(height, density, &mut SeededRng::chacha8_from_seed_u64(seed)).0
+    }
+
+    /// Like `random`, drawing from `rng`, and returns the `RngProvider::source` to record next to the tree.
+    pub fn random_with(height: usize, density: f64, rng: &mut impl RngProvider) -> (Self, String) {
+        assert!((0.0..=1.0).contains(&density), "density is a fraction");
+        assert!(
+            height < usize::BITS as usize,
+            "tree is too large to populate"
+        );
+
+        let capacity = 1usize << height;
+        let count = (density * capacity as f64).round() as usize;
+
+        // Rejection sampling of whichever of the occupied and empty leaves are fewer.
+        let mut sampled = HashSet::new();
+        while sampled.len() < count.min(capacity - count) {
+            sampled.insert((rng.next_u64() as usize) & (capacity - 1));
+        }
+        let mut indices: Vec<usize> = if count <= capacity - count {
+            sampled.into_iter().collect()
+        } else {
+            (0..capacity).filter(|i| !sampled.contains(i)).collect()
+        };
+        indices.sort_unstable();
+
+        let leaves: Vec<(usize, F)> = indices
+            .into_iter()
+            .map(|index| (index, F::random(&mut *rng)))
+            .collect();
+        let mut tree = Self::new(height);
+        tree.update_batch(&leaves);
+
+        (tree, rng.source())
+    }
+}
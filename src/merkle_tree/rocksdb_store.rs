@@ -0,0 +1,267 @@
+use std::{fmt, path::Path, sync::Arc};
+
+use ff::PrimeField;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+
+use super::{
+    dedup_store::{
+        decode_dedup_node, decode_dedup_root, encode_dedup_key, encode_dedup_node,
+        encode_dedup_root, DedupBackend, DedupNode,
+    },
+    node_store::{
+        decode_checkpoint, decode_node, decode_path, encode_checkpoint, encode_path,
+        next_checkpoint_key, NodeStore, PendingWrites,
+    },
+    path::TreePath,
+    tree::{Checkpoint, Leafable},
+};
+
+/// Prefix of the key of every node, followed by its path from `encode_path`.
+const NODE_PREFIX: u8 = b'n';
+/// Prefix of the key of every leaf, followed by its path from `encode_path`.
+const LEAF_PREFIX: u8 = b'l';
+/// Prefix of the key of every checkpoint, followed by its `next_checkpoint_key`.
+const CHECKPOINT_PREFIX: u8 = b'c';
+/// Key of the number of stored nodes, as a big-endian `u64`.
+const LEN_KEY: &[u8] = b"m:len";
+/// Prefix of the key of every node of a `DedupNodeStore` pool, followed by its `encode_dedup_key`.
+const DEDUP_NODE_PREFIX: u8 = b'd';
+/// Prefix of the key of the root of every named `DedupNodeStore`, followed by its name.
+const DEDUP_ROOT_PREFIX: u8 = b'r';
+
+/// Node store in a RocksDB database, so that the nodes of a tree survive restarts.
+///
+/// Writes are buffered until `commit` and then written as a single RocksDB write batch, which
+/// RocksDB logs before applying, so the stored nodes always match a committed version of the tree.
+/// Leaves, keyed by path, and checkpoints are written in the same batch.
+/// As a `DedupBackend`, it keeps a `DedupNodeStore` pool under its own keys, each commit of which
+/// is likewise a single batch.
+/// A copy would share the database, so neither the store nor a tree using it can be cloned.
+/// Reopen a tree with `MerkleTree::open_with_store`.
+///
+/// Panics if the database fails or holds a value that is not a field element.
+pub struct RocksDbNodeStore<F: PrimeField> {
+    db: Arc<DB>,
+    pending: PendingWrites<F>,
+}
+
+impl<F: PrimeField> RocksDbNodeStore<F> {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, rocksdb::Error> {
+        Ok(Self {
+            db: Arc::new(DB::open_default(path)?),
+            pending: PendingWrites::default(),
+        })
+    }
+
+    /// Flushes the memtables to disk, so the nodes do not depend on replaying the write-ahead log.
+    pub fn flush(&self) -> Result<(), rocksdb::Error> {
+        self.db.flush()
+    }
+
+    /// Key of the last stored checkpoint, without its prefix.
+    fn last_checkpoint_key(&self) -> Option<Vec<u8>> {
+        let end = [CHECKPOINT_PREFIX + 1];
+        self.db
+            .iterator(IteratorMode::From(&end[..], Direction::Reverse))
+            .map(|entry| entry.expect("node store read failed"))
+            .find(|(key, _)| key.first() <= Some(&CHECKPOINT_PREFIX))
+            .filter(|(key, _)| key.first() == Some(&CHECKPOINT_PREFIX))
+            .map(|(key, _)| key[1..].to_vec())
+    }
+
+    fn stored_len(&self) -> usize {
+        self.db
+            .get(LEN_KEY)
+            .expect("node store read failed")
+            .map_or(0, |bytes| {
+                u64::from_be_bytes(bytes.try_into().expect("corrupt node count")) as usize
+            })
+    }
+}
+
+impl<F: PrimeField> fmt::Debug for RocksDbNodeStore<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RocksDbNodeStore")
+            .field("path", &self.db.path())
+            .finish()
+    }
+}
+
+impl<F: PrimeField> NodeStore<F> for RocksDbNodeStore<F> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        if let Some(h) = self.pending.get(path) {
+            return h;
+        }
+        let value = self
+            .db
+            .get(path_key(NODE_PREFIX, path))
+            .expect("node store read failed")?;
+        Some(decode_node(&value))
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        let old = self.get(&path);
+        let len = self.len() + old.is_none() as usize;
+        self.pending.write(path, Some(hash), len);
+
+        old
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        let old = self.get(path)?;
+        let len = self.len() - 1;
+        self.pending.write(path.clone(), None, len);
+
+        Some(old)
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len.unwrap_or_else(|| self.stored_len())
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    fn record_checkpoint(&mut self, checkpoint: Checkpoint<F>) {
+        self.pending.checkpoints.push(checkpoint);
+    }
+
+    fn checkpoints(&self) -> Vec<Checkpoint<F>> {
+        let start = [CHECKPOINT_PREFIX];
+        self.db
+            .iterator(IteratorMode::From(&start[..], Direction::Forward))
+            .map(|entry| entry.expect("node store read failed"))
+            .take_while(|(key, _)| key.first() == Some(&CHECKPOINT_PREFIX))
+            .map(|(_, value)| decode_checkpoint(&value))
+            .collect()
+    }
+
+    fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+        self.pending.write_leaves(writes);
+    }
+
+    fn leaves<V: Leafable<F>>(&self, _height: usize) -> Vec<(TreePath, V)> {
+        let start = [LEAF_PREFIX];
+        let stored = self
+            .db
+            .iterator(IteratorMode::From(&start[..], Direction::Forward))
+            .map(|entry| entry.expect("node store read failed"))
+            .take_while(|(key, _)| key.first() == Some(&LEAF_PREFIX))
+            .map(|(key, value)| (decode_path(&key[1..]), value.to_vec()));
+        self.pending.leaf_overlay(stored)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        let start = [NODE_PREFIX];
+        let stored = self
+            .db
+            .iterator(IteratorMode::From(&start[..], Direction::Forward))
+            .map(|entry| entry.expect("node store read failed"))
+            .take_while(|(key, _)| key.first() == Some(&NODE_PREFIX))
+            .map(|(key, value)| (decode_path(&key[1..]), decode_node(&value)));
+        Box::new(self.pending.overlay(stored))
+    }
+
+    fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        let mut batch = WriteBatch::default();
+        if pending.cleared {
+            batch.delete_range([NODE_PREFIX], [NODE_PREFIX + 1]);
+            batch.delete_range([LEAF_PREFIX], [LEAF_PREFIX + 1]);
+        }
+        for (path, h) in pending.nodes {
+            match h {
+                Some(h) => batch.put(path_key(NODE_PREFIX, &path), h.to_repr()),
+                None => batch.delete(path_key(NODE_PREFIX, &path)),
+            }
+        }
+        for (path, value) in pending.leaves {
+            match value {
+                Some(value) => batch.put(path_key(LEAF_PREFIX, &path), value),
+                None => batch.delete(path_key(LEAF_PREFIX, &path)),
+            }
+        }
+        if let Some(len) = pending.len {
+            batch.put(LEN_KEY, (len as u64).to_be_bytes());
+        }
+        let mut last = self.last_checkpoint_key();
+        for checkpoint in &pending.checkpoints {
+            let mut key = vec![CHECKPOINT_PREFIX];
+            key.extend_from_slice(&next_checkpoint_key(last.as_deref()));
+            batch.put(&key, encode_checkpoint(checkpoint));
+            last = Some(key[1..].to_vec());
+        }
+        self.db.write(batch).expect("node store write failed");
+    }
+}
+
+fn path_key(prefix: u8, path: &TreePath) -> Vec<u8> {
+    let mut key = vec![prefix];
+    key.extend_from_slice(&encode_path(path));
+    key
+}
+
+impl<F: PrimeField> DedupBackend<F> for RocksDbNodeStore<F> {
+    fn get_node(&self, depth: usize, hash: &F) -> Option<DedupNode<F>> {
+        let value = self
+            .db
+            .get(dedup_key(DEDUP_NODE_PREFIX, &encode_dedup_key(depth, hash)))
+            .expect("node store read failed")?;
+        Some(decode_dedup_node(&value))
+    }
+
+    fn get_root(&self, name: &str) -> Option<(F, usize)> {
+        let value = self
+            .db
+            .get(dedup_key(DEDUP_ROOT_PREFIX, name.as_bytes()))
+            .expect("node store read failed")?;
+        Some(decode_dedup_root(&value))
+    }
+
+    fn num_nodes(&self) -> usize {
+        let start = [DEDUP_NODE_PREFIX];
+        self.db
+            .iterator(IteratorMode::From(&start[..], Direction::Forward))
+            .map(|entry| entry.expect("node store read failed"))
+            .take_while(|(key, _)| key.first() == Some(&DEDUP_NODE_PREFIX))
+            .count()
+    }
+
+    fn write(
+        &mut self,
+        nodes: Vec<(usize, F, Option<DedupNode<F>>)>,
+        root: Option<(&str, Option<(F, usize)>)>,
+    ) {
+        let mut batch = WriteBatch::default();
+        for (depth, h, node) in nodes {
+            let key = dedup_key(DEDUP_NODE_PREFIX, &encode_dedup_key(depth, &h));
+            match node {
+                Some(node) => batch.put(key, encode_dedup_node(&node)),
+                None => batch.delete(key),
+            }
+        }
+        if let Some((name, root)) = root {
+            let key = dedup_key(DEDUP_ROOT_PREFIX, name.as_bytes());
+            match root {
+                Some(root) => batch.put(key, encode_dedup_root(root)),
+                None => batch.delete(key),
+            }
+        }
+        self.db.write(batch).expect("node store write failed");
+    }
+}
+
+fn dedup_key(prefix: u8, id: &[u8]) -> Vec<u8> {
+    let mut key = vec![prefix];
+    key.extend_from_slice(id);
+    key
+}
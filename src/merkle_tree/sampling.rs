@@ -0,0 +1,114 @@
+use std::collections::BTreeSet;
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+
+use super::{
+    multiproof::MerkleMultiProof,
+    node_store::NodeStore,
+    tree::{hash_pair, Leafable, MerkleTree},
+};
+use crate::rng::RngProvider;
+
+/// Random leaves of a tree with a single proof against its root, for data availability sampling.
+#[derive(Clone, Debug)]
+pub struct DaSample<F: PrimeField, V: Leafable<F>> {
+    pub seed: F,
+    /// `RngProvider::source` of the seed, or `None` if the caller chose it.
+    pub seed_source: Option<String>,
+    /// Sampled leaves in the order of `proof.indices`.
+    pub leaves: Vec<V>,
+    pub proof: MerkleMultiProof<F>,
+}
+
+/// Derives `count` distinct indices of a tree of `height` levels from a public `seed`.
+///
+/// The `i`-th candidate is the low bits of `H(seed, i)`; repeated candidates are skipped.
+/// Returns every index if the tree has no more than `count` leaves.
+pub fn sample_indices<F: PrimeField>(
+    constants: &PoseidonConstants<F, U2>,
+    seed: F,
+    height: usize,
+    count: usize,
+) -> Vec<usize> {
+    if height < usize::BITS as usize && count >= 1 << height {
+        return (0..1 << height).collect();
+    }
+
+    let mut indices = BTreeSet::new();
+    let mut counter = 0u64;
+    while indices.len() < count {
+        let h = hash_pair(constants, seed, F::from(counter));
+        let mut bytes = [0u8; 8];
+        let repr = h.to_repr();
+        let len = repr.as_ref().len().min(8);
+        bytes[..len].copy_from_slice(&repr.as_ref()[..len]);
+        let candidate = u64::from_le_bytes(bytes) as usize;
+        indices.insert(if height < usize::BITS as usize {
+            candidate & ((1 << height) - 1)
+        } else {
+            candidate
+        });
+        counter += 1;
+    }
+
+    indices.into_iter().collect()
+}
+
+impl<F: PrimeField, V: Leafable<F>, S: NodeStore<F>> MerkleTree<F, V, S> {
+    /// Samples `count` leaves selected by `seed`.
+    pub fn sample(&self, seed: F, count: usize) -> DaSample<F, V> {
+        let indices = sample_indices(&self.poseidon_constants, seed, self.height, count);
+        let proof = self.prove_many(&indices);
+        let leaves = proof
+            .indices
+            .iter()
+            .map(|&index| self.get_leaf(index))
+            .collect();
+
+        DaSample {
+            seed,
+            seed_source: None,
+            leaves,
+            proof,
+        }
+    }
+
+    /// Samples `count` leaves selected by a seed drawn from `rng`.
+    pub fn sample_with(&self, rng: &mut impl RngProvider, count: usize) -> DaSample<F, V> {
+        let seed = F::random(&mut *rng);
+
+        DaSample {
+            seed_source: Some(rng.source()),
+            ..self.sample(seed, count)
+        }
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F>> DaSample<F, V> {
+    /// Checks that the sample holds the `count` leaves selected by its seed under `root` of a
+    /// tree of `height` levels. An empty sample, for a `count` of zero, proves nothing and is
+    /// accepted as long as it carries no leaves or siblings.
+    pub fn verify(
+        &self,
+        constants: &PoseidonConstants<F, U2>,
+        root: F,
+        height: usize,
+        count: usize,
+    ) -> bool {
+        if self.proof.height != height {
+            return false;
+        }
+        let indices = sample_indices(constants, self.seed, height, count);
+        if indices != self.proof.indices {
+            return false;
+        }
+        if indices.is_empty() {
+            return self.leaves.is_empty() && self.proof.siblings.is_empty();
+        }
+
+        let leaf_hashes: Vec<F> = self.leaves.iter().map(|leaf| leaf.hash()).collect();
+        self.proof.verify(constants, root, &leaf_hashes)
+    }
+}
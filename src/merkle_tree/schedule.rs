@@ -0,0 +1,74 @@
+use ff::PrimeField;
+
+use super::{
+    circuits::MerkleProcessCircuit,
+    tree::{Leafable, MerkleTree},
+};
+
+/// Decides the order in which the operations of a block are proven.
+///
+/// Updates of distinct leaves commute, so any order reaches the same final root. Implementations
+/// must keep the relative order of operations on the same index, or the block's result changes.
+pub trait Scheduler {
+    /// Permutation of `0..indices.len()` giving the proving order of operations on `indices`.
+    fn order(&self, indices: &[usize]) -> Vec<usize>;
+}
+
+/// Proves operations in the order they were submitted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InOrder;
+
+impl Scheduler for InOrder {
+    fn order(&self, indices: &[usize]) -> Vec<usize> {
+        (0..indices.len()).collect()
+    }
+}
+
+/// Proves operations in index order, so consecutive steps share as much of their path as possible
+/// and the nodes read for one step are still cached when the next one needs them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Locality;
+
+impl Scheduler for Locality {
+    fn order(&self, indices: &[usize]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..indices.len()).collect();
+        // Stable, so operations on the same index keep their submission order.
+        order.sort_by_key(|&i| indices[i]);
+        order
+    }
+}
+
+/// Reorders `operations` as chosen by `scheduler`.
+pub fn schedule<V>(scheduler: &impl Scheduler, operations: Vec<(usize, V)>) -> Vec<(usize, V)> {
+    let indices: Vec<usize> = operations.iter().map(|&(index, _)| index).collect();
+    let order = scheduler.order(&indices);
+    assert_eq!(
+        order.len(),
+        operations.len(),
+        "schedule must be a permutation"
+    );
+
+    let mut operations: Vec<Option<(usize, V)>> = operations.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|i| {
+            operations[i]
+                .take()
+                .expect("schedule must be a permutation")
+        })
+        .collect()
+}
+
+impl<F: PrimeField> MerkleProcessCircuit<F> {
+    /// Applies `operations` to `tree` in the order chosen by `scheduler` and returns one step per operation.
+    pub fn from_updates<V: Leafable<F>>(
+        tree: &mut MerkleTree<F, V>,
+        operations: Vec<(usize, V)>,
+        scheduler: &impl Scheduler,
+    ) -> Vec<Self> {
+        schedule(scheduler, operations)
+            .into_iter()
+            .map(|(index, leaf)| Self::from_update(tree, index, leaf))
+            .collect()
+    }
+}
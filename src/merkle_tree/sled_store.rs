@@ -0,0 +1,251 @@
+use std::path::Path;
+
+use ff::PrimeField;
+use sled::{transaction::ConflictableTransactionError, Transactional};
+
+use super::{
+    dedup_store::{
+        decode_dedup_node, decode_dedup_root, encode_dedup_key, encode_dedup_node,
+        encode_dedup_root, DedupBackend, DedupNode,
+    },
+    node_store::{
+        decode_checkpoint, decode_node, decode_path, encode_checkpoint, encode_path,
+        next_checkpoint_key, NodeStore, PendingWrites,
+    },
+    path::TreePath,
+    tree::{Checkpoint, Leafable},
+};
+
+/// Name of the sled tree holding the nodes, keyed by `encode_path`.
+const NODES_TREE: &str = "merkle_nodes";
+/// Name of the sled tree holding the leaves, keyed by `encode_path`.
+const LEAVES_TREE: &str = "merkle_leaves";
+/// Name of the sled tree holding the checkpoints, keyed by `next_checkpoint_key`.
+const CHECKPOINTS_TREE: &str = "merkle_checkpoints";
+/// Name of the sled tree holding a `DedupNodeStore` pool, with the nodes keyed by `DEDUP_NODE_PREFIX`
+/// and their `encode_dedup_key` and the roots by `DEDUP_ROOT_PREFIX` and the store name, so
+/// that both are written in a single batch.
+const DEDUP_TREE: &str = "merkle_dedup";
+const DEDUP_NODE_PREFIX: u8 = b'd';
+const DEDUP_ROOT_PREFIX: u8 = b'r';
+
+/// Node store in an embedded sled database, a pure-Rust alternative to `RocksDbNodeStore`.
+///
+/// Writes are buffered until `commit` and then applied as one sled transaction over a batch of
+/// nodes and a batch of leaves, so after a crash the stored nodes and leaves match a committed
+/// version of the tree; they are durable after `flush`.
+/// Checkpoints are written once their nodes are, so a crash may lose a checkpoint but never keeps
+/// one whose nodes are missing.
+/// As a `DedupBackend`, it keeps a `DedupNodeStore` pool in a tree of its own, each commit of which
+/// is a single atomic batch.
+/// A copy would share the database, so neither the store nor a tree using it can be cloned.
+/// `len` scans every node. Reopen a tree with `MerkleTree::open_with_store`.
+///
+/// Panics if the database fails or holds a value that is not a field element.
+#[derive(Debug)]
+pub struct SledNodeStore<F: PrimeField> {
+    nodes: sled::Tree,
+    leaves: sled::Tree,
+    checkpoints: sled::Tree,
+    dedup: sled::Tree,
+    pending: PendingWrites<F>,
+}
+
+impl<F: PrimeField> SledNodeStore<F> {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> sled::Result<Self> {
+        Self::from_db(&sled::open(path)?)
+    }
+
+    /// Store in the trees of an already open database, which may hold other data.
+    pub fn from_db(db: &sled::Db) -> sled::Result<Self> {
+        Ok(Self {
+            nodes: db.open_tree(NODES_TREE)?,
+            leaves: db.open_tree(LEAVES_TREE)?,
+            checkpoints: db.open_tree(CHECKPOINTS_TREE)?,
+            dedup: db.open_tree(DEDUP_TREE)?,
+            pending: PendingWrites::default(),
+        })
+    }
+
+    /// Writes buffered updates to disk.
+    pub fn flush(&self) -> sled::Result<()> {
+        self.nodes.flush()?;
+        self.leaves.flush()?;
+        self.checkpoints.flush()?;
+        self.dedup.flush().map(|_| ())
+    }
+}
+
+impl<F: PrimeField> NodeStore<F> for SledNodeStore<F> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        if let Some(h) = self.pending.get(path) {
+            return h;
+        }
+        let value = self
+            .nodes
+            .get(encode_path(path))
+            .expect("node store read failed")?;
+        Some(decode_node(&value))
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        let old = self.get(&path);
+        let len = self.len() + old.is_none() as usize;
+        self.pending.write(path, Some(hash), len);
+
+        old
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        let old = self.get(path)?;
+        let len = self.len() - 1;
+        self.pending.write(path.clone(), None, len);
+
+        Some(old)
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len.unwrap_or_else(|| self.nodes.len())
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    fn record_checkpoint(&mut self, checkpoint: Checkpoint<F>) {
+        self.pending.checkpoints.push(checkpoint);
+    }
+
+    fn checkpoints(&self) -> Vec<Checkpoint<F>> {
+        self.checkpoints
+            .iter()
+            .map(|entry| {
+                let (_, value) = entry.expect("node store read failed");
+                decode_checkpoint(&value)
+            })
+            .collect()
+    }
+
+    fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+        self.pending.write_leaves(writes);
+    }
+
+    fn leaves<V: Leafable<F>>(&self, _height: usize) -> Vec<(TreePath, V)> {
+        let stored = self.leaves.iter().map(|entry| {
+            let (key, value) = entry.expect("node store read failed");
+            (decode_path(&key), value.to_vec())
+        });
+        self.pending.leaf_overlay(stored)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        let stored = self.nodes.iter().map(|entry| {
+            let (key, value) = entry.expect("node store read failed");
+            (decode_path(&key), decode_node(&value))
+        });
+        Box::new(self.pending.overlay(stored))
+    }
+
+    fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        let mut node_batch = sled::Batch::default();
+        let mut leaf_batch = sled::Batch::default();
+        if pending.cleared {
+            for entry in self.nodes.iter().keys() {
+                node_batch.remove(entry.expect("node store read failed"));
+            }
+            for entry in self.leaves.iter().keys() {
+                leaf_batch.remove(entry.expect("node store read failed"));
+            }
+        }
+        for (path, h) in pending.nodes {
+            match h {
+                Some(h) => node_batch.insert(encode_path(&path), h.to_repr().as_ref()),
+                None => node_batch.remove(encode_path(&path)),
+            }
+        }
+        for (path, value) in pending.leaves {
+            match value {
+                Some(value) => leaf_batch.insert(encode_path(&path), value),
+                None => leaf_batch.remove(encode_path(&path)),
+            }
+        }
+        (&self.nodes, &self.leaves)
+            .transaction(|(nodes, leaves)| {
+                nodes.apply_batch(&node_batch)?;
+                leaves.apply_batch(&leaf_batch)?;
+                Ok::<_, ConflictableTransactionError>(())
+            })
+            .expect("node store write failed");
+        for checkpoint in &pending.checkpoints {
+            let last = self.checkpoints.last().expect("node store read failed");
+            self.checkpoints
+                .insert(
+                    next_checkpoint_key(last.as_ref().map(|(key, _)| key.as_ref())),
+                    encode_checkpoint(checkpoint),
+                )
+                .expect("node store write failed");
+        }
+    }
+}
+
+impl<F: PrimeField> DedupBackend<F> for SledNodeStore<F> {
+    fn get_node(&self, depth: usize, hash: &F) -> Option<DedupNode<F>> {
+        let value = self
+            .dedup
+            .get(dedup_key(DEDUP_NODE_PREFIX, &encode_dedup_key(depth, hash)))
+            .expect("node store read failed")?;
+        Some(decode_dedup_node(&value))
+    }
+
+    fn get_root(&self, name: &str) -> Option<(F, usize)> {
+        let value = self
+            .dedup
+            .get(dedup_key(DEDUP_ROOT_PREFIX, name.as_bytes()))
+            .expect("node store read failed")?;
+        Some(decode_dedup_root(&value))
+    }
+
+    fn num_nodes(&self) -> usize {
+        self.dedup.scan_prefix([DEDUP_NODE_PREFIX]).count()
+    }
+
+    fn write(
+        &mut self,
+        nodes: Vec<(usize, F, Option<DedupNode<F>>)>,
+        root: Option<(&str, Option<(F, usize)>)>,
+    ) {
+        let mut batch = sled::Batch::default();
+        for (depth, h, node) in nodes {
+            let key = dedup_key(DEDUP_NODE_PREFIX, &encode_dedup_key(depth, &h));
+            match node {
+                Some(node) => batch.insert(key, encode_dedup_node(&node)),
+                None => batch.remove(key),
+            }
+        }
+        if let Some((name, root)) = root {
+            let key = dedup_key(DEDUP_ROOT_PREFIX, name.as_bytes());
+            match root {
+                Some(root) => batch.insert(key, encode_dedup_root(root)),
+                None => batch.remove(key),
+            }
+        }
+        self.dedup
+            .apply_batch(batch)
+            .expect("node store write failed");
+    }
+}
+
+fn dedup_key(prefix: u8, id: &[u8]) -> Vec<u8> {
+    let mut key = vec![prefix];
+    key.extend_from_slice(id);
+    key
+}
@@ -0,0 +1,158 @@
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    node_store::NodeStore,
+    path::TreePath,
+    tree::{Leafable, MerkleTree},
+};
+use crate::encoding::EncodingError;
+
+const SNAPSHOT_MAGIC: [u8; 4] = *b"NSMT";
+const SNAPSHOT_VERSION: u8 = 1;
+
+impl<F: PrimeField, V: Leafable<F> + Serialize + DeserializeOwned, S: NodeStore<F>>
+    MerkleTree<F, V, S>
+{
+    /// Writes the occupied leaves and stored nodes of the current version.
+    ///
+    /// The layout is the magic `NSMT`, a version byte, the height as a big-endian `u32` and the
+    /// bincode encoding of the empty leaf. Then come the number of leaves as a big-endian `u64`,
+    /// each leaf as its path from `TreePath::to_bytes_be` and bincode value, and likewise every
+    /// stored node as its depth as a big-endian `u32`, its path and its hash representation.
+    /// History is not included.
+    pub fn write_snapshot<W: Write>(&self, mut writer: W) -> Result<(), EncodingError> {
+        let height = u32::try_from(self.height).map_err(|_| EncodingError::InvalidHeader)?;
+        let io = |error| EncodingError::Encoding(bincode::Error::from(error));
+
+        writer.write_all(&SNAPSHOT_MAGIC).map_err(io)?;
+        writer.write_all(&[SNAPSHOT_VERSION]).map_err(io)?;
+        writer.write_all(&height.to_be_bytes()).map_err(io)?;
+        bincode::serialize_into(&mut writer, &self.empty_leaf).map_err(EncodingError::Encoding)?;
+
+        writer
+            .write_all(&(self.leaves.len() as u64).to_be_bytes())
+            .map_err(io)?;
+        for (path, leaf) in &self.leaves {
+            writer.write_all(&path.to_bytes_be()).map_err(io)?;
+            bincode::serialize_into(&mut writer, leaf).map_err(EncodingError::Encoding)?;
+        }
+
+        writer
+            .write_all(&(self.node_hashes.len() as u64).to_be_bytes())
+            .map_err(io)?;
+        for (path, h) in self.node_hashes.iter() {
+            writer
+                .write_all(&(path.depth() as u32).to_be_bytes())
+                .map_err(io)?;
+            writer.write_all(&path.to_bytes_be()).map_err(io)?;
+            writer.write_all(h.to_repr().as_ref()).map_err(io)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds a tree from `write_snapshot` without rehashing, keeping its nodes in `store`,
+    /// which must be empty, along with its leaves if the store keeps them. The tree starts at
+    /// version 0.
+    ///
+    /// A snapshot of a tree taller than `max_height` is rejected with `InvalidHeader` before any
+    /// hash is computed for it. Nodes are trusted as read; `verify_integrity` checks them against
+    /// the leaves.
+    pub fn read_snapshot_with<R: Read>(
+        mut reader: R,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        mut store: S,
+        max_height: usize,
+    ) -> Result<Self, EncodingError> {
+        assert!(store.is_empty(), "node store is not empty");
+
+        let mut header = [0; 9];
+        read_exact(&mut reader, &mut header)?;
+        if header[..4] != SNAPSHOT_MAGIC {
+            return Err(EncodingError::InvalidHeader);
+        }
+        if header[4] != SNAPSHOT_VERSION {
+            return Err(EncodingError::UnsupportedVersion { found: header[4] });
+        }
+        let height = u32::from_be_bytes(header[5..].try_into().unwrap()) as usize;
+        if height > max_height {
+            return Err(EncodingError::InvalidHeader);
+        }
+        let empty: V = bincode::deserialize_from(&mut reader).map_err(EncodingError::Encoding)?;
+
+        let path_len = height.div_ceil(8);
+        let mut leaves = vec![];
+        for _ in 0..read_u64(&mut reader)? {
+            let path = read_path(&mut reader, path_len, height)?;
+            let leaf: V =
+                bincode::deserialize_from(&mut reader).map_err(EncodingError::Encoding)?;
+            leaves.push((path, leaf));
+        }
+
+        for _ in 0..read_u64(&mut reader)? {
+            let mut depth = [0; 4];
+            read_exact(&mut reader, &mut depth)?;
+            let depth = u32::from_be_bytes(depth) as usize;
+            if depth > height {
+                return Err(EncodingError::InvalidHeader);
+            }
+            let path = read_path(&mut reader, depth.div_ceil(8), depth)?;
+            let mut repr = F::Repr::default();
+            read_exact(&mut reader, repr.as_mut())?;
+            let h = Option::from(F::from_repr(repr)).ok_or(EncodingError::InvalidFieldElement)?;
+            store.insert(path, h);
+        }
+        store.write_leaves(
+            leaves
+                .iter()
+                .map(|(path, leaf)| (path.clone(), Some(leaf.clone()))),
+        );
+        store.commit();
+
+        Ok(Self::with_store_and_leaves(
+            height,
+            poseidon_constants,
+            empty,
+            store,
+            leaves,
+        ))
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F> + Serialize + DeserializeOwned> MerkleTree<F, V> {
+    /// Like `read_snapshot_with`, in memory with the default constants.
+    pub fn read_snapshot<R: Read>(reader: R, max_height: usize) -> Result<Self, EncodingError> {
+        Self::read_snapshot_with(
+            reader,
+            Arc::new(PoseidonConstants::new()),
+            im::HashMap::new(),
+            max_height,
+        )
+    }
+}
+
+fn read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), EncodingError> {
+    reader
+        .read_exact(buf)
+        .map_err(|error| EncodingError::Encoding(bincode::Error::from(error)))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, EncodingError> {
+    let mut bytes = [0; 8];
+    read_exact(reader, &mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn read_path<R: Read>(reader: &mut R, len: usize, depth: usize) -> Result<TreePath, EncodingError> {
+    let mut bytes = vec![0; len];
+    read_exact(reader, &mut bytes)?;
+    Ok(TreePath::from_bytes_be(&bytes, depth))
+}
@@ -0,0 +1,174 @@
+use std::marker::PhantomData;
+
+use bellperson::{gadgets::num::AllocatedNum, ConstraintSystem, SynthesisError};
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::{circuit::poseidon_hash, poseidon::PoseidonConstants};
+
+use super::tree::hash_pair;
+use crate::sealed::Sealed;
+
+/// Number of elements absorbed per Poseidon call.
+pub const RATE: usize = 2;
+
+/// How variable-length input is extended to a whole number of blocks before absorption.
+///
+/// Padding only depends on the input length, so the native and circuit paths agree by construction.
+///
+/// Sealed, as a rule that is not injective would let distinct inputs collide.
+pub trait PaddingRule: Sealed {
+    /// Elements absorbed before an input of `len` elements.
+    fn prefix<F: PrimeField>(len: usize) -> Vec<F>;
+
+    /// Elements absorbed after an input of `len` elements.
+    fn suffix<F: PrimeField>(len: usize) -> Vec<F>;
+}
+
+/// Appends zeros up to a whole number of blocks, at least one.
+///
+/// Inputs differing only in trailing zeros collide, so use it only for fixed-length data.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZeroPad;
+
+/// Absorbs the length first, then pads with zeros.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LengthPrefix;
+
+/// Appends a one followed by zeros, as in the multi-rate padding of sponge constructions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Multirate;
+
+fn zeros<F: PrimeField>(len: usize) -> Vec<F> {
+    let padded = len.max(1).div_ceil(RATE) * RATE;
+    vec![F::zero(); padded - len]
+}
+
+impl Sealed for ZeroPad {}
+
+impl PaddingRule for ZeroPad {
+    fn prefix<F: PrimeField>(_len: usize) -> Vec<F> {
+        vec![]
+    }
+
+    fn suffix<F: PrimeField>(len: usize) -> Vec<F> {
+        zeros(len)
+    }
+}
+
+impl Sealed for LengthPrefix {}
+
+impl PaddingRule for LengthPrefix {
+    fn prefix<F: PrimeField>(len: usize) -> Vec<F> {
+        vec![F::from(len as u64)]
+    }
+
+    fn suffix<F: PrimeField>(len: usize) -> Vec<F> {
+        zeros(len + 1)
+    }
+}
+
+impl Sealed for Multirate {}
+
+impl PaddingRule for Multirate {
+    fn prefix<F: PrimeField>(_len: usize) -> Vec<F> {
+        vec![]
+    }
+
+    fn suffix<F: PrimeField>(len: usize) -> Vec<F> {
+        let mut suffix = vec![F::one()];
+        suffix.extend(zeros::<F>(len + 1));
+        suffix
+    }
+}
+
+/// Hashes variable-length leaf data with Poseidon, padded by `P`.
+///
+/// Each block `(a, b)` is absorbed as `state = H(state + a, b)`, starting from a zero state.
+#[derive(Clone, Debug)]
+pub struct LeafSponge<F: PrimeField, P: PaddingRule> {
+    pub constants: PoseidonConstants<F, U2>,
+    _padding: PhantomData<P>,
+}
+
+impl<F: PrimeField, P: PaddingRule> LeafSponge<F, P> {
+    pub fn new(constants: PoseidonConstants<F, U2>) -> Self {
+        Self {
+            constants,
+            _padding: PhantomData,
+        }
+    }
+
+    pub fn hash(&self, input: &[F]) -> F {
+        let mut padded = P::prefix::<F>(input.len());
+        padded.extend_from_slice(input);
+        padded.extend(P::suffix::<F>(input.len()));
+        assert_eq!(padded.len() % RATE, 0, "padding must fill whole blocks");
+
+        padded.chunks(RATE).fold(F::zero(), |state, block| {
+            hash_pair(&self.constants, state + block[0], block[1])
+        })
+    }
+
+    pub fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        input: &[AllocatedNum<F>],
+    ) -> Result<AllocatedNum<F>, SynthesisError> {
+        let prefix = P::prefix::<F>(input.len());
+        let suffix = P::suffix::<F>(input.len());
+        assert_eq!(
+            (prefix.len() + input.len() + suffix.len()) % RATE,
+            0,
+            "padding must fill whole blocks"
+        );
+
+        let mut padded = vec![];
+        for (i, c) in prefix.into_iter().enumerate() {
+            padded.push(alloc_constant(cs.namespace(|| format!("prefix {i}")), c)?);
+        }
+        padded.extend(input.iter().cloned());
+        for (i, c) in suffix.into_iter().enumerate() {
+            padded.push(alloc_constant(cs.namespace(|| format!("suffix {i}")), c)?);
+        }
+
+        let mut state = alloc_constant(cs.namespace(|| "initial state"), F::zero())?;
+        for (i, block) in padded.chunks(RATE).enumerate() {
+            let mut cs = cs.namespace(|| format!("absorb block {i}"));
+            let sum = AllocatedNum::alloc(cs.namespace(|| "allocate sum"), || {
+                let mut sum = state.get_value().ok_or(SynthesisError::AssignmentMissing)?;
+                sum += block[0]
+                    .get_value()
+                    .ok_or(SynthesisError::AssignmentMissing)?;
+                Ok(sum)
+            })?;
+            cs.enforce(
+                || "add block to state",
+                |lc| lc + state.get_variable() + block[0].get_variable(),
+                |lc| lc + CS::one(),
+                |lc| lc + sum.get_variable(),
+            );
+            state = poseidon_hash(
+                cs.namespace(|| "calculate poseidon"),
+                vec![sum, block[1].clone()],
+                &self.constants,
+            )?;
+        }
+
+        Ok(state)
+    }
+}
+
+fn alloc_constant<F: PrimeField, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    value: F,
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    let num = AllocatedNum::alloc(cs.namespace(|| "allocate constant"), || Ok(value))?;
+    cs.enforce(
+        || "fix constant",
+        |lc| lc + num.get_variable(),
+        |lc| lc + CS::one(),
+        |lc| lc + (value, CS::one()),
+    );
+
+    Ok(num)
+}
@@ -0,0 +1,342 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use ff::PrimeField;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{
+    dedup_store::{DedupBackend, DedupNode},
+    node_store::{decode_node, decode_path, encode_path, NodeStore, PendingWrites},
+    path::TreePath,
+    tree::{Checkpoint, Leafable},
+};
+
+/// Table of the nodes, keyed by `encode_path`.
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS merkle_nodes \
+    (path BLOB PRIMARY KEY, hash BLOB NOT NULL) WITHOUT ROWID";
+/// Table of the leaves, keyed by `encode_path`, with their `Leafable::to_stored` value.
+const CREATE_LEAVES_TABLE: &str = "CREATE TABLE IF NOT EXISTS merkle_leaves \
+    (path BLOB PRIMARY KEY, value BLOB NOT NULL) WITHOUT ROWID";
+/// Table of the checkpoints, in the order they were recorded.
+const CREATE_CHECKPOINTS_TABLE: &str = "CREATE TABLE IF NOT EXISTS merkle_checkpoints \
+    (id INTEGER PRIMARY KEY, version INTEGER NOT NULL, root BLOB NOT NULL, \
+    timestamp INTEGER NOT NULL)";
+/// Table of the nodes of a `DedupNodeStore` pool, keyed by their depth and hash, with a `NULL`
+/// empty child.
+const CREATE_DEDUP_NODES_TABLE: &str = "CREATE TABLE IF NOT EXISTS merkle_dedup_nodes \
+    (depth INTEGER NOT NULL, hash BLOB NOT NULL, left BLOB, right BLOB, refs INTEGER NOT NULL, \
+    PRIMARY KEY (depth, hash)) WITHOUT ROWID";
+/// Table of the roots of the named `DedupNodeStore`s of the pool.
+const CREATE_DEDUP_ROOTS_TABLE: &str = "CREATE TABLE IF NOT EXISTS merkle_dedup_roots \
+    (name TEXT PRIMARY KEY, root BLOB NOT NULL, len INTEGER NOT NULL) WITHOUT ROWID";
+
+/// Node store in a single-file SQLite database, for deployments that do not want to run RocksDB.
+///
+/// Writes are buffered until `commit` and then applied in a single SQLite transaction, so every
+/// update of the tree moves the stored nodes from one root to the next atomically, together with
+/// its leaves and the checkpoint of the new version if one is due. As a `DedupBackend`, it keeps a
+/// `DedupNodeStore` pool in tables of its own, each commit of which is a transaction. A copy
+/// would share the database, so neither the store nor a tree using it can be cloned. `len`
+/// counts the rows. Reopen a tree with `MerkleTree::open_with_store`.
+///
+/// Panics if the database fails or holds a value that is not a field element.
+#[derive(Debug)]
+pub struct SqliteNodeStore<F: PrimeField> {
+    conn: Arc<Mutex<Connection>>,
+    pending: PendingWrites<F>,
+}
+
+impl<F: PrimeField> SqliteNodeStore<F> {
+    /// Opens the database at `path`, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    /// Store in the `merkle_nodes`, `merkle_leaves`, `merkle_checkpoints` and `merkle_dedup_*`
+    /// tables of `conn`, created if missing. The database may hold other tables.
+    pub fn from_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute(CREATE_TABLE, [])?;
+        conn.execute(CREATE_LEAVES_TABLE, [])?;
+        conn.execute(CREATE_CHECKPOINTS_TABLE, [])?;
+        conn.execute(CREATE_DEDUP_NODES_TABLE, [])?;
+        conn.execute(CREATE_DEDUP_ROOTS_TABLE, [])?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            pending: PendingWrites::default(),
+        })
+    }
+
+    fn stored_len(&self) -> usize {
+        let conn = self.conn.lock().unwrap();
+        let len: i64 = conn
+            .query_row("SELECT COUNT(*) FROM merkle_nodes", [], |row| row.get(0))
+            .expect("node store read failed");
+        len as usize
+    }
+}
+
+impl<F: PrimeField> NodeStore<F> for SqliteNodeStore<F> {
+    fn get(&self, path: &TreePath) -> Option<F> {
+        if let Some(h) = self.pending.get(path) {
+            return h;
+        }
+        let conn = self.conn.lock().unwrap();
+        let value: Vec<u8> = conn
+            .prepare_cached("SELECT hash FROM merkle_nodes WHERE path = ?1")
+            .and_then(|mut statement| {
+                statement
+                    .query_row([encode_path(path)], |row| row.get(0))
+                    .optional()
+            })
+            .expect("node store read failed")?;
+        Some(decode_node(&value))
+    }
+
+    fn insert(&mut self, path: TreePath, hash: F) -> Option<F> {
+        let old = self.get(&path);
+        let len = self.len() + old.is_none() as usize;
+        self.pending.write(path, Some(hash), len);
+
+        old
+    }
+
+    fn remove(&mut self, path: &TreePath) -> Option<F> {
+        let old = self.get(path)?;
+        let len = self.len() - 1;
+        self.pending.write(path.clone(), None, len);
+
+        Some(old)
+    }
+
+    fn len(&self) -> usize {
+        self.pending.len.unwrap_or_else(|| self.stored_len())
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    fn record_checkpoint(&mut self, checkpoint: Checkpoint<F>) {
+        self.pending.checkpoints.push(checkpoint);
+    }
+
+    fn checkpoints(&self) -> Vec<Checkpoint<F>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare("SELECT version, root, timestamp FROM merkle_checkpoints ORDER BY id")
+            .expect("node store read failed");
+        let rows = statement
+            .query_map([], |row| {
+                Ok(Checkpoint {
+                    version: row.get::<_, i64>(0)? as usize,
+                    root: decode_node(&row.get::<_, Vec<u8>>(1)?),
+                    timestamp: row.get::<_, i64>(2)? as u64,
+                })
+            })
+            .expect("node store read failed");
+        rows.map(|row| row.expect("node store read failed"))
+            .collect()
+    }
+
+    fn write_leaves<V: Leafable<F>>(
+        &mut self,
+        writes: impl IntoIterator<Item = (TreePath, Option<V>)>,
+    ) {
+        self.pending.write_leaves(writes);
+    }
+
+    fn leaves<V: Leafable<F>>(&self, _height: usize) -> Vec<(TreePath, V)> {
+        let stored: Vec<(TreePath, Vec<u8>)> = {
+            let conn = self.conn.lock().unwrap();
+            let mut statement = conn
+                .prepare("SELECT path, value FROM merkle_leaves")
+                .expect("node store read failed");
+            let rows = statement
+                .query_map([], |row| {
+                    Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .expect("node store read failed");
+            rows.map(|row| {
+                let (path, value) = row.expect("node store read failed");
+                (decode_path(&path), value)
+            })
+            .collect()
+        };
+        self.pending.leaf_overlay(stored.into_iter())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (TreePath, F)> + '_> {
+        let stored: Vec<(TreePath, F)> = {
+            let conn = self.conn.lock().unwrap();
+            let mut statement = conn
+                .prepare("SELECT path, hash FROM merkle_nodes")
+                .expect("node store read failed");
+            let rows = statement
+                .query_map([], |row| {
+                    Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .expect("node store read failed");
+            rows.map(|row| {
+                let (path, hash) = row.expect("node store read failed");
+                (decode_path(&path), decode_node(&hash))
+            })
+            .collect()
+        };
+        Box::new(self.pending.overlay(stored.into_iter()))
+    }
+
+    fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        let mut conn = self.conn.lock().unwrap();
+        let write = |conn: &mut Connection| -> rusqlite::Result<()> {
+            let transaction = conn.transaction()?;
+            if pending.cleared {
+                transaction.execute("DELETE FROM merkle_nodes", [])?;
+                transaction.execute("DELETE FROM merkle_leaves", [])?;
+            }
+            {
+                let mut put = transaction.prepare_cached(
+                    "INSERT OR REPLACE INTO merkle_nodes (path, hash) VALUES (?1, ?2)",
+                )?;
+                let mut delete =
+                    transaction.prepare_cached("DELETE FROM merkle_nodes WHERE path = ?1")?;
+                for (path, h) in &pending.nodes {
+                    match h {
+                        Some(h) => put.execute(params![encode_path(path), h.to_repr().as_ref()])?,
+                        None => delete.execute([encode_path(path)])?,
+                    };
+                }
+                let mut put_leaf = transaction.prepare_cached(
+                    "INSERT OR REPLACE INTO merkle_leaves (path, value) VALUES (?1, ?2)",
+                )?;
+                let mut delete_leaf =
+                    transaction.prepare_cached("DELETE FROM merkle_leaves WHERE path = ?1")?;
+                for (path, value) in &pending.leaves {
+                    match value {
+                        Some(value) => put_leaf.execute(params![encode_path(path), value])?,
+                        None => delete_leaf.execute([encode_path(path)])?,
+                    };
+                }
+                let mut record = transaction.prepare_cached(
+                    "INSERT INTO merkle_checkpoints (version, root, timestamp) VALUES (?1, ?2, ?3)",
+                )?;
+                for checkpoint in &pending.checkpoints {
+                    record.execute(params![
+                        checkpoint.version as i64,
+                        checkpoint.root.to_repr().as_ref(),
+                        checkpoint.timestamp as i64
+                    ])?;
+                }
+            }
+            transaction.commit()
+        };
+        write(&mut conn).expect("node store write failed");
+    }
+}
+
+impl<F: PrimeField> DedupBackend<F> for SqliteNodeStore<F> {
+    fn get_node(&self, depth: usize, hash: &F) -> Option<DedupNode<F>> {
+        let conn = self.conn.lock().unwrap();
+        conn.prepare_cached(
+            "SELECT left, right, refs FROM merkle_dedup_nodes WHERE depth = ?1 AND hash = ?2",
+        )
+        .and_then(|mut statement| {
+            statement
+                .query_row(params![depth as i64, hash.to_repr().as_ref()], |row| {
+                    let child = |i| {
+                        row.get::<_, Option<Vec<u8>>>(i)
+                            .map(|value| value.map(|value| decode_node(&value)))
+                    };
+                    Ok(DedupNode {
+                        children: [child(0)?, child(1)?],
+                        refs: row.get::<_, i64>(2)? as usize,
+                    })
+                })
+                .optional()
+        })
+        .expect("node store read failed")
+    }
+
+    fn get_root(&self, name: &str) -> Option<(F, usize)> {
+        let conn = self.conn.lock().unwrap();
+        conn.prepare_cached("SELECT root, len FROM merkle_dedup_roots WHERE name = ?1")
+            .and_then(|mut statement| {
+                statement
+                    .query_row([name], |row| {
+                        Ok((
+                            decode_node(&row.get::<_, Vec<u8>>(0)?),
+                            row.get::<_, i64>(1)? as usize,
+                        ))
+                    })
+                    .optional()
+            })
+            .expect("node store read failed")
+    }
+
+    fn num_nodes(&self) -> usize {
+        let conn = self.conn.lock().unwrap();
+        let len: i64 = conn
+            .query_row("SELECT COUNT(*) FROM merkle_dedup_nodes", [], |row| {
+                row.get(0)
+            })
+            .expect("node store read failed");
+        len as usize
+    }
+
+    fn write(
+        &mut self,
+        nodes: Vec<(usize, F, Option<DedupNode<F>>)>,
+        root: Option<(&str, Option<(F, usize)>)>,
+    ) {
+        let mut conn = self.conn.lock().unwrap();
+        let write = |conn: &mut Connection| -> rusqlite::Result<()> {
+            let transaction = conn.transaction()?;
+            {
+                let mut put = transaction.prepare_cached(
+                    "INSERT OR REPLACE INTO merkle_dedup_nodes (depth, hash, left, right, refs) \
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?;
+                let mut delete = transaction.prepare_cached(
+                    "DELETE FROM merkle_dedup_nodes WHERE depth = ?1 AND hash = ?2",
+                )?;
+                for (depth, h, node) in &nodes {
+                    let depth = *depth as i64;
+                    let hash = h.to_repr();
+                    match node {
+                        Some(node) => {
+                            let [left, right] =
+                                node.children.map(|child| child.map(|h| h.to_repr()));
+                            put.execute(params![
+                                depth,
+                                hash.as_ref(),
+                                left.as_ref().map(|repr| repr.as_ref()),
+                                right.as_ref().map(|repr| repr.as_ref()),
+                                node.refs as i64
+                            ])?
+                        }
+                        None => delete.execute(params![depth, hash.as_ref()])?,
+                    };
+                }
+                match root {
+                    Some((name, Some((root, len)))) => transaction.execute(
+                        "INSERT OR REPLACE INTO merkle_dedup_roots (name, root, len) \
+                        VALUES (?1, ?2, ?3)",
+                        params![name, root.to_repr().as_ref(), len as i64],
+                    )?,
+                    Some((name, None)) => transaction
+                        .execute("DELETE FROM merkle_dedup_roots WHERE name = ?1", [name])?,
+                    None => 0,
+                };
+            }
+            transaction.commit()
+        };
+        write(&mut conn).expect("node store write failed");
+    }
+}
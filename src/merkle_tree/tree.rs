@@ -1,9 +1,20 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+    ops::Range,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use ff::PrimeField;
 use generic_array::typenum::U2;
 use neptune::{poseidon::PoseidonConstants, Poseidon};
 
+use super::{
+    hasher::MerkleHasher, node_store::NodeStore, path::TreePath, verify,
+    zero_hashes::zero_hash_ladder,
+};
+
 /// Can be a leaf of Merkle trees.
 pub trait Leafable<F: PrimeField>: Clone {
     /// Default hash which indicates empty value.
@@ -11,6 +22,17 @@ pub trait Leafable<F: PrimeField>: Clone {
 
     /// Hash of its value.
     fn hash(&self) -> F;
+
+    /// Encoding of the leaf kept by the persistent node stores, or `None` if it has none, in
+    /// which case a tree of such leaves keeps its nodes in memory.
+    fn to_stored(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Leaf encoded by `to_stored`, or `None` if `bytes` encode none.
+    fn from_stored(_bytes: &[u8]) -> Option<Self> {
+        None
+    }
 }
 
 impl<F: PrimeField> Leafable<F> for F {
@@ -21,113 +43,1252 @@ impl<F: PrimeField> Leafable<F> for F {
     fn hash(&self) -> F {
         *self
     }
+
+    fn to_stored(&self) -> Option<Vec<u8>> {
+        Some(self.to_repr().as_ref().to_vec())
+    }
+
+    fn from_stored(bytes: &[u8]) -> Option<Self> {
+        let mut repr = F::Repr::default();
+        if bytes.len() != repr.as_ref().len() {
+            return None;
+        }
+        repr.as_mut().copy_from_slice(bytes);
+        F::from_repr(repr).into()
+    }
+}
+
+/// Values overwritten by a single version, used to look up and restore earlier versions.
+#[derive(Clone, Debug)]
+pub(crate) struct Changeset<F: PrimeField, V: Leafable<F>> {
+    pub(crate) nodes: HashMap<TreePath, Option<F>>,
+    pub(crate) leaves: HashMap<TreePath, Option<V>>,
 }
 
-#[derive(Debug)]
-pub struct MerkleTree<F: PrimeField, V: Leafable<F>> {
-    pub(crate) poseidon_constants: PoseidonConstants<F, U2>,
+impl<F: PrimeField, V: Leafable<F>> Default for Changeset<F, V> {
+    fn default() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            leaves: HashMap::new(),
+        }
+    }
+}
+
+/// Identifies a version of a tree that can be restored with `MerkleTree::rollback`.
+///
+/// A version number is reissued by the first update after a rollback, so the id also holds the
+/// generation that created the version. Generations only grow, and an id stays unique to the
+/// state it was taken of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SnapshotId {
+    pub(crate) version: usize,
+    pub(crate) generation: u64,
+}
+
+impl SnapshotId {
+    pub fn version(&self) -> usize {
+        self.version
+    }
+}
+
+/// Root of a tree recorded at a version, kept as a restore point and an audit trail of roots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint<F: PrimeField> {
+    pub version: usize,
+    pub root: F,
+    /// Seconds since the Unix epoch when the checkpoint was recorded.
+    pub timestamp: u64,
+}
+
+/// Resolves leaves occupied in both trees of `MerkleTree::merge` with different values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    KeepOurs,
+    TakeTheirs,
+    /// Leaves both trees unchanged and reports the first conflicting index.
+    Fail,
+}
+
+/// Leaf occupied in both merged trees with different values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub path: TreePath,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "leaf {:?} differs between the merged trees",
+            self.path.bits()
+        )
+    }
+}
+
+impl std::error::Error for MergeConflict {}
+
+/// Leaf of a tree deeper than `usize::BITS` levels whose index does not fit in a `usize`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexOverflow {
+    pub path: TreePath,
+}
+
+impl fmt::Display for IndexOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "leaf {:?} has no usize index", self.path.bits())
+    }
+}
+
+impl std::error::Error for IndexOverflow {}
+
+fn checked_index(path: &TreePath) -> Result<usize, IndexOverflow> {
+    path.try_to_index()
+        .ok_or_else(|| IndexOverflow { path: path.clone() })
+}
+
+/// Node whose stored hash does not match its children, or the leaf below it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntegrityError {
+    pub path: TreePath,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {:?} at depth {} does not match its children",
+            self.path.bits(),
+            self.path.depth()
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Nodes, leaves and history are persistent maps, so clones share storage with the original
+/// until either is updated and cloning takes constant time.
+///
+/// Leaves are stored by path, so the height is not limited by the width of `usize`. Methods taking
+/// a `usize` index address the first `2^usize::BITS` leaves; the `_by_path` variants reach all of them.
+///
+/// Internal nodes are kept in `S`, an in-memory map unless another store is given to `new_with_store`.
+/// Stores on disk keep the leaves as well, written with the nodes of each version.
+#[derive(Clone, Debug)]
+pub struct MerkleTree<F: PrimeField, V: Leafable<F>, S: NodeStore<F> = im::HashMap<TreePath, F>> {
+    pub(crate) poseidon_constants: Arc<PoseidonConstants<F, U2>>,
     pub(crate) height: usize,
-    pub(crate) node_hashes: HashMap<Vec<bool>, F>,
-    pub(crate) leaves: HashMap<usize, V>,
+    pub(crate) node_hashes: S,
+    pub(crate) leaves: im::HashMap<TreePath, V>,
     pub(crate) zero_hashes: Vec<F>,
+    pub(crate) empty_leaf: V,
+    pub(crate) version: usize,
+    /// `history[i]` holds the values overwritten by version `oldest_version() + i + 1`.
+    pub(crate) history: im::Vector<Changeset<F, V>>,
+    /// Generation that created each version from `oldest_version()` to the current one.
+    pub(crate) generations: im::Vector<u64>,
+    /// Generation of the versions created from now on, advanced by every rollback and resize.
+    pub(crate) generation: u64,
+    /// Number of versions kept in `history`, or `None` to keep every version.
+    pub(crate) history_limit: Option<usize>,
+    /// Versions between automatic checkpoints, or `None` to only record them on request.
+    pub(crate) checkpoint_interval: Option<usize>,
+    pub(crate) checkpoints: im::Vector<Checkpoint<F>>,
 }
 
 impl<F: PrimeField, V: Leafable<F>> MerkleTree<F, V> {
     pub fn new(height: usize) -> Self {
-        let poseidon_constants = PoseidonConstants::new();
-        // zero_hashes = reverse([H(zero_leaf), H(H(zero_leaf), H(zero_leaf)), ...])
-        let mut zero_hashes = vec![];
-        let mut h = V::empty_leaf().hash();
-        zero_hashes.push(h);
-        for _ in 0..height {
-            h = Poseidon::new_with_preimage(&[h, h], &poseidon_constants).hash();
-            zero_hashes.push(h);
+        Self::new_with_empty(height, V::empty_leaf())
+    }
+
+    /// Tree whose unset leaves are `empty` instead of `V::empty_leaf()`.
+    pub fn new_with_empty(height: usize, empty: V) -> Self {
+        Self::new_with(height, Arc::new(PoseidonConstants::new()), empty)
+    }
+
+    /// Tree hashing with `constants`, which may be shared with other trees and circuits.
+    pub fn new_with_constants(height: usize, constants: Arc<PoseidonConstants<F, U2>>) -> Self {
+        Self::new_with(height, constants, V::empty_leaf())
+    }
+
+    /// Tree with both the hash constants and the empty leaf chosen by the caller.
+    pub fn new_with(
+        height: usize,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        empty: V,
+    ) -> Self {
+        Self::new_with_store(height, poseidon_constants, empty, im::HashMap::new())
+    }
+}
+
+impl<F: PrimeField, V: Leafable<F>, S: NodeStore<F>> MerkleTree<F, V, S> {
+    /// Like `new_with`, keeping internal nodes in `store`, which must be empty.
+    pub fn new_with_store(
+        height: usize,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        empty: V,
+        store: S,
+    ) -> Self {
+        assert!(store.is_empty(), "node store is not empty");
+        let zero_hashes = Self::zero_hashes(&poseidon_constants, height, empty.hash());
+
+        let leaves = im::HashMap::new();
+
+        Self {
+            poseidon_constants,
+            height,
+            node_hashes: store,
+            leaves,
+            zero_hashes,
+            empty_leaf: empty,
+            version: 0,
+            history: im::Vector::new(),
+            generations: im::vector![0],
+            generation: 0,
+            history_limit: None,
+            checkpoint_interval: None,
+            checkpoints: im::Vector::new(),
         }
-        zero_hashes.reverse();
+    }
 
-        let node_hashes: HashMap<Vec<bool>, F> = HashMap::new();
-        let leaves: HashMap<usize, V> = HashMap::new();
+    /// Reopens a tree whose nodes and leaves were kept in a persistent `store`. The history
+    /// starts over at version 0, while the checkpoints recorded in the store are listed with the
+    /// versions of the session that recorded them.
+    ///
+    /// The nodes are trusted as is; `verify_integrity` checks them against the leaves.
+    pub fn open_with_store(
+        height: usize,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        empty: V,
+        store: S,
+    ) -> Self {
+        let leaves = store.leaves(height);
+        Self::with_store_and_leaves(height, poseidon_constants, empty, store, leaves)
+    }
+
+    /// Tree over the nodes of `store` and the occupied `leaves`, which the store need not hold.
+    pub(crate) fn with_store_and_leaves(
+        height: usize,
+        poseidon_constants: Arc<PoseidonConstants<F, U2>>,
+        empty: V,
+        store: S,
+        leaves: impl IntoIterator<Item = (TreePath, V)>,
+    ) -> Self {
+        let zero_hashes = Self::zero_hashes(&poseidon_constants, height, empty.hash());
+        let empty_hash = empty.hash();
+        let leaves: im::HashMap<TreePath, V> = leaves
+            .into_iter()
+            .filter(|(_, leaf)| leaf.hash() != empty_hash)
+            .collect();
+        assert!(
+            leaves.keys().all(|path| path.depth() == height),
+            "path does not lead to a leaf"
+        );
+
+        let checkpoints = store.checkpoints().into_iter().collect();
 
         Self {
             poseidon_constants,
             height,
-            node_hashes,
+            node_hashes: store,
             leaves,
             zero_hashes,
+            empty_leaf: empty,
+            version: 0,
+            history: im::Vector::new(),
+            generations: im::vector![0],
+            generation: 0,
+            history_limit: None,
+            checkpoint_interval: None,
+            checkpoints,
         }
     }
 
-    fn get_node_hash(&self, path: &Vec<bool>) -> F {
-        assert!(path.len() <= self.height);
+    pub fn poseidon_constants(&self) -> &Arc<PoseidonConstants<F, U2>> {
+        &self.poseidon_constants
+    }
+
+    fn zero_hashes(constants: &PoseidonConstants<F, U2>, height: usize, empty_hash: F) -> Vec<F> {
+        // zero_hashes = reverse([H(zero_leaf), H(H(zero_leaf), H(zero_leaf)), ...])
+        let mut zero_hashes = zero_hash_ladder(constants, empty_hash, height);
+        zero_hashes.reverse();
+
+        zero_hashes
+    }
+
+    /// Rebuilds the tree with `new_height` levels, keeping every leaf at its index.
+    ///
+    /// The history is discarded and the resized tree starts again from version 0.
+    pub fn resize(&mut self, new_height: usize) {
+        let removed = self.height.saturating_sub(new_height);
+        assert!(
+            self.leaves
+                .keys()
+                .all(|path| path.bits()[..removed].iter().all(|&bit| !bit)),
+            "a leaf does not fit in {new_height} levels"
+        );
+
+        let added = new_height.saturating_sub(self.height);
+        let leaves: Vec<(TreePath, V)> = std::mem::take(&mut self.leaves)
+            .into_iter()
+            .map(|(path, leaf)| {
+                let bits = path.bits()[removed..].iter().copied();
+                (
+                    std::iter::repeat_n(false, added).chain(bits).collect(),
+                    leaf,
+                )
+            })
+            .collect();
+        self.height = new_height;
+        self.zero_hashes =
+            Self::zero_hashes(&self.poseidon_constants, new_height, self.empty_leaf.hash());
+        self.node_hashes.clear();
+        self.update_batch_by_path(&leaves);
+        self.node_hashes.commit();
+        self.history.clear();
+        self.version = 0;
+        self.generation += 1;
+        self.generations = im::vector![self.generation];
+    }
+
+    pub(crate) fn get_node_hash(&self, path: &TreePath) -> F {
+        assert!(path.depth() <= self.height);
         match self.node_hashes.get(path) {
-            Some(h) => *h,
-            None => self.zero_hashes[path.len()],
+            Some(h) => h,
+            None => self.zero_hashes[path.depth()],
         }
     }
 
-    fn get_sibling_hash(&self, path: &Vec<bool>) -> F {
-        assert!(!path.is_empty());
-        let mut path = path.clone();
-        let last = path.len() - 1;
-        path[last] = !path[last];
-        self.get_node_hash(&path)
+    fn get_sibling_hash(&self, path: &TreePath) -> F {
+        self.get_node_hash(&path.sibling())
+    }
+
+    /// Changesets of the versions after `version`, oldest first, or `None` if the version does not exist yet.
+    fn changes_since(&self, version: usize) -> Option<im::Vector<Changeset<F, V>>> {
+        if version > self.version || version < self.oldest_version() {
+            return None;
+        }
+
+        let skipped = version - self.oldest_version();
+        Some(self.history.clone().slice(skipped..))
+    }
+
+    /// Hash of the node at `path` as of the first version covered by `changes`.
+    fn get_node_hash_in(&self, changes: &im::Vector<Changeset<F, V>>, path: &TreePath) -> F {
+        for changeset in changes {
+            if let Some(old) = changeset.nodes.get(path) {
+                return old.unwrap_or(self.zero_hashes[path.depth()]);
+            }
+        }
+        self.get_node_hash(path)
+    }
+
+    /// Hash of the node at `path` with the writes `staged` for the next version applied.
+    fn get_staged_node_hash(&self, staged: &HashMap<TreePath, Option<F>>, path: &TreePath) -> F {
+        match staged.get(path) {
+            Some(h) => h.unwrap_or(self.zero_hashes[path.depth()]),
+            None => self.get_node_hash(path),
+        }
+    }
+
+    /// Stages `h` at `path`, to be written to the store with the rest of the version by `commit`.
+    /// Nodes equal to the hash of an empty subtree are removed rather than stored,
+    /// so deleting leaves reclaims their storage.
+    fn set_node_hash(
+        &self,
+        changes: &mut Changeset<F, V>,
+        staged: &mut HashMap<TreePath, Option<F>>,
+        path: TreePath,
+        h: F,
+    ) {
+        if !changes.nodes.contains_key(&path) {
+            changes
+                .nodes
+                .insert(path.clone(), self.node_hashes.get(&path));
+        }
+        let h = (h != self.zero_hashes[path.depth()]).then_some(h);
+        staged.insert(path, h);
+    }
+
+    fn set_leaf(&mut self, changes: &mut Changeset<F, V>, path: TreePath, leaf: V) {
+        let old = if leaf.hash() == self.zero_hashes[self.height] {
+            self.leaves.remove(&path)
+        } else {
+            self.leaves.insert(path.clone(), leaf)
+        };
+        changes.leaves.entry(path).or_insert(old);
+    }
+
+    /// Path of the leaf at `index`.
+    fn leaf_path(&self, index: usize) -> TreePath {
+        assert!(
+            self.height >= usize::BITS as usize || index >> self.height == 0,
+            "index {index} out of range"
+        );
+        TreePath::from_index(index, self.height)
+    }
+
+    fn check_leaf_path(&self, path: &TreePath) {
+        assert_eq!(path.depth(), self.height, "path does not lead to a leaf");
+    }
+
+    /// Writes the `staged` nodes in one batch, with the leaves of `changes`, and records `changes`
+    /// as a new version, forgetting the oldest one if the history is full. A checkpoint due at the
+    /// new version is committed with its nodes.
+    fn commit(&mut self, changes: Changeset<F, V>, staged: HashMap<TreePath, Option<F>>) {
+        self.node_hashes.write_batch(staged);
+        self.node_hashes.write_leaves(
+            changes
+                .leaves
+                .keys()
+                .map(|path| (path.clone(), self.leaves.get(path).cloned())),
+        );
+        let version = self.version + 1;
+        if self
+            .checkpoint_interval
+            .is_some_and(|interval| version.is_multiple_of(interval))
+        {
+            self.record_checkpoint(version);
+        }
+        self.node_hashes.commit();
+        self.history.push_back(changes);
+        self.generations.push_back(self.generation);
+        self.version += 1;
+        self.trim_history();
+    }
+
+    fn trim_history(&mut self) {
+        if let Some(limit) = self.history_limit {
+            while self.history.len() > limit {
+                self.history.pop_front();
+                self.generations.pop_front();
+            }
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Value of every leaf that has not been set.
+    pub fn empty_leaf(&self) -> &V {
+        &self.empty_leaf
+    }
+
+    /// Hash of an empty subtree whose root is at `depth`, so `zero_hash(height())` is the empty leaf hash.
+    pub fn zero_hash(&self, depth: usize) -> F {
+        self.zero_hashes[depth]
+    }
+
+    /// Number of non-empty leaves.
+    pub fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_leaves() == 0
+    }
+
+    /// Number of stored internal nodes. Nodes of empty subtrees are not stored.
+    pub fn node_count(&self) -> usize {
+        self.node_hashes.len()
+    }
+
+    /// Rough number of heap bytes held by the current state, ignoring hash map overhead.
+    pub fn memory_usage(&self) -> usize {
+        let nodes: usize = self
+            .node_hashes
+            .iter()
+            .map(|(path, _)| path.heap_size() + size_of::<TreePath>() + size_of::<F>())
+            .sum();
+        let leaves: usize = self
+            .leaves
+            .keys()
+            .map(|path| path.heap_size() + size_of::<TreePath>() + size_of::<V>())
+            .sum();
+        let zero_hashes = self.zero_hashes.capacity() * size_of::<F>();
+
+        nodes + leaves + zero_hashes
+    }
+
+    /// Rough number of heap bytes held by the version history, ignoring hash map overhead.
+    pub fn history_memory_usage(&self) -> usize {
+        self.history
+            .iter()
+            .map(|changes| {
+                let nodes: usize = changes
+                    .nodes
+                    .keys()
+                    .map(|path| path.heap_size() + size_of::<TreePath>() + size_of::<Option<F>>())
+                    .sum();
+                let leaves: usize = changes
+                    .leaves
+                    .keys()
+                    .map(|path| path.heap_size() + size_of::<TreePath>() + size_of::<Option<V>>())
+                    .sum();
+                nodes + leaves
+            })
+            .sum()
+    }
+
+    /// Current version. Every update creates a new version, starting from 0 for the empty tree.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    pub fn snapshot(&self) -> SnapshotId {
+        SnapshotId {
+            version: self.version,
+            generation: self.generation_of(self.version),
+        }
+    }
+
+    /// Snapshot restoring `checkpoint` with `rollback`, or `None` if the tree no longer retains
+    /// its version or has since rolled back past it.
+    pub fn checkpoint_snapshot(&self, checkpoint: &Checkpoint<F>) -> Option<SnapshotId> {
+        if self.get_root_at(checkpoint.version)? != checkpoint.root {
+            return None;
+        }
+
+        Some(SnapshotId {
+            version: checkpoint.version,
+            generation: self.generation_of(checkpoint.version),
+        })
+    }
+
+    /// Generation that created the retained `version`.
+    fn generation_of(&self, version: usize) -> u64 {
+        self.generations[version - self.oldest_version()]
+    }
+
+    /// Whether `snapshot` is a retained version of this tree that no rollback has discarded.
+    fn is_retained(&self, snapshot: SnapshotId) -> bool {
+        (self.oldest_version()..=self.version).contains(&snapshot.version)
+            && self.generation_of(snapshot.version) == snapshot.generation
+    }
+
+    /// Oldest version that can still be looked up or restored.
+    pub fn oldest_version(&self) -> usize {
+        self.version - self.history.len()
+    }
+
+    pub fn history_limit(&self) -> Option<usize> {
+        self.history_limit
+    }
+
+    /// Keeps only the latest `limit` versions besides the current one, or every version if `None`.
+    /// Older versions are forgotten immediately and on every later update.
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.history_limit = limit;
+        self.trim_history();
+    }
+
+    pub fn checkpoint_interval(&self) -> Option<usize> {
+        self.checkpoint_interval
+    }
+
+    /// Records a checkpoint with every version that is a multiple of `interval`, or only on
+    /// request with `checkpoint` if `None`.
+    pub fn set_checkpoint_interval(&mut self, interval: Option<usize>) {
+        assert_ne!(interval, Some(0), "checkpoint interval must be positive");
+        self.checkpoint_interval = interval;
+    }
+
+    /// Records a checkpoint of the current version and commits it to the node store.
+    pub fn checkpoint(&mut self) -> Checkpoint<F> {
+        let checkpoint = self.record_checkpoint(self.version);
+        self.node_hashes.commit();
+
+        checkpoint
+    }
+
+    /// Checkpoints in the order they were recorded, including those read from the node store by
+    /// `open_with_store`. Versions repeat after a rollback or resize.
+    pub fn list_checkpoints(&self) -> Vec<Checkpoint<F>> {
+        self.checkpoints.iter().copied().collect()
+    }
+
+    /// Records a checkpoint of the root as it will be once the store commits `version`.
+    fn record_checkpoint(&mut self, version: usize) -> Checkpoint<F> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let checkpoint = Checkpoint {
+            version,
+            root: self.get_root(),
+            timestamp,
+        };
+        self.node_hashes.record_checkpoint(checkpoint);
+        self.checkpoints.push_back(checkpoint);
+
+        checkpoint
+    }
+
+    /// Reverts every update made after `snapshot`. Versions newer than the snapshot are discarded,
+    /// and so are the snapshots taken of them.
+    pub fn rollback(&mut self, snapshot: SnapshotId) {
+        assert!(snapshot.version <= self.version, "snapshot is newer than the tree");
+        assert!(
+            snapshot.version >= self.oldest_version(),
+            "snapshot is older than the retained history"
+        );
+        assert!(
+            self.is_retained(snapshot),
+            "snapshot was discarded by a rollback"
+        );
+
+        let mut restored = HashSet::new();
+        while self.version > snapshot.version {
+            let changes = self.history.pop_back().unwrap();
+            self.generations.pop_back();
+            self.node_hashes.write_batch(changes.nodes);
+            for (path, old) in changes.leaves {
+                match old {
+                    Some(leaf) => self.leaves.insert(path.clone(), leaf),
+                    None => self.leaves.remove(&path),
+                };
+                restored.insert(path);
+            }
+            self.version -= 1;
+        }
+        self.generation += 1;
+        self.node_hashes.write_leaves(
+            restored
+                .into_iter()
+                .map(|path| (path.clone(), self.leaves.get(&path).cloned())),
+        );
+        self.node_hashes.commit();
     }
 
     pub fn get_root(&self) -> F {
-        self.get_node_hash(&vec![])
+        self.get_node_hash(&TreePath::root())
+    }
+
+    /// Root as of `version`, or `None` if the version does not exist yet or is no longer retained.
+    pub fn get_root_at(&self, version: usize) -> Option<F> {
+        let changes = self.changes_since(version)?;
+        Some(self.get_node_hash_in(&changes, &TreePath::root()))
+    }
+
+    /// Hash of the `index`-th node at depth `level`, where level 0 is the root
+    /// and level `height` holds the leaf hashes.
+    pub fn get_node(&self, level: usize, index: usize) -> F {
+        assert!(level <= self.height, "level {level} is below the leaves");
+        assert!(
+            index_fits(index, level),
+            "index {index} out of range at level {level}"
+        );
+        self.get_node_hash(&TreePath::from_index(index, level))
+    }
+
+    /// Root of the `index`-th subtree with `subtree_height` levels, counted from the leaves.
+    /// The subtree covers the leaves `index << subtree_height .. (index + 1) << subtree_height`.
+    pub fn get_subtree_root(&self, subtree_height: usize, index: usize) -> F {
+        assert!(subtree_height <= self.height);
+        self.get_node(self.height - subtree_height, index)
     }
 
     pub fn get_leaf(&self, index: usize) -> V {
-        match self.leaves.get(&index) {
+        self.get_leaf_by_path(&self.leaf_path(index))
+    }
+
+    pub fn get_leaf_by_path(&self, path: &TreePath) -> V {
+        self.check_leaf_path(path);
+        match self.leaves.get(path) {
             Some(leaf) => leaf.clone(),
-            None => V::empty_leaf(),
+            None => self.empty_leaf.clone(),
         }
     }
 
-    pub fn update(&mut self, index: usize, leaf: V) {
-        let mut path = usize_to_vec(index, self.height);
+    /// Non-empty leaves in index order, or an error if a leaf is beyond the range of `usize`
+    /// indices; `iter_leaves_by_path` lists those as well.
+    pub fn iter_leaves(&self) -> Result<impl Iterator<Item = (usize, &V)> + '_, IndexOverflow> {
+        let leaves = self
+            .iter_leaves_by_path()
+            .map(|(path, leaf)| Ok((checked_index(path)?, leaf)))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        self.leaves.insert(index, leaf.clone());
+        Ok(leaves.into_iter())
+    }
 
-        let mut h = leaf.hash();
-        self.node_hashes.insert(path.clone(), h);
+    /// Non-empty leaves in path order, which is index order.
+    pub fn iter_leaves_by_path(&self) -> impl Iterator<Item = (&TreePath, &V)> + '_ {
+        let mut leaves: Vec<(&TreePath, &V)> = self.leaves.iter().collect();
+        leaves.sort_unstable_by_key(|&(path, _)| path);
 
-        while !path.is_empty() {
-            let sibling = self.get_sibling_hash(&path);
-            let preimage = if path.pop().unwrap() {
-                vec![sibling, h]
+        leaves.into_iter()
+    }
+
+    /// Leaf at `index` as of `version`, or `None` if the version does not exist yet or is no longer retained.
+    pub fn get_leaf_at(&self, version: usize, index: usize) -> Option<V> {
+        let path = self.leaf_path(index);
+        let changes = self.changes_since(version)?;
+        for changeset in &changes {
+            if let Some(old) = changeset.leaves.get(&path) {
+                return Some(old.clone().unwrap_or_else(|| self.empty_leaf.clone()));
+            }
+        }
+        Some(self.get_leaf_by_path(&path))
+    }
+
+    /// Sets the leaf at `index` and returns the leaf it replaced.
+    pub fn update(&mut self, index: usize, leaf: V) -> V {
+        self.update_by_path(self.leaf_path(index), leaf)
+    }
+
+    pub fn update_by_path(&mut self, path: TreePath, leaf: V) -> V {
+        let old = self.get_leaf_by_path(&path);
+        self.update_batch_by_path(&[(path, leaf)]);
+        old
+    }
+
+    /// Applies all `updates` as a single version, rehashing each affected internal node once.
+    /// Later updates of the same index take precedence.
+    pub fn update_batch(&mut self, updates: &[(usize, V)]) {
+        let updates: Vec<(TreePath, V)> = updates
+            .iter()
+            .map(|(index, leaf)| (self.leaf_path(*index), leaf.clone()))
+            .collect();
+        self.update_batch_by_path(&updates)
+    }
+
+    /// Like `update_batch`, with each leaf given by its path.
+    pub fn update_batch_by_path(&mut self, updates: &[(TreePath, V)]) {
+        if updates.is_empty() {
+            return;
+        }
+
+        let mut changes = Changeset::default();
+        let mut staged = HashMap::new();
+
+        let mut paths = BTreeSet::new();
+        for (path, leaf) in updates {
+            self.check_leaf_path(path);
+            self.set_leaf(&mut changes, path.clone(), leaf.clone());
+            self.set_node_hash(&mut changes, &mut staged, path.clone(), leaf.hash());
+            paths.insert(path.clone());
+        }
+
+        for _ in 0..self.height {
+            let parents: BTreeSet<TreePath> = paths
+                .into_iter()
+                .map(|mut path| {
+                    path.pop();
+                    path
+                })
+                .collect();
+            for path in &parents {
+                let (l, r) = (
+                    self.get_staged_node_hash(&staged, &path.child(false)),
+                    self.get_staged_node_hash(&staged, &path.child(true)),
+                );
+                let h = hash_pair(&self.poseidon_constants, l, r);
+                self.set_node_hash(&mut changes, &mut staged, path.clone(), h);
+            }
+            paths = parents;
+        }
+        self.commit(changes, staged);
+    }
+
+    /// Replaces the `index`-th subtree with `subtree.height()` levels by `subtree`, as a single version.
+    ///
+    /// The nodes of `subtree` are copied as they are, so only the path from the subtree root
+    /// up to the root is rehashed. Both trees must share their hash constants and empty leaf.
+    pub fn insert_subtree(&mut self, index: usize, subtree: &Self) {
+        assert!(
+            subtree.height <= self.height,
+            "subtree is taller than the tree"
+        );
+        let level = self.height - subtree.height;
+        assert!(
+            index_fits(index, level),
+            "subtree index {index} out of range"
+        );
+        assert!(
+            subtree.zero_hashes[..] == self.zero_hashes[level..],
+            "subtree hashes with different constants or empty leaf"
+        );
+
+        let mut changes = Changeset::default();
+        let mut staged = HashMap::new();
+        let prefix = TreePath::from_index(index, level);
+
+        let old_nodes: Vec<TreePath> = self
+            .node_hashes
+            .iter()
+            .map(|(path, _)| path)
+            .filter(|path| path.starts_with(&prefix))
+            .collect();
+        for path in old_nodes {
+            let zero = self.zero_hashes[path.depth()];
+            self.set_node_hash(&mut changes, &mut staged, path, zero);
+        }
+        let old_leaves: Vec<TreePath> = self
+            .leaves
+            .keys()
+            .filter(|path| path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for path in old_leaves {
+            self.set_leaf(&mut changes, path, self.empty_leaf.clone());
+        }
+
+        for (path, h) in subtree.node_hashes.iter() {
+            self.set_node_hash(&mut changes, &mut staged, prefix.join(&path), h);
+        }
+        for (path, leaf) in &subtree.leaves {
+            self.set_leaf(&mut changes, prefix.join(path), leaf.clone());
+        }
+
+        let mut path = prefix;
+        while path.pop().is_some() {
+            let (l, r) = (
+                self.get_staged_node_hash(&staged, &path.child(false)),
+                self.get_staged_node_hash(&staged, &path.child(true)),
+            );
+            let h = hash_pair(&self.poseidon_constants, l, r);
+            self.set_node_hash(&mut changes, &mut staged, path.clone(), h);
+        }
+        self.commit(changes, staged);
+    }
+
+    /// Adds the occupied leaves of `other` to this tree as a single version.
+    ///
+    /// Both trees must have the same height, hash constants and empty leaf.
+    pub fn merge(&mut self, other: &Self, policy: ConflictPolicy) -> Result<(), MergeConflict> {
+        assert!(
+            self.zero_hashes == other.zero_hashes,
+            "merged trees differ in height, constants or empty leaf"
+        );
+
+        let mut updates = vec![];
+        for (path, leaf) in other.iter_leaves_by_path() {
+            match self.leaves.get(path) {
+                None => updates.push((path.clone(), leaf.clone())),
+                Some(ours) if ours.hash() == leaf.hash() => {}
+                Some(_) => match policy {
+                    ConflictPolicy::KeepOurs => {}
+                    ConflictPolicy::TakeTheirs => updates.push((path.clone(), leaf.clone())),
+                    ConflictPolicy::Fail => return Err(MergeConflict { path: path.clone() }),
+                },
+            }
+        }
+        self.update_batch_by_path(&updates);
+
+        Ok(())
+    }
+
+    /// Leaves that differ from `other`, as `(index, leaf in self, leaf in other)` sorted by index,
+    /// or an error if one of them is beyond the range of `usize` indices.
+    pub fn diff(&self, other: &Self) -> Result<Vec<(usize, V, V)>, IndexOverflow> {
+        let mut indices: Vec<usize> = self
+            .leaves
+            .keys()
+            .chain(other.leaves.keys())
+            .map(checked_index)
+            .collect::<Result<_, _>>()?;
+        indices.sort_unstable();
+        indices.dedup();
+
+        Ok(self.diff_leaves(
+            indices,
+            |index| self.get_leaf(index),
+            |index| other.get_leaf(index),
+        ))
+    }
+
+    /// Leaves changed since `snapshot`, as `(index, old leaf, current leaf)` sorted by index,
+    /// or an error if one of them is beyond the range of `usize` indices.
+    pub fn diff_since(&self, snapshot: SnapshotId) -> Result<Vec<(usize, V, V)>, IndexOverflow> {
+        assert!(
+            self.is_retained(snapshot),
+            "snapshot is not in the retained history"
+        );
+        let changes = self.changes_since(snapshot.version).unwrap();
+        let mut indices: Vec<usize> = changes
+            .iter()
+            .flat_map(|changeset| changeset.leaves.keys())
+            .map(checked_index)
+            .collect::<Result<_, _>>()?;
+        indices.sort_unstable();
+        indices.dedup();
+
+        Ok(self.diff_leaves(
+            indices,
+            |index| self.get_leaf_at(snapshot.version, index).unwrap(),
+            |index| self.get_leaf(index),
+        ))
+    }
+
+    fn diff_leaves(
+        &self,
+        indices: Vec<usize>,
+        old: impl Fn(usize) -> V,
+        new: impl Fn(usize) -> V,
+    ) -> Vec<(usize, V, V)> {
+        indices
+            .into_iter()
+            .map(|index| (index, old(index), new(index)))
+            .filter(|(_, old, new)| old.hash() != new.hash())
+            .collect()
+    }
+
+    /// Clears every leaf in `range` as a single version, replacing whole subtrees by empty ones
+    /// so that only the paths above the cleared subtrees are rehashed.
+    pub fn clear_range(&mut self, range: Range<usize>) {
+        let blocks = aligned_blocks(self.height, range.clone());
+        if blocks.is_empty() {
+            return;
+        }
+
+        let mut changes = Changeset::default();
+        let mut staged = HashMap::new();
+        let height = self.height;
+        // Subtrees too large or too far right for `usize` indices are never inside `range`.
+        let covered = |path: &TreePath| {
+            let size = 1usize.checked_shl((height - path.depth()) as u32);
+            let first = path
+                .try_to_index()
+                .zip(size)
+                .and_then(|(index, size)| index.checked_mul(size));
+            match (first, size) {
+                (Some(first), Some(size)) => {
+                    first >= range.start
+                        && first.checked_add(size).is_some_and(|end| end <= range.end)
+                }
+                _ => false,
+            }
+        };
+        let cleared_nodes: Vec<TreePath> = self
+            .node_hashes
+            .iter()
+            .map(|(path, _)| path)
+            .filter(|path| covered(path))
+            .collect();
+        for path in cleared_nodes {
+            let zero = self.zero_hashes[path.depth()];
+            self.set_node_hash(&mut changes, &mut staged, path, zero);
+        }
+        let cleared_leaves: Vec<TreePath> = self
+            .leaves
+            .keys()
+            .filter(|path| {
+                path.try_to_index()
+                    .is_some_and(|index| range.contains(&index))
+            })
+            .cloned()
+            .collect();
+        for path in cleared_leaves {
+            self.set_leaf(&mut changes, path, self.empty_leaf.clone());
+        }
+
+        let mut ancestors = BTreeSet::new();
+        for &(level, index) in &blocks {
+            let mut path = TreePath::from_index(index, self.height - level);
+            while path.pop().is_some() {
+                ancestors.insert(path.clone());
+            }
+        }
+        let mut ancestors: Vec<TreePath> = ancestors.into_iter().collect();
+        ancestors.sort_by_key(|path| std::cmp::Reverse(path.depth()));
+
+        for path in ancestors {
+            let (l, r) = (
+                self.get_staged_node_hash(&staged, &path.child(false)),
+                self.get_staged_node_hash(&staged, &path.child(true)),
+            );
+            let h = hash_pair(&self.poseidon_constants, l, r);
+            self.set_node_hash(&mut changes, &mut staged, path, h);
+        }
+        self.commit(changes, staged);
+    }
+
+    /// Drops stored nodes that are not on the path of any occupied leaf and returns how many were dropped.
+    ///
+    /// Such nodes should hash to empty subtrees and are not reachable from the root, but may be left
+    /// behind by earlier versions of the tree or by trees loaded from storage. Dropping them is a
+    /// version of its own, with the same root, so `rollback` and `diff_since` see the nodes as
+    /// they were before it. Nothing is recorded if no node is dropped.
+    pub fn compact(&mut self) -> usize {
+        let mut live = HashSet::new();
+        for path in self.leaves.keys() {
+            let mut path = path.clone();
+            while live.insert(path.clone()) && path.pop().is_some() {}
+        }
+
+        let dropped: Vec<TreePath> = self
+            .node_hashes
+            .iter()
+            .map(|(path, _)| path)
+            .filter(|path| !live.contains(path))
+            .collect();
+        if dropped.is_empty() {
+            return 0;
+        }
+
+        let count = dropped.len();
+        let mut changes = Changeset::default();
+        let mut staged = HashMap::new();
+        for path in dropped {
+            let zero = self.zero_hashes[path.depth()];
+            self.set_node_hash(&mut changes, &mut staged, path, zero);
+        }
+        self.commit(changes, staged);
+
+        count
+    }
+
+    /// Rehashes every stored node from the leaves up and reports the first one, deepest first,
+    /// that does not match its children. Unstored parents of stored nodes are checked as empty subtrees.
+    pub fn verify_integrity(&self) -> Result<(), IntegrityError> {
+        let mut paths: BTreeSet<TreePath> = BTreeSet::new();
+        let nodes = self.node_hashes.iter().map(|(path, _)| path);
+        for path in nodes.chain(self.leaves.keys().cloned()) {
+            let mut parent = path.clone();
+            if parent.pop().is_some() {
+                paths.insert(parent);
+            }
+            paths.insert(path);
+        }
+        let mut paths: Vec<TreePath> = paths.into_iter().collect();
+        paths.sort_by_key(|path| std::cmp::Reverse(path.depth()));
+
+        for path in paths {
+            let expected = if path.depth() == self.height {
+                self.get_leaf_by_path(&path).hash()
             } else {
-                vec![h, sibling]
+                hash_pair(
+                    &self.poseidon_constants,
+                    self.get_node_hash(&path.child(false)),
+                    self.get_node_hash(&path.child(true)),
+                )
             };
-            h = Poseidon::new_with_preimage(&preimage, &self.poseidon_constants).hash();
-            self.node_hashes.insert(path.clone(), h);
+            if self.get_node_hash(&path) != expected {
+                return Err(IntegrityError { path });
+            }
         }
+
+        Ok(())
     }
 
+    /// Clears the leaf at `index`, dropping every node that becomes the hash of an empty subtree.
     pub fn remove(&mut self, index: usize) {
-        self.update(index, V::empty_leaf())
+        self.update(index, self.empty_leaf.clone());
     }
 
     pub fn prove(&self, index: usize) -> Vec<F> {
-        let mut path = usize_to_vec(index, self.height);
+        self.prove_by_path(&self.leaf_path(index))
+    }
+
+    /// Siblings of the leaf at `path` from the leaf upwards, checked by `verify_merkle_proof_by_path`.
+    pub fn prove_by_path(&self, path: &TreePath) -> Vec<F> {
+        self.check_leaf_path(path);
+        let mut path = path.clone();
         let mut siblings = vec![];
-        while !path.is_empty() {
+        while !path.is_root() {
             siblings.push(self.get_sibling_hash(&path));
             path.pop();
         }
 
         siblings
     }
+
+    /// Like `prove`, yielding `(level, sibling, lr_bit)` from the leaf upwards without collecting
+    /// the siblings, where `lr_bit` is set if the node on the path at `level` is a right child.
+    pub fn audit_path_iter(&self, index: usize) -> impl Iterator<Item = (usize, F, bool)> + '_ {
+        self.audit_path_iter_by_path(&self.leaf_path(index))
+    }
+
+    /// Like `audit_path_iter`, for the leaf at `path`.
+    pub fn audit_path_iter_by_path(
+        &self,
+        path: &TreePath,
+    ) -> impl Iterator<Item = (usize, F, bool)> + '_ {
+        self.check_leaf_path(path);
+        let mut path = path.clone();
+        let mut level = 0;
+        std::iter::from_fn(move || {
+            if path.is_root() {
+                return None;
+            }
+            let sibling = self.get_sibling_hash(&path);
+            let lr_bit = path.pop()?;
+            level += 1;
+            Some((level - 1, sibling, lr_bit))
+        })
+    }
+
+    /// Proves that the leaf at `index` is the empty leaf. Returns `None` if it is occupied.
+    pub fn prove_empty(&self, index: usize) -> Option<Vec<F>> {
+        if self.leaves.contains_key(&self.leaf_path(index)) {
+            return None;
+        }
+
+        Some(self.prove(index))
+    }
+
+    /// Checks a proof from `prove_empty` against `root` of a tree of `height` levels, where
+    /// `empty` is the empty leaf of the tree.
+    pub fn verify_empty(
+        constants: &PoseidonConstants<F, U2>,
+        root: F,
+        height: usize,
+        index: usize,
+        siblings: &[F],
+        empty: &V,
+    ) -> bool {
+        verify::verify_empty(constants, root, height, index, empty.hash(), siblings)
+    }
+
+    /// Siblings of the leaf at `index` as of `version`, valid against `get_root_at(version)`.
+    pub fn prove_at(&self, version: usize, index: usize) -> Option<Vec<F>> {
+        let mut path = self.leaf_path(index);
+        let changes = self.changes_since(version)?;
+        let mut siblings = vec![];
+        while !path.is_root() {
+            siblings.push(self.get_node_hash_in(&changes, &path.sibling()));
+            path.pop();
+        }
+
+        Some(siblings)
+    }
+
+    /// Siblings of the leaf at `index` valid against `root`, which may be the current root or that
+    /// of any retained version. Returns `None` if no retained version has this root.
+    pub fn prove_at_root(&self, root: F, index: usize) -> Option<Vec<F>> {
+        let mut version = self.version;
+        let mut current = self.get_root();
+        for changes in self.history.iter().rev() {
+            if current == root {
+                break;
+            }
+            version -= 1;
+            if let Some(old) = changes.nodes.get(&TreePath::root()) {
+                current = old.unwrap_or(self.zero_hashes[0]);
+            }
+        }
+        if current != root {
+            return None;
+        }
+
+        self.prove_at(version, index)
+    }
+}
+
+/// Poseidon hash of two sibling nodes.
+pub(crate) fn hash_pair<F: PrimeField>(
+    constants: &PoseidonConstants<F, U2>,
+    left: F,
+    right: F,
+) -> F {
+    Poseidon::new_with_preimage(&[left, right], constants).hash()
+}
+
+/// Whether `index` is the index of a leaf of a tree of `height` levels.
+pub fn index_fits(index: usize, height: usize) -> bool {
+    index.checked_shr(height as u32).unwrap_or(0) == 0
+}
+
+/// Root implied by `leaf_hash` at `index` and its `siblings` from the leaf upwards.
+///
+/// Panics if `index` is not below `2^siblings.len()`: no other index would select the same leaf.
+/// Check proofs from untrusted sources with `verify_merkle_proof`, which rejects it instead.
+pub fn compute_merkle_root<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    index: usize,
+    leaf_hash: F,
+    siblings: &[F],
+) -> F {
+    assert!(
+        index_fits(index, siblings.len()),
+        "index {index} is out of range for {} levels",
+        siblings.len()
+    );
+    let path = TreePath::from_index(index, siblings.len());
+    compute_merkle_root_by_path(hasher, &path, leaf_hash, siblings)
+}
+
+/// Like `compute_merkle_root`, for the leaf at `path`.
+pub fn compute_merkle_root_by_path<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    path: &TreePath,
+    leaf_hash: F,
+    siblings: &[F],
+) -> F {
+    assert_eq!(path.depth(), siblings.len(), "proof of another tree height");
+    let mut h = leaf_hash;
+    for (lr_bit, &sibling) in path.lr_bits().zip(siblings.iter()) {
+        h = if lr_bit {
+            hasher.hash_pair(sibling, h)
+        } else {
+            hasher.hash_pair(h, sibling)
+        };
+    }
+
+    h
+}
+
+/// Checks siblings from `MerkleTree::prove` against `root` of a tree of `height` levels.
+///
+/// `height` must come from the verifier, not the proof: a shorter proof ending at `root` would
+/// otherwise prove an internal node as a leaf. Indices beyond the tree are rejected.
+pub fn verify_merkle_proof<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    root: F,
+    height: usize,
+    index: usize,
+    leaf_hash: F,
+    siblings: &[F],
+) -> bool {
+    siblings.len() == height
+        && index_fits(index, height)
+        && compute_merkle_root(hasher, index, leaf_hash, siblings) == root
+}
+
+/// Checks siblings from `MerkleTree::prove_by_path` against `root` of a tree of `height` levels.
+pub fn verify_merkle_proof_by_path<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    root: F,
+    height: usize,
+    path: &TreePath,
+    leaf_hash: F,
+    siblings: &[F],
+) -> bool {
+    path.depth() == height
+        && siblings.len() == height
+        && compute_merkle_root_by_path(hasher, path, leaf_hash, siblings) == root
+}
+
+/// Largest aligned subtrees covering `range` in a tree of `height` levels, in index order,
+/// as `(subtree height, subtree index)` like the arguments of `MerkleTree::get_subtree_root`.
+pub fn aligned_blocks(height: usize, range: Range<usize>) -> Vec<(usize, usize)> {
+    assert!(
+        height >= usize::BITS as usize || range.end <= 1 << height,
+        "range exceeds the tree"
+    );
+
+    let mut blocks = vec![];
+    let mut start = range.start;
+    while start < range.end {
+        let mut level = 0;
+        // A block of `2 << level` leaves must also be addressable by a `usize` range.
+        while level < height
+            && level + 1 < usize::BITS as usize
+            && start.is_multiple_of(2 << level)
+            && start
+                .checked_add(2 << level)
+                .is_some_and(|end| end <= range.end)
+        {
+            level += 1;
+        }
+        blocks.push((level, start >> level));
+        start += 1 << level;
+    }
+
+    blocks
 }
 
 /// usize to big endian bool vec.
+#[deprecated(note = "use `TreePath::from_index`")]
 pub fn usize_to_vec(x: usize, length: usize) -> Vec<bool> {
-    let mut x = x;
-    let mut v = vec![];
-    for _ in 0..length {
-        v.push((x & 1) == 1);
-        x >>= 1;
-    }
-    v.reverse();
-    v
+    TreePath::from_index(x, length).bits().to_vec()
 }
@@ -0,0 +1,157 @@
+//! Checks of proofs from roots, positions, hashes and siblings alone, so that light clients
+//! can verify proofs without building a `MerkleTree`.
+
+use std::collections::HashMap;
+
+use ff::PrimeField;
+
+pub use super::tree::{
+    compute_merkle_root, compute_merkle_root_by_path, index_fits, verify_merkle_proof,
+    verify_merkle_proof_by_path,
+};
+use super::{
+    hasher::MerkleHasher,
+    path::{MerklePath, MerkleProcessProof, TreePath},
+};
+
+/// Checks that the leaf at `index` is the empty leaf, which hashes to `empty_hash`.
+pub fn verify_empty<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    root: F,
+    height: usize,
+    index: usize,
+    empty_hash: F,
+    siblings: &[F],
+) -> bool {
+    verify_merkle_proof(hasher, root, height, index, empty_hash, siblings)
+}
+
+/// Checks that replacing the leaf at `index` hashing to `old_hash` by one hashing to `new_hash`
+/// takes the tree from `old_root` to `new_root`, as a `MerkleProcessCircuit` step does.
+///
+/// This is the native counterpart of `StepCircuit::output` for that step, without the
+/// synthesis, so a step can be rejected before it is queued for proving.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_update<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    old_root: F,
+    new_root: F,
+    height: usize,
+    index: usize,
+    old_hash: F,
+    new_hash: F,
+    siblings: &[F],
+) -> bool {
+    verify_merkle_proof(hasher, old_root, height, index, old_hash, siblings)
+        && verify_merkle_proof(hasher, new_root, height, index, new_hash, siblings)
+}
+
+/// Checks a proof from `MerkleTree::prove_insert`: the leaf at its index was the empty leaf,
+/// hashing to `empty_hash`, in `old_root`, and inserting its new value yields `new_root`.
+pub fn verify_insert<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    old_root: F,
+    new_root: F,
+    height: usize,
+    empty_hash: F,
+    proof: &MerkleProcessProof<F>,
+) -> bool {
+    proof.old_value == empty_hash
+        && verify_update(
+            hasher,
+            old_root,
+            new_root,
+            height,
+            proof.index,
+            proof.old_value,
+            proof.new_value,
+            &proof.siblings,
+        )
+}
+
+/// Checks every proof against `root` of a tree of `height` levels, hashing each node shared by
+/// several paths once.
+///
+/// A path stops as soon as it reaches a node already computed by an earlier path, since that
+/// node is known to lead to `root`.
+pub fn verify_batch<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    root: F,
+    height: usize,
+    proofs: &[MerklePath<F>],
+) -> bool {
+    if proofs
+        .iter()
+        .any(|proof| proof.height() != height || !index_fits(proof.index, height))
+    {
+        return false;
+    }
+
+    // Computed nodes by (height above the leaves, position at that height).
+    let mut known: HashMap<(usize, usize), F> = HashMap::new();
+    for proof in proofs {
+        let path = TreePath::from_index(proof.index, proof.height());
+        let mut position = proof.index;
+        let mut h = proof.leaf_hash;
+        let mut reached = false;
+        for (level, (lr_bit, &sibling)) in path.lr_bits().zip(proof.siblings.iter()).enumerate() {
+            match known.insert((level, position), h) {
+                Some(previous) if previous == h => {
+                    reached = true;
+                    break;
+                }
+                Some(_) => return false,
+                None => {}
+            }
+            h = if lr_bit {
+                hasher.hash_pair(sibling, h)
+            } else {
+                hasher.hash_pair(h, sibling)
+            };
+            position >>= 1;
+        }
+        if !reached && h != root {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Like `compute_merkle_root`, without branching on the bits of `index`.
+///
+/// Each level orders the pair with `h + bit * (sibling - h)` as the circuit does, so the
+/// time taken does not depend on the position of the leaf.
+pub fn compute_merkle_root_constant_time<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    index: usize,
+    leaf_hash: F,
+    siblings: &[F],
+) -> F {
+    let mut h = leaf_hash;
+    for (level, &sibling) in siblings.iter().enumerate() {
+        let bit = F::from((index.checked_shr(level as u32).unwrap_or(0) & 1) as u64);
+        let left = h + bit * (sibling - h);
+        let right = sibling + bit * (h - sibling);
+        h = hasher.hash_pair(left, right);
+    }
+
+    h
+}
+
+/// Like `verify_merkle_proof`, without branching on the bits of `index` or on where the roots
+/// differ. The height and range of `index` are public and checked first.
+pub fn verify_merkle_proof_constant_time<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    root: F,
+    height: usize,
+    index: usize,
+    leaf_hash: F,
+    siblings: &[F],
+) -> bool {
+    if siblings.len() != height || !index_fits(index, height) {
+        return false;
+    }
+    let computed = compute_merkle_root_constant_time(hasher, index, leaf_hash, siblings);
+    (computed - root).is_zero().into()
+}
@@ -0,0 +1,60 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use ff::PrimeField;
+use generic_array::typenum::U2;
+use neptune::poseidon::PoseidonConstants;
+
+use super::hasher::MerkleHasher;
+
+/// Field, domain tag, full and partial rounds of the constants, and the empty leaf hash.
+///
+/// Constants are generated deterministically from the field, arity, hash type and strength,
+/// which the domain tag and round counts capture.
+type LadderKey = (TypeId, Vec<u8>, usize, usize, Vec<u8>);
+
+/// Ladders computed so far, each a `Vec<F>` for the field of its key.
+static LADDERS: OnceLock<Mutex<HashMap<LadderKey, Box<dyn Any + Send + Sync>>>> = OnceLock::new();
+
+/// Hashes of the empty subtrees of height `0..=height` over `empty_hash`, leaf first.
+///
+/// The chain is computed once per process for each hash constants and empty leaf and shared
+/// by every tree using them, extending it when a taller tree asks for more levels.
+pub(crate) fn zero_hash_ladder<F: PrimeField>(
+    constants: &PoseidonConstants<F, U2>,
+    empty_hash: F,
+    height: usize,
+) -> Vec<F> {
+    let key = (
+        TypeId::of::<F>(),
+        constants.domain_tag.to_repr().as_ref().to_vec(),
+        constants.full_rounds,
+        constants.partial_rounds,
+        empty_hash.to_repr().as_ref().to_vec(),
+    );
+
+    let mut ladders = LADDERS.get_or_init(Default::default).lock().unwrap();
+    let ladder = ladders
+        .entry(key)
+        .or_insert_with(|| Box::new(vec![empty_hash]))
+        .downcast_mut::<Vec<F>>()
+        .expect("ladders are keyed by their field");
+    extend_ladder(constants, ladder, height);
+
+    ladder[..=height].to_vec()
+}
+
+/// Extends `ladder`, which starts from the empty leaf, up to the empty subtree of `height`.
+pub(crate) fn extend_ladder<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    ladder: &mut Vec<F>,
+    height: usize,
+) {
+    while ladder.len() <= height {
+        let h = ladder[ladder.len() - 1];
+        ladder.push(hasher.hash_pair(h, h));
+    }
+}
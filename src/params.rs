@@ -0,0 +1,188 @@
+use std::io::{Read, Write};
+
+use generic_array::typenum::U2;
+use neptune::{poseidon::PoseidonConstants, Strength};
+use nova_snark::{traits::circuit::TrivialTestCircuit, PublicParams};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+
+use crate::{
+    merkle_tree::{
+        circuits::MerkleProcessCircuit,
+        hasher::RescuePrime,
+        node_store::NodeStore,
+        tree::{Leafable, MerkleTree},
+        zero_hashes::{self, extend_ladder},
+    },
+    prover::{MerklePublicParams, F1, MAX_HEIGHT},
+};
+
+/// Hash function of internal nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HasherId {
+    Poseidon,
+    /// `RescuePrime::reference(128)`, for roots that are proven again in STARK-based stacks.
+    RescuePrime,
+}
+
+/// Order in which index bits select children.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PathEndianness {
+    /// The most significant bit selects the child of the root, as in `TreePath::from_index`.
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashStrength {
+    Standard,
+    Strengthened,
+}
+
+impl From<Strength> for HashStrength {
+    fn from(strength: Strength) -> Self {
+        match strength {
+            Strength::Standard => HashStrength::Standard,
+            Strength::Strengthened => HashStrength::Strengthened,
+        }
+    }
+}
+
+impl From<HashStrength> for Strength {
+    fn from(strength: HashStrength) -> Self {
+        match strength {
+            HashStrength::Standard => Strength::Standard,
+            HashStrength::Strengthened => Strength::Strengthened,
+        }
+    }
+}
+
+/// Everything that determines the roots of a tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeConfig {
+    pub height: usize,
+    pub hasher: HasherId,
+    pub endianness: PathEndianness,
+    /// Hash of the empty leaf.
+    pub empty_leaf: F1,
+    pub strength: HashStrength,
+}
+
+impl TreeConfig {
+    /// Configuration of `MerkleTree::new(height)` with field-element leaves.
+    pub fn new(height: usize) -> Self {
+        Self {
+            height,
+            hasher: HasherId::Poseidon,
+            endianness: PathEndianness::BigEndian,
+            empty_leaf: F1::zero(),
+            strength: HashStrength::Standard,
+        }
+    }
+
+    /// Configuration of `tree`.
+    pub fn of<V: Leafable<F1>, S: NodeStore<F1>>(tree: &MerkleTree<F1, V, S>) -> Self {
+        Self {
+            height: tree.height(),
+            hasher: tree.hasher_id(),
+            endianness: PathEndianness::BigEndian,
+            empty_leaf: tree.empty_leaf().hash(),
+            strength: tree.poseidon_constants().strength.into(),
+        }
+    }
+
+    pub fn poseidon_constants(&self) -> PoseidonConstants<F1, U2> {
+        PoseidonConstants::new_with_strength(self.strength.into())
+    }
+
+    /// Hashes of the empty subtrees, from the empty leaf at index 0 up to the root of the empty
+    /// tree at index `height`, for external verifiers to check their constants against.
+    ///
+    /// `ladder[k]` does not depend on the height, so the ladders of lower trees are prefixes of higher ones.
+    pub fn zero_hash_ladder(&self) -> Vec<F1> {
+        match self.hasher {
+            HasherId::Poseidon => zero_hashes::zero_hash_ladder(
+                &self.poseidon_constants(),
+                self.empty_leaf,
+                self.height,
+            ),
+            HasherId::RescuePrime => {
+                let mut ladder = vec![self.empty_leaf];
+                extend_ladder(&RescuePrime::reference(128), &mut ladder, self.height);
+                ladder
+            }
+        }
+    }
+}
+
+/// Public parameters bundled with the tree configuration they were produced for.
+///
+/// The bundle also holds a digest of both, so that `read` rejects parameters that were produced
+/// for, or stored with, another configuration.
+#[derive(Serialize, Deserialize)]
+pub struct ParamsBundle {
+    config: TreeConfig,
+    pp: MerklePublicParams,
+    /// SHA3-256 of the encodings of `config` and `pp`.
+    digest: [u8; 32],
+}
+
+impl ParamsBundle {
+    /// Produces public parameters for Merkle process proofs over trees configured by `config`.
+    pub fn setup(config: TreeConfig) -> Self {
+        assert!(
+            config.height <= MAX_HEIGHT,
+            "height {} exceeds the supported maximum {MAX_HEIGHT}",
+            config.height
+        );
+        assert_eq!(
+            config.hasher,
+            HasherId::Poseidon,
+            "Merkle process circuits hash with Poseidon"
+        );
+
+        let circuit_primary = MerkleProcessCircuit {
+            constants: config.poseidon_constants(),
+            siblings: vec![F1::zero(); config.height],
+            index: 0,
+            old_value: F1::zero(),
+            new_value: F1::zero(),
+        };
+        let pp = PublicParams::setup(circuit_primary, TrivialTestCircuit::default());
+        let digest = digest(&config, &pp).expect("public parameters are serializable");
+
+        Self { config, pp, digest }
+    }
+
+    pub fn config(&self) -> &TreeConfig {
+        &self.config
+    }
+
+    pub fn public_params(&self) -> &MerklePublicParams {
+        &self.pp
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), bincode::Error> {
+        bincode::serialize_into(writer, self)
+    }
+
+    /// Reads a bundle written by `write`, or fails if its digest does not match its
+    /// configuration and parameters.
+    pub fn read<R: Read>(reader: R) -> Result<Self, bincode::Error> {
+        let bundle: Self = bincode::deserialize_from(reader)?;
+        if digest(&bundle.config, &bundle.pp)? != bundle.digest {
+            return Err(Box::new(bincode::ErrorKind::Custom(
+                "public parameters do not match the configuration".to_string(),
+            )));
+        }
+
+        Ok(bundle)
+    }
+}
+
+fn digest(config: &TreeConfig, pp: &MerklePublicParams) -> Result<[u8; 32], bincode::Error> {
+    let mut hasher = Sha3_256::new();
+    bincode::serialize_into(&mut hasher, config)?;
+    bincode::serialize_into(&mut hasher, pp)?;
+
+    Ok(hasher.finalize().into())
+}
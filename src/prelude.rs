@@ -0,0 +1,33 @@
+//! Types needed to build trees, prove their updates and ship the proofs, importable at once with
+//! `use nova_snark_example::prelude::*;`.
+//!
+//! Items re-exported here only change in breaking releases. Other public paths of the crate
+//! remain available but may move between minor releases.
+
+pub use crate::{
+    envelope::{DecodeMode, EnvelopeError, Finality, ProofEnvelope},
+    merkle_tree::{
+        append::{AppendOnlyMerkleTree, ConsistencyProof},
+        circuits::{
+            BatchMerkleProcessCircuit, MerkleInclusionCircuit, MerkleNonInclusionCircuit,
+            MerkleProcessCircuit, RangeClearCircuit, SequentialMerkleProcessCircuit,
+        },
+        frontier::FrontierBuilder,
+        kv::{KvMerkleTree, KvProof},
+        multiproof::{MerkleMultiProof, MerkleRangeProof},
+        node_store::NodeStore,
+        path::{CompressedMerklePath, MerklePath, MerkleProcessProof, TreePath},
+        quaternary::{QuaternaryInclusionCircuit, QuaternaryProcessCircuit},
+        schedule::{InOrder, Locality, Scheduler},
+        tree::{
+            compute_merkle_root, verify_merkle_proof, Checkpoint, ConflictPolicy, IndexOverflow,
+            IntegrityError, Leafable, MergeConflict, MerkleTree, SnapshotId,
+        },
+        verify::{verify_batch, verify_insert, verify_update},
+    },
+    params::{HashStrength, HasherId, ParamsBundle, PathEndianness, TreeConfig},
+    proof_system::{ProofSystem, SpartanIpa},
+    prover::{Prover, ProverError, Verifier, F1, F2, G1, G2},
+    public_inputs::ZLayout,
+    rng::{OsRngProvider, RngProvider, SeededRng},
+};
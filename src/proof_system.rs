@@ -0,0 +1,87 @@
+use nova_snark::{errors::NovaError, CompressedSNARK};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    prover::{
+        MerklePublicParams, MerkleRecursiveSNARK, PrimaryCircuit, SecondaryCircuit, F1, F2, G1, G2,
+    },
+    public_inputs::ZLayout,
+    sealed::Sealed,
+};
+
+/// Backend compressing folded Merkle process proofs into succinct proofs.
+///
+/// Sealed, as envelopes name the backend by `ID`.
+pub trait ProofSystem: Sealed {
+    /// Identifier recorded in proof envelopes.
+    const ID: &'static str;
+
+    type ProverKey;
+    type VerifierKey;
+    type Proof: Serialize + DeserializeOwned;
+
+    fn setup(pp: &MerklePublicParams) -> (Self::ProverKey, Self::VerifierKey);
+
+    fn prove_compressed(
+        pp: &MerklePublicParams,
+        pk: &Self::ProverKey,
+        recursive_snark: &MerkleRecursiveSNARK,
+    ) -> Result<Self::Proof, NovaError>;
+
+    /// Verifies `num_steps` steps starting from `initial_root` and returns the final root.
+    fn verify_compressed(
+        pp: &MerklePublicParams,
+        vk: &Self::VerifierKey,
+        proof: &Self::Proof,
+        num_steps: usize,
+        initial_root: F1,
+    ) -> Result<F1, NovaError>;
+}
+
+type EE1 = nova_snark::provider::ipa_pc::EvaluationEngine<G1>;
+type EE2 = nova_snark::provider::ipa_pc::EvaluationEngine<G2>;
+type S1 = nova_snark::spartan::RelaxedR1CSSNARK<G1, EE1>;
+type S2 = nova_snark::spartan::RelaxedR1CSSNARK<G2, EE2>;
+
+/// Spartan with IPA-PC on both curves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpartanIpa;
+
+impl Sealed for SpartanIpa {}
+
+impl ProofSystem for SpartanIpa {
+    const ID: &'static str = "spartan-ipa-pc";
+
+    // Spartan reads everything it needs from the public parameters.
+    type ProverKey = ();
+    type VerifierKey = ();
+    type Proof = CompressedSNARK<G1, G2, PrimaryCircuit, SecondaryCircuit, S1, S2>;
+
+    fn setup(_pp: &MerklePublicParams) -> (Self::ProverKey, Self::VerifierKey) {
+        ((), ())
+    }
+
+    fn prove_compressed(
+        pp: &MerklePublicParams,
+        _pk: &Self::ProverKey,
+        recursive_snark: &MerkleRecursiveSNARK,
+    ) -> Result<Self::Proof, NovaError> {
+        CompressedSNARK::prove(pp, recursive_snark)
+    }
+
+    fn verify_compressed(
+        pp: &MerklePublicParams,
+        _vk: &Self::VerifierKey,
+        proof: &Self::Proof,
+        num_steps: usize,
+        initial_root: F1,
+    ) -> Result<F1, NovaError> {
+        let z0_primary = ZLayout::MERKLE_PROCESS
+            .builder()
+            .set("root", initial_root)
+            .build();
+        let (zn_primary, _) = proof.verify(pp, num_steps, z0_primary, vec![F2::zero()])?;
+
+        Ok(ZLayout::MERKLE_PROCESS.read(&zn_primary, "root"))
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+
+use bellperson::{
+    gadgets::num::AllocatedNum, util_cs::test_cs::TestConstraintSystem, ConstraintSystem,
+    SynthesisError,
+};
+use neptune::Strength;
+use nova_snark::{
+    errors::NovaError,
+    traits::{
+        circuit::{StepCircuit, TrivialTestCircuit},
+        Group,
+    },
+    PublicParams, RecursiveSNARK,
+};
+
+use crate::{
+    envelope::{EnvelopeError, ProofEnvelope},
+    merkle_tree::{
+        circuits::MerkleProcessCircuit,
+        node_store::NodeStore,
+        tree::{index_fits, Leafable, MerkleTree},
+    },
+    params::{HasherId, ParamsBundle, TreeConfig},
+    proof_system::ProofSystem,
+    public_inputs::ZLayout,
+};
+
+pub type G1 = pasta_curves::pallas::Point;
+pub type G2 = pasta_curves::vesta::Point;
+pub type F1 = <G1 as Group>::Scalar;
+pub type F2 = <G2 as Group>::Scalar;
+
+pub type PrimaryCircuit = MerkleProcessCircuit<F1>;
+pub type SecondaryCircuit = TrivialTestCircuit<F2>;
+
+pub type MerklePublicParams = PublicParams<G1, G2, PrimaryCircuit, SecondaryCircuit>;
+pub type MerkleRecursiveSNARK = RecursiveSNARK<G1, G2, PrimaryCircuit, SecondaryCircuit>;
+
+#[derive(Debug)]
+pub enum ProverError {
+    /// The witness of a step violates a constraint, named by its full namespace path.
+    Unsatisfied { step: usize, constraint: String },
+    /// A step could not be synthesized.
+    Synthesis { step: usize, error: SynthesisError },
+    /// Nova failed to fold a step.
+    Nova { step: usize, error: NovaError },
+    /// A step was built for a different tree height or hash strength than the parameters, or
+    /// the parameters are for a hasher the steps do not use.
+    ConfigMismatch { step: usize },
+    /// The tree differs from the configuration of the parameters, see `Prover::check_tree`.
+    TreeMismatch { found: TreeConfig },
+    /// No step was given.
+    NoSteps,
+}
+
+impl fmt::Display for ProverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProverError::Unsatisfied { constraint, .. } => {
+                write!(f, "unsatisfied constraint `{constraint}`")
+            }
+            ProverError::Synthesis { step, error } => {
+                write!(f, "synthesis failed at step {step}: {error}")
+            }
+            ProverError::Nova { step, error } => {
+                write!(f, "folding failed at step {step}: {error:?}")
+            }
+            ProverError::ConfigMismatch { step } => {
+                write!(f, "step {step} does not match the tree configuration")
+            }
+            ProverError::TreeMismatch { found } => {
+                write!(f, "tree does not match the configuration: found {found:?}")
+            }
+            ProverError::NoSteps => write!(f, "nothing to prove"),
+        }
+    }
+}
+
+impl std::error::Error for ProverError {}
+
+/// Largest tree height accepted by the prover.
+///
+/// wasm32 has no threads and a 4GB address space, so only small parameter sets are proved there.
+#[cfg(target_arch = "wasm32")]
+pub const MAX_HEIGHT: usize = 8;
+#[cfg(not(target_arch = "wasm32"))]
+pub const MAX_HEIGHT: usize = usize::BITS as usize;
+
+/// Folds Merkle process steps with the public parameters of a [`ParamsBundle`].
+pub struct Prover<'a> {
+    bundle: &'a ParamsBundle,
+}
+
+impl<'a> Prover<'a> {
+    pub fn new(bundle: &'a ParamsBundle) -> Self {
+        Self { bundle }
+    }
+
+    /// Folds one step per circuit, starting from `initial_root`.
+    ///
+    /// Each step is checked against the root produced by the previous one before folding, as is
+    /// its index against the height, so an invalid witness is reported as the constraint it
+    /// violates instead of a generic error.
+    pub fn prove(
+        &self,
+        circuits: &[PrimaryCircuit],
+        initial_root: F1,
+    ) -> Result<MerkleRecursiveSNARK, ProverError> {
+        if circuits.is_empty() {
+            return Err(ProverError::NoSteps);
+        }
+
+        let config = self.bundle.config();
+        let strength = Strength::from(config.strength);
+        let z0_primary = ZLayout::MERKLE_PROCESS
+            .builder()
+            .set("root", initial_root)
+            .build();
+        let z0_secondary = vec![F2::zero()];
+        let mut recursive_snark = None;
+        let mut root = initial_root;
+        for (step, circuit_primary) in circuits.iter().enumerate() {
+            if config.hasher != HasherId::Poseidon
+                || circuit_primary.siblings.len() != config.height
+                || circuit_primary.constants.strength != strength
+            {
+                return Err(ProverError::ConfigMismatch { step });
+            }
+            if !index_fits(circuit_primary.index, config.height)
+                || circuit_primary.old_root() != root
+            {
+                return Err(diagnose(step, circuit_primary, root));
+            }
+
+            let res = RecursiveSNARK::prove_step(
+                self.bundle.public_params(),
+                recursive_snark,
+                circuit_primary.clone(),
+                TrivialTestCircuit::default(),
+                z0_primary.clone(),
+                z0_secondary.clone(),
+            )
+            .map_err(|error| ProverError::Nova { step, error })?;
+            recursive_snark = Some(res);
+            root = circuit_primary.output(&[root])[0];
+        }
+
+        Ok(recursive_snark.unwrap())
+    }
+
+    /// Checks every field of the configuration of the parameters against `tree`, including the
+    /// empty leaf and hasher, which the steps built from it do not record.
+    pub fn check_tree<V: Leafable<F1>, S: NodeStore<F1>>(
+        &self,
+        tree: &MerkleTree<F1, V, S>,
+    ) -> Result<(), ProverError> {
+        let found = TreeConfig::of(tree);
+        if found != *self.bundle.config() {
+            return Err(ProverError::TreeMismatch { found });
+        }
+
+        Ok(())
+    }
+
+    pub fn prove_compressed<P: ProofSystem>(
+        &self,
+        pk: &P::ProverKey,
+        recursive_snark: &MerkleRecursiveSNARK,
+    ) -> Result<P::Proof, NovaError> {
+        P::prove_compressed(self.bundle.public_params(), pk, recursive_snark)
+    }
+}
+
+/// Synthesizes a failing step in a test constraint system to find the violated constraint.
+fn diagnose(step: usize, circuit: &PrimaryCircuit, root: F1) -> ProverError {
+    let mut cs = TestConstraintSystem::<F1>::new();
+    let result = {
+        let mut cs = cs.namespace(|| format!("step {step}"));
+        AllocatedNum::alloc(cs.namespace(|| "old root"), || Ok(root))
+            .and_then(|z| circuit.synthesize(&mut cs, &[z]))
+    };
+    if let Err(error) = result {
+        return ProverError::Synthesis { step, error };
+    }
+
+    let constraint = cs
+        .which_is_unsatisfied()
+        .unwrap_or("unknown constraint")
+        .to_string();
+
+    ProverError::Unsatisfied { step, constraint }
+}
+
+/// Checks Merkle process proofs against the public parameters of a [`ParamsBundle`].
+pub struct Verifier<'a> {
+    bundle: &'a ParamsBundle,
+}
+
+impl<'a> Verifier<'a> {
+    pub fn new(bundle: &'a ParamsBundle) -> Self {
+        Self { bundle }
+    }
+
+    /// Verifies `num_steps` folded steps starting from `initial_root` and returns the final root.
+    pub fn verify(
+        &self,
+        recursive_snark: &MerkleRecursiveSNARK,
+        num_steps: usize,
+        initial_root: F1,
+    ) -> Result<F1, NovaError> {
+        let (zn_primary, _) = recursive_snark.verify(
+            self.bundle.public_params(),
+            num_steps,
+            ZLayout::MERKLE_PROCESS
+                .builder()
+                .set("root", initial_root)
+                .build(),
+            vec![F2::zero()],
+        )?;
+
+        Ok(ZLayout::MERKLE_PROCESS.read(&zn_primary, "root"))
+    }
+
+    /// Verifies a sealed compressed proof and returns its final root.
+    pub fn open<P: ProofSystem>(
+        &self,
+        vk: &P::VerifierKey,
+        envelope: &ProofEnvelope,
+    ) -> Result<F1, EnvelopeError> {
+        envelope.open::<P>(self.bundle.public_params(), vk)
+    }
+}
@@ -0,0 +1,83 @@
+use ff::PrimeField;
+
+/// Names of the entries of the step state `z`, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ZLayout {
+    names: &'static [&'static str],
+}
+
+impl ZLayout {
+    /// Layout of `MerkleProcessCircuit`: the root before the first step in `z0`, after the last in `zn`.
+    pub const MERKLE_PROCESS: ZLayout = ZLayout { names: &["root"] };
+
+    /// Layout of `SequentialMerkleProcessCircuit`: the root and the index of the next leaf to
+    /// update.
+    pub const SEQUENTIAL_PROCESS: ZLayout = ZLayout {
+        names: &["root", "next_index"],
+    };
+
+    pub const fn new(names: &'static [&'static str]) -> Self {
+        Self { names }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn names(&self) -> &'static [&'static str] {
+        self.names
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|&n| n == name)
+    }
+
+    pub fn builder<F: PrimeField>(&self) -> PublicInputs<F> {
+        PublicInputs {
+            layout: *self,
+            values: vec![None; self.arity()],
+        }
+    }
+
+    /// Entry `name` of a state laid out by `self`.
+    pub fn read<F: PrimeField>(&self, z: &[F], name: &str) -> F {
+        assert_eq!(z.len(), self.arity(), "state does not match the layout");
+        let position = self
+            .position(name)
+            .unwrap_or_else(|| panic!("unknown public input `{name}`"));
+
+        z[position]
+    }
+}
+
+/// Assembles `z0` or `zn` from named entries so that both sides order them by the same layout.
+#[derive(Clone, Debug)]
+pub struct PublicInputs<F: PrimeField> {
+    layout: ZLayout,
+    values: Vec<Option<F>>,
+}
+
+impl<F: PrimeField> PublicInputs<F> {
+    pub fn set(mut self, name: &str, value: F) -> Self {
+        let position = self
+            .layout
+            .position(name)
+            .unwrap_or_else(|| panic!("unknown public input `{name}`"));
+        assert!(
+            self.values[position].is_none(),
+            "public input `{name}` is set twice"
+        );
+        self.values[position] = Some(value);
+        self
+    }
+
+    pub fn build(self) -> Vec<F> {
+        self.values
+            .into_iter()
+            .zip(self.layout.names())
+            .map(|(value, name)| {
+                value.unwrap_or_else(|| panic!("public input `{name}` is not set"))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,112 @@
+use rand_chacha::{ChaCha20Rng, ChaCha8Rng};
+use rand_core::{CryptoRng, OsRng, RngCore, SeedableRng};
+
+/// Source of the randomness the prover samples, such as sampling seeds and random trees.
+pub trait RngProvider: RngCore {
+    /// Describes the source, recorded next to whatever is sampled from it.
+    fn source(&self) -> String;
+}
+
+/// Randomness of the operating system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsRngProvider;
+
+impl RngCore for OsRngProvider {
+    fn next_u32(&mut self) -> u32 {
+        OsRng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        OsRng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        OsRng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        OsRng.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for OsRngProvider {}
+
+impl RngProvider for OsRngProvider {
+    fn source(&self) -> String {
+        "os".to_string()
+    }
+}
+
+/// ChaCha stream from a seed that anyone can replay: a test seed or the output of a randomness beacon.
+#[derive(Clone, Debug)]
+pub struct SeededRng {
+    rng: Stream,
+    source: String,
+}
+
+#[derive(Clone, Debug)]
+enum Stream {
+    ChaCha8(ChaCha8Rng),
+    ChaCha20(ChaCha20Rng),
+}
+
+impl SeededRng {
+    /// Deterministic source for tests and benchmarks.
+    pub fn from_seed_u64(seed: u64) -> Self {
+        Self {
+            rng: Stream::ChaCha20(ChaCha20Rng::seed_from_u64(seed)),
+            source: format!("chacha20 seed {seed}"),
+        }
+    }
+
+    /// ChaCha8 stream of `seed`, which `MerkleTree::random` has always drawn from.
+    pub fn chacha8_from_seed_u64(seed: u64) -> Self {
+        Self {
+            rng: Stream::ChaCha8(ChaCha8Rng::seed_from_u64(seed)),
+            source: format!("chacha8 seed {seed}"),
+        }
+    }
+
+    /// DRBG seeded with the `randomness` published by a beacon in `round`.
+    pub fn from_beacon(round: u64, randomness: [u8; 32]) -> Self {
+        Self {
+            rng: Stream::ChaCha20(ChaCha20Rng::from_seed(randomness)),
+            source: format!("chacha20 beacon round {round}"),
+        }
+    }
+}
+
+impl SeededRng {
+    fn rng(&mut self) -> &mut dyn RngCore {
+        match &mut self.rng {
+            Stream::ChaCha8(rng) => rng,
+            Stream::ChaCha20(rng) => rng,
+        }
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.rng().try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for SeededRng {}
+
+impl RngProvider for SeededRng {
+    fn source(&self) -> String {
+        self.source.clone()
+    }
+}
@@ -0,0 +1,5 @@
+//! Supertrait of the public traits whose implementations all live in this crate, such as
+//! `ProofSystem` and `WireProof`. Downstream crates cannot name it, so they can use these traits
+//! but not implement them, and the traits can gain items without a breaking release.
+
+pub trait Sealed {}
@@ -0,0 +1,78 @@
+//! Systematically corrupted proofs, to check that a verifier rejects each of them.
+//!
+//! Every mutant of a valid proof is invalid for the same root, except where noted.
+
+use ff::PrimeField;
+
+use crate::merkle_tree::{hasher::MerkleHasher, path::MerklePath};
+
+/// A way to corrupt a `MerklePath`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mutation {
+    /// Adds one to the sibling at this level.
+    FlipSibling(usize),
+    /// Flips the bit of the index choosing the side at this level.
+    FlipIndexBit(usize),
+    /// Adds one to the leaf hash.
+    FlipLeaf,
+    /// Drops the sibling closest to the root.
+    Truncate,
+    /// Appends a sibling above the root.
+    Extend,
+}
+
+impl Mutation {
+    /// Applies the mutation to `path`. Panics if the level is not below the height, or for
+    /// `FlipIndexBit`, beyond the bits of a `usize` index.
+    pub fn apply<F: PrimeField>(&self, path: &MerklePath<F>) -> MerklePath<F> {
+        let mut mutant = path.clone();
+        match *self {
+            Mutation::FlipSibling(level) => mutant.siblings[level] += F::one(),
+            Mutation::FlipIndexBit(level) => {
+                assert!(level < path.height(), "level {level} out of range");
+                mutant.index ^= 1usize
+                    .checked_shl(level as u32)
+                    .expect("index bit out of range");
+            }
+            Mutation::FlipLeaf => mutant.leaf_hash += F::one(),
+            Mutation::Truncate => {
+                mutant.siblings.pop();
+            }
+            Mutation::Extend => mutant.siblings.push(F::zero()),
+        }
+
+        mutant
+    }
+}
+
+/// Every mutation of `path`, with the mutant it produces, where `hasher` hashes the nodes of
+/// the tree of the path.
+///
+/// Flipping an index bit swaps the node on the path with its sibling, which leaves the root
+/// unchanged if they are equal, as for two empty subtrees; such levels are skipped, as are the
+/// levels of paths taller than `usize::BITS` that have no bit in the index.
+pub fn mutants<F: PrimeField, H: MerkleHasher<F>>(
+    hasher: &H,
+    path: &MerklePath<F>,
+) -> Vec<(Mutation, MerklePath<F>)> {
+    let mut mutations = vec![Mutation::FlipLeaf, Mutation::Truncate, Mutation::Extend];
+    mutations.extend((0..path.height()).map(Mutation::FlipSibling));
+
+    let mut node = path.leaf_hash;
+    for (level, (lr_bit, &sibling)) in path.lr_bits().into_iter().zip(&path.siblings).enumerate() {
+        if node != sibling && level < usize::BITS as usize {
+            mutations.push(Mutation::FlipIndexBit(level));
+        }
+        node = if lr_bit {
+            hasher.hash_pair(sibling, node)
+        } else {
+            hasher.hash_pair(node, sibling)
+        };
+    }
+
+    mutations
+        .into_iter()
+        .filter(|mutation| *mutation != Mutation::Truncate || path.height() > 0)
+        .map(|mutation| (mutation, mutation.apply(path)))
+        .collect()
+}
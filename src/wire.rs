@@ -0,0 +1,151 @@
+use ff::PrimeField;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    encoding::EncodingError,
+    merkle_tree::{
+        append::ConsistencyProof,
+        kv::KvProof,
+        multiproof::{MerkleMultiProof, MerkleRangeProof},
+        path::{CompressedMerklePath, MerklePath, MerkleProcessProof},
+        quaternary::QuaternaryPath,
+    },
+    params::{HashStrength, HasherId},
+    sealed::Sealed,
+};
+
+/// First bytes of every encoded proof.
+pub const WIRE_MAGIC: [u8; 4] = *b"NSMP";
+/// Version of the layout after the magic, increased on every incompatible change.
+pub const WIRE_VERSION: u8 = 1;
+/// Magic, version, hasher, strength, arity and big-endian `u16` height.
+pub(crate) const HEADER_LEN: usize = 10;
+
+/// Proof that can be sent in the wire format, for a tree of `height()` levels.
+///
+/// Sealed, as the format only covers the proofs of this crate.
+pub trait WireProof: Serialize + DeserializeOwned + Sealed {
+    /// Children per internal node of the tree.
+    const ARITY: u8 = 2;
+
+    fn height(&self) -> usize;
+}
+
+// Each proof names the field holding its height, or whose length is its height.
+macro_rules! impl_wire_proof {
+    ($($proof:ident => $field:ident $(.$method:ident())?),* $(,)?) => {
+        $(
+            impl<F: PrimeField> Sealed for $proof<F> {}
+
+            impl<F: PrimeField + Serialize + DeserializeOwned> WireProof for $proof<F> {
+                fn height(&self) -> usize {
+                    self.$field$(.$method())?
+                }
+            }
+        )*
+    };
+}
+
+impl_wire_proof!(
+    MerklePath => siblings.len(),
+    CompressedMerklePath => height,
+    MerkleProcessProof => siblings.len(),
+    MerkleMultiProof => height,
+    MerkleRangeProof => height,
+    ConsistencyProof => siblings.len(),
+    KvProof => siblings.len(),
+);
+
+impl<F: PrimeField> Sealed for QuaternaryPath<F> {}
+
+impl<F: PrimeField + Serialize + DeserializeOwned> WireProof for QuaternaryPath<F> {
+    const ARITY: u8 = 4;
+
+    fn height(&self) -> usize {
+        self.siblings.len()
+    }
+}
+
+/// Tree parameters recorded in the header of an encoded proof.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WireHeader {
+    pub hasher: HasherId,
+    pub strength: HashStrength,
+    pub arity: u8,
+    pub height: usize,
+}
+
+/// Encodes `proof` with a header naming the hash function, arity and height of its tree.
+///
+/// The body is the bincode encoding of the proof, which the header pins to this version.
+pub fn encode_wire<P: WireProof>(
+    proof: &P,
+    hasher: HasherId,
+    strength: HashStrength,
+) -> Result<Vec<u8>, EncodingError> {
+    let height = u16::try_from(proof.height()).map_err(|_| EncodingError::InvalidHeader)?;
+
+    let mut bytes = WIRE_MAGIC.to_vec();
+    bytes.push(WIRE_VERSION);
+    bytes.push(match hasher {
+        HasherId::Poseidon => 0,
+        HasherId::RescuePrime => 1,
+    });
+    bytes.push(match strength {
+        HashStrength::Standard => 0,
+        HashStrength::Strengthened => 1,
+    });
+    bytes.push(P::ARITY);
+    bytes.extend_from_slice(&height.to_be_bytes());
+    bincode::serialize_into(&mut bytes, proof).map_err(EncodingError::Encoding)?;
+
+    Ok(bytes)
+}
+
+/// Inverse of `encode_wire`, rejecting unknown versions and parameters, an arity or height that
+/// differs from the proof, and any bytes after the proof.
+pub fn decode_wire<P: WireProof>(bytes: &[u8]) -> Result<(WireHeader, P), EncodingError> {
+    if bytes.len() < HEADER_LEN || bytes[..4] != WIRE_MAGIC {
+        return Err(EncodingError::InvalidHeader);
+    }
+    if bytes[4] != WIRE_VERSION {
+        return Err(EncodingError::UnsupportedVersion { found: bytes[4] });
+    }
+    let hasher = match bytes[5] {
+        0 => HasherId::Poseidon,
+        1 => HasherId::RescuePrime,
+        _ => return Err(EncodingError::InvalidHeader),
+    };
+    let strength = match bytes[6] {
+        0 => HashStrength::Standard,
+        1 => HashStrength::Strengthened,
+        _ => return Err(EncodingError::InvalidHeader),
+    };
+    let arity = bytes[7];
+    if arity != P::ARITY {
+        return Err(EncodingError::InvalidHeader);
+    }
+    let height = u16::from_be_bytes([bytes[8], bytes[9]]) as usize;
+
+    let body = &bytes[HEADER_LEN..];
+    let proof: P = bincode::deserialize(body).map_err(EncodingError::Encoding)?;
+    let size = bincode::serialized_size(&proof).map_err(EncodingError::Encoding)? as usize;
+    if size < body.len() {
+        return Err(EncodingError::TrailingBytes {
+            count: body.len() - size,
+        });
+    }
+    if proof.height() != height {
+        return Err(EncodingError::InvalidHeader);
+    }
+
+    Ok((
+        WireHeader {
+            hasher,
+            strength,
+            arity,
+            height,
+        },
+        proof,
+    ))
+}
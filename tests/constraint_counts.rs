@@ -0,0 +1,48 @@
+use nova_snark_example::prelude::{HashStrength, ParamsBundle, TreeConfig};
+
+/// Size of one folded step, including the verifier circuits added by Nova.
+#[derive(Debug, PartialEq, Eq)]
+struct StepShape {
+    primary_constraints: usize,
+    primary_variables: usize,
+    secondary_constraints: usize,
+    secondary_variables: usize,
+}
+
+/// Pinned shapes of the shipped configurations as `(height, strength, shape)`.
+///
+/// Any change to these numbers changes proving cost and invalidates published parameters,
+/// so it has to be made deliberately together with the circuit change that causes it.
+const PINNED_SHAPES: &[(usize, HashStrength, StepShape)] = &[(
+    16,
+    HashStrength::Standard,
+    StepShape {
+        primary_constraints: 19849,
+        primary_variables: 19858,
+        secondary_constraints: 10347,
+        secondary_variables: 10329,
+    },
+)];
+
+#[test]
+fn step_shapes_match_the_pinned_counts() {
+    for (height, strength, expected) in PINNED_SHAPES {
+        let config = TreeConfig {
+            strength: *strength,
+            ..TreeConfig::new(*height)
+        };
+        let bundle = ParamsBundle::setup(config);
+        let (primary_constraints, secondary_constraints) = bundle.public_params().num_constraints();
+        let (primary_variables, secondary_variables) = bundle.public_params().num_variables();
+        let found = StepShape {
+            primary_constraints,
+            primary_variables,
+            secondary_constraints,
+            secondary_variables,
+        };
+        assert_eq!(
+            &found, expected,
+            "step shape of height {height} with {strength:?} strength"
+        );
+    }
+}
@@ -0,0 +1,37 @@
+use nova_snark_example::{
+    encoding::field_from_hex,
+    merkle_tree::hasher::{MerkleHasher, RescuePrime},
+    prover::F1,
+};
+
+/// Known-answer vectors of `RescuePrime::reference(128)` over `F1` as `(left, right, hash)`,
+/// in the format of `encoding::field_to_hex`.
+///
+/// Computed with the parameter generation and sponge of the Rescue-Prime reference
+/// implementation, independently of this crate.
+const RESCUE_PRIME_VECTORS: &[(&str, &str, &str)] = &[
+    (
+        "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "0x3483be1f3e89bc26bea5eeb64acf91434b4dfe343de196e47137eb5ef67efa5f",
+    ),
+    (
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+        "0x0000000000000000000000000000000000000000000000000000000000000002",
+        "0x39111022dd43d1fc7d4a50bbc6c0de7bc5100139ff9c500555bfe5792d463517",
+    ),
+    (
+        "0x40000000000000000000000000000000224698fc0994a8dd8c46eb2100000000",
+        "0x0000000000000000000000000000000000000000000000000000000000000007",
+        "0x16b7d777e64dcaa33cde1b2c4a970db99d4631e6d5c9d51bfb1ac1f178c24e39",
+    ),
+];
+
+#[test]
+fn reference_parameters_reproduce_the_known_answers() {
+    let hasher = RescuePrime::<F1>::reference(128);
+    for &(left, right, hash) in RESCUE_PRIME_VECTORS {
+        let [left, right, hash] = [left, right, hash].map(|hex| field_from_hex::<F1>(hex).unwrap());
+        assert_eq!(hasher.hash_pair(left, right), hash);
+    }
+}
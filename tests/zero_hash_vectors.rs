@@ -0,0 +1,150 @@
+use nova_snark_example::{
+    encoding::field_from_hex,
+    prelude::{HashStrength, HasherId, TreeConfig},
+    prover::F1,
+};
+
+/// Roots of the empty trees of heights 8 to 256 over a zero empty leaf,
+/// as `(hasher, strength, height, root)` in the format of `encoding::field_to_hex`.
+///
+/// Rescue-Prime does not depend on the strength, so only the standard one is listed.
+const ZERO_HASH_ROOTS: &[(HasherId, HashStrength, usize, &str)] = &[
+    (
+        HasherId::Poseidon,
+        HashStrength::Standard,
+        8,
+        "0x0730eaa119869b1758f37649639b495cd8a212231bf35760026c2d7c9eff1097",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Standard,
+        16,
+        "0x24a82ae38c9c3744c91686df5102064a4fc0bf039cc33b6d33b95fe863286f76",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Standard,
+        32,
+        "0x0a677235c4fdf3d607425e6e89588e67fa5d833b2bf5fd7a9ec5fcc189724e24",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Standard,
+        64,
+        "0x3b80a79fece57c8be0c8ccc867e0ba9cbac8c98e7a7785ed5446e9cd1b90e5ff",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Standard,
+        128,
+        "0x06937f808e91de39d2887bc20efa1923e0257a0ef3c16c50a73d70b67b2559e2",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Standard,
+        256,
+        "0x3e77873ea20b5dc86c28ffb08033037b407b7f14d9f96538689d58a3e22c968a",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Strengthened,
+        8,
+        "0x06a5730ccbeba770faabe78d04618aec21c740d25ef8535d12033a0675b477ce",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Strengthened,
+        16,
+        "0x0807c4d84cca81852b437492704ddf13e0ac8f8eeb30c39f48bbe798ebd2f39f",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Strengthened,
+        32,
+        "0x3469f0d706e89c26b5f482e391046b023ea46802ea4d937d9f430527e7aa287f",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Strengthened,
+        64,
+        "0x1eeb50b81240e93d880930333df98877c9496ab345278cdec265698a53c487c5",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Strengthened,
+        128,
+        "0x28ada13616ae0fc48eb475600362dbdf96eb4a25ae5572e584cc381ff404d751",
+    ),
+    (
+        HasherId::Poseidon,
+        HashStrength::Strengthened,
+        256,
+        "0x27b0cc63c2f7106076c2051b9527f1c9a3624cd8e4667f45ab7d502e02ea6606",
+    ),
+    (
+        HasherId::RescuePrime,
+        HashStrength::Standard,
+        8,
+        "0x0fb797c373acf696fde1787e157186256689c0a55dead13370d9fad7ea922d87",
+    ),
+    (
+        HasherId::RescuePrime,
+        HashStrength::Standard,
+        16,
+        "0x09cdc7752b5ba9a7768b89e4f49a80aee2fc2dfffd50eaf88588e96d31ae1c4f",
+    ),
+    (
+        HasherId::RescuePrime,
+        HashStrength::Standard,
+        32,
+        "0x18b838fdac0ceb940bedc4c3644b3c6086701987fde40b2d2c59b197f289c9b4",
+    ),
+    (
+        HasherId::RescuePrime,
+        HashStrength::Standard,
+        64,
+        "0x328b0e9998798e0570207b97f0ecd973089f92a1fac755768a2e79a005ae60e2",
+    ),
+    (
+        HasherId::RescuePrime,
+        HashStrength::Standard,
+        128,
+        "0x28f233ecbca4dab414690179feef01893bf6da3de4bc82cc68104cafc8edeef2",
+    ),
+    (
+        HasherId::RescuePrime,
+        HashStrength::Standard,
+        256,
+        "0x270e701072f661633f73e70bbb3f085092fefa10752b077a2cb660a8c44a1829",
+    ),
+];
+
+#[test]
+fn zero_hash_ladders_end_at_the_pinned_roots() {
+    for &(hasher, strength, height, root) in ZERO_HASH_ROOTS {
+        let config = TreeConfig {
+            hasher,
+            strength,
+            ..TreeConfig::new(height)
+        };
+        assert_eq!(
+            config.zero_hash_ladder()[height],
+            field_from_hex::<F1>(root).unwrap(),
+            "root of height {height} with {hasher:?} and {strength:?} strength"
+        );
+    }
+}
+
+#[test]
+fn lower_ladders_are_prefixes_of_higher_ones() {
+    for hasher in [HasherId::Poseidon, HasherId::RescuePrime] {
+        let config = |height| TreeConfig {
+            hasher,
+            ..TreeConfig::new(height)
+        };
+        let ladder = config(256).zero_hash_ladder();
+        for height in [8, 16, 32, 64, 128] {
+            assert_eq!(config(height).zero_hash_ladder(), ladder[..=height]);
+        }
+    }
+}